@@ -2,7 +2,7 @@ extern crate bluetooth_serial_port;
 extern crate mio;
 use bluetooth_serial_port::{BtProtocol, BtSocket};
 use std::io::{Read, Write};
-use mio::{Poll, PollOpt, Token, Ready};
+use mio::{Interest, Poll, Token};
 
 fn main() {
     // scan for devices
@@ -11,7 +11,7 @@ fn main() {
 
     // "device.addr" is the MAC address of the device
     let device = &devices[0];
-    println!("Connecting to `{}` ({})", device.name, device.addr.to_string());
+    println!("Connecting to `{}` ({})", device.name.as_ref().map_or("[unknown]", |name| name.as_str()), device.addr.to_string());
 
     // create and connect the RFCOMM socket
     let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
@@ -23,8 +23,8 @@ fn main() {
     let num_bytes_written = socket.write(&buffer[0..num_bytes_read]).unwrap();
     println!("Read `{}` bytes, wrote `{}` bytes", num_bytes_read, num_bytes_written);
 
-    // BtSocket also implements `mio::Evented` for async IO
+    // BtSocket also implements `mio::event::Source` for async IO
     let poll = Poll::new().unwrap();
-    poll.register(&socket, Token(0), Ready::readable() | Ready::writable(), PollOpt::edge() | PollOpt::oneshot()).unwrap();
+    poll.registry().register(&mut socket, Token(0), Interest::READABLE | Interest::WRITABLE).unwrap();
     // loop { ... poll events and wait for socket to be readable/writable ... }
 }
@@ -0,0 +1,762 @@
+//! The operations of a Bluetooth stack, which the public types delegate to.
+//!
+//! Every thread uses the Linux stack, unless a test installs another implementation for it, like
+//! the `MockStack` of the `mock` feature. Sockets and processes keep using the stack which created
+//! them.
+
+use bluetooth::{Advance, BtAdapter, BtAddr, BtAsync, BtDevice, BtDiscoveryEvent, BtError, BtProtocol, BtServiceRecord,
+                BtSocketAddr, BtUuid, DeviceClass, InquiryMode, LinkMode, ScanOptions, SecurityLevel};
+use hci_packet::{HciCommand, HciEvent, HciFilter};
+use linux::LinuxStack;
+use mio;
+use mio::{Interest, Registry};
+use mio::unix::SourceFd;
+#[cfg(feature = "mock")]
+use std::cell::RefCell;
+use std::borrow::BorrowMut;
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// A Bluetooth stack, which opens sockets, starts asynchronous processes and controls adapters.
+pub(crate) trait Stack: Debug {
+    fn socket(&self, protocol: BtProtocol) -> Result<Box<dyn Socket>, BtError>;
+
+    /// Takes ownership of the socket `fd`, which needs to be open.
+    unsafe fn socket_from_raw_fd(&self, fd: RawFd) -> Box<dyn Socket>;
+
+    fn listener(&self, protocol: BtProtocol) -> Result<Box<dyn Listener>, BtError>;
+
+    fn browse_services(&self, local_addr: BtAddr, addr: BtAddr) -> Box<dyn ServiceBrowse>;
+
+    fn register_service(&self, record: &BtServiceRecord) -> Result<Box<dyn ServiceRegistration>, BtError>;
+
+    /// Requests the name of `addr`, paging it with the page scan repetition mode and clock offset
+    /// in `inquiry_info` if an inquiry found it.
+    fn read_remote_name(&self, adapter: Option<u16>, addr: BtAddr, inquiry_info: Option<(u8, Option<u16>)>)
+                        -> Box<dyn ReadRemoteName>;
+
+    fn discover_devices(&self, options: &ScanOptions) -> Result<Box<dyn Discovery>, BtError>;
+
+    fn scan_devices(&self, options: &ScanOptions) -> Result<Vec<BtDevice>, BtError>;
+
+    fn open_hci_socket(&self, adapter: Option<u16>) -> Result<Box<dyn Hci>, BtError>;
+
+    fn list_adapters(&self) -> Result<Vec<BtAdapter>, BtError>;
+
+    fn default_adapter(&self) -> Result<BtAdapter, BtError>;
+
+    fn read_local_name(&self, adapter: u16) -> Result<String, BtError>;
+
+    fn set_adapter_up(&self, adapter: u16, up: bool) -> Result<(), BtError>;
+
+    fn write_scan_enable(&self, adapter: u16, discoverable: bool, connectable: bool) -> Result<(), BtError>;
+
+    fn write_local_name(&self, adapter: u16, name: &str) -> Result<(), BtError>;
+
+    fn write_class_of_device(&self, adapter: u16, class: DeviceClass) -> Result<(), BtError>;
+
+    fn write_inquiry_mode(&self, adapter: Option<u16>, mode: InquiryMode) -> Result<(), BtError>;
+}
+
+/// An RFCOMM or L2CAP socket.
+pub(crate) trait Socket: Debug + Send + Sync {
+    fn bind(&mut self, local_addr: BtAddr) -> Result<(), BtError>;
+
+    /// Starts connecting to the RFCOMM channel of `service`, which is looked up through SDP.
+    ///
+    /// The returned process must not outlive the socket.
+    fn connect(&mut self, addr: BtAddr, service: BtUuid) -> Box<dyn SocketConnect>;
+
+    fn connect_channel(&mut self, addr: BtAddr, channel: u8) -> Box<dyn SocketConnect>;
+
+    fn connect_psm(&mut self, addr: BtAddr, psm: u16) -> Box<dyn SocketConnect>;
+
+    fn mtu(&self) -> Result<(u16, u16), BtError>;
+
+    fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError>;
+
+    fn peer_addr(&self) -> Result<BtSocketAddr, BtError>;
+
+    fn local_addr(&self) -> Result<BtSocketAddr, BtError>;
+
+    fn security(&self) -> Result<SecurityLevel, BtError>;
+
+    fn set_security(&self, level: SecurityLevel) -> Result<(), BtError>;
+
+    fn link_mode(&self) -> Result<LinkMode, BtError>;
+
+    fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError>;
+
+    fn send_buffer_size(&self) -> Result<usize, BtError>;
+
+    fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError>;
+
+    fn recv_buffer_size(&self) -> Result<usize, BtError>;
+
+    fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError>;
+
+    fn linger(&self) -> Result<Option<Duration>, BtError>;
+
+    fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError>;
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), BtError>;
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize>;
+
+    fn flush(&self) -> io::Result<()>;
+
+    fn as_raw_fd(&self) -> RawFd;
+
+    fn into_raw_fd(self: Box<Self>) -> RawFd;
+}
+
+/// A connection accepted by a `Listener` together with the address of the remote device.
+pub(crate) type Accepted = (Box<dyn Socket>, BtAddr);
+
+/// A socket accepting RFCOMM or L2CAP connections.
+pub(crate) trait Listener: Debug + Send + Sync {
+    fn bind(&self, addr: BtAddr, port: u16) -> Result<(), BtError>;
+
+    fn mtu(&self) -> Result<(u16, u16), BtError>;
+
+    fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError>;
+
+    fn local_addr(&self) -> Result<BtSocketAddr, BtError>;
+
+    fn defer_setup(&self) -> Result<bool, BtError>;
+
+    fn set_defer_setup(&self, defer: bool) -> Result<(), BtError>;
+
+    fn security(&self) -> Result<SecurityLevel, BtError>;
+
+    fn set_security(&self, level: SecurityLevel) -> Result<(), BtError>;
+
+    fn link_mode(&self) -> Result<LinkMode, BtError>;
+
+    fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError>;
+
+    fn send_buffer_size(&self) -> Result<usize, BtError>;
+
+    fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError>;
+
+    fn recv_buffer_size(&self) -> Result<usize, BtError>;
+
+    fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError>;
+
+    fn linger(&self) -> Result<Option<Duration>, BtError>;
+
+    fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError>;
+
+    fn listen(&self, backlog: i32) -> Result<(), BtError>;
+
+    /// Returns `None` if no connection is pending.
+    fn accept(&self) -> Result<Option<Accepted>, BtError>;
+
+    /// Returns the file descriptor which becomes readable once a connection is pending.
+    fn as_raw_fd(&self) -> RawFd;
+}
+
+/// Connects a socket, aborting the connection attempt when dropped unfinished.
+pub(crate) trait SocketConnect: Advance + Debug + Send + Sync {}
+
+/// Lists the SDP service records of a remote device.
+pub(crate) trait ServiceBrowse: Advance + Debug + Send + Sync {
+    fn take_records(&mut self) -> Option<Vec<BtServiceRecord>>;
+}
+
+/// A service record registered with the local SDP server until dropped.
+pub(crate) trait ServiceRegistration: Debug + Send + Sync {
+    fn handle(&self) -> u32;
+}
+
+/// Requests the name of a remote device, cancelling the request when dropped unfinished.
+pub(crate) trait ReadRemoteName: Advance + Debug + Send + Sync {
+    fn take_name(&mut self) -> Option<String>;
+}
+
+/// A running inquiry, cancelled when dropped unfinished.
+pub(crate) trait Discovery: Debug + Send + Sync {
+    fn next_event(&mut self) -> Result<Option<BtDiscoveryEvent>, BtError>;
+
+    fn cancel(&mut self) -> Result<(), BtError>;
+
+    fn is_done(&self) -> bool;
+
+    /// Returns the file descriptor which becomes readable once events are available.
+    fn as_raw_fd(&self) -> RawFd;
+}
+
+/// A raw HCI socket.
+pub(crate) trait Hci: Debug + Send + Sync {
+    fn set_filter(&self, filter: &HciFilter) -> Result<(), BtError>;
+
+    fn send(&self, command: &HciCommand) -> Result<(), BtError>;
+
+    fn read_event(&self) -> Result<Option<HciEvent>, BtError>;
+
+    fn as_raw_fd(&self) -> RawFd;
+}
+
+#[cfg(feature = "mock")]
+thread_local!(static INSTALLED_STACK: RefCell<Option<Rc<dyn Stack>>> = RefCell::new(None));
+
+/// Returns the stack of the current thread.
+fn current() -> Rc<dyn Stack> {
+    #[cfg(feature = "mock")]
+    {
+        if let Some(stack) = INSTALLED_STACK.with(|installed| installed.borrow().clone()) {
+            return stack;
+        }
+    }
+    Rc::new(LinuxStack)
+}
+
+/// Makes `stack` the stack of the current thread, `None` restores the Linux stack.
+#[cfg(feature = "mock")]
+pub(crate) fn install(stack: Option<Rc<dyn Stack>>) {
+    INSTALLED_STACK.with(|installed| *installed.borrow_mut() = stack);
+}
+
+#[derive(Debug)]
+pub struct BtSocket(Box<dyn Socket>);
+
+impl BtSocket {
+    pub fn new(protocol: BtProtocol) -> Result<BtSocket, BtError> {
+        Ok(BtSocket(current().socket(protocol)?))
+    }
+
+    pub fn bind(&mut self, local_addr: BtAddr) -> Result<(), BtError> {
+        self.0.bind(local_addr)
+    }
+
+    pub fn connect(&mut self, addr: BtAddr, service: BtUuid) -> BtSocketConnect<&mut BtSocket> {
+        BtSocketConnect::new(self, addr, service)
+    }
+
+    pub fn connect_channel(&mut self, addr: BtAddr, channel: u8) -> BtSocketConnect<&mut BtSocket> {
+        BtSocketConnect::with_channel(self, addr, channel)
+    }
+
+    pub fn connect_psm(&mut self, addr: BtAddr, psm: u16) -> BtSocketConnect<&mut BtSocket> {
+        BtSocketConnect::with_psm(self, addr, psm)
+    }
+
+    pub fn mtu(&self) -> Result<(u16, u16), BtError> {
+        self.0.mtu()
+    }
+
+    pub fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        self.0.set_mtu(input, output)
+    }
+
+    pub fn peer_addr(&self) -> Result<BtSocketAddr, BtError> {
+        self.0.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        self.0.local_addr()
+    }
+
+    pub fn security(&self) -> Result<SecurityLevel, BtError> {
+        self.0.security()
+    }
+
+    pub fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        self.0.set_security(level)
+    }
+
+    pub fn link_mode(&self) -> Result<LinkMode, BtError> {
+        self.0.link_mode()
+    }
+
+    pub fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        self.0.set_link_mode(mode)
+    }
+
+    pub fn send_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.send_buffer_size()
+    }
+
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_send_buffer_size(size)
+    }
+
+    pub fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.recv_buffer_size()
+    }
+
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_recv_buffer_size(size)
+    }
+
+    pub fn linger(&self) -> Result<Option<Duration>, BtError> {
+        self.0.linger()
+    }
+
+    pub fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        self.0.set_linger(timeout)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), BtError> {
+        self.0.set_nonblocking(nonblocking)
+    }
+}
+
+impl AsRawFd for BtSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for BtSocket {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl FromRawFd for BtSocket {
+    unsafe fn from_raw_fd(fd: RawFd) -> BtSocket {
+        BtSocket(current().socket_from_raw_fd(fd))
+    }
+}
+
+impl mio::event::Source for BtSocket {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).deregister(registry)
+    }
+}
+
+impl Read for BtSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for BtSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Read for &BtSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for &BtSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BtListener(Box<dyn Listener>);
+
+impl BtListener {
+    pub fn new(protocol: BtProtocol) -> Result<BtListener, BtError> {
+        Ok(BtListener(current().listener(protocol)?))
+    }
+
+    pub fn bind(&self, addr: BtAddr, port: u16) -> Result<(), BtError> {
+        self.0.bind(addr, port)
+    }
+
+    pub fn mtu(&self) -> Result<(u16, u16), BtError> {
+        self.0.mtu()
+    }
+
+    pub fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        self.0.set_mtu(input, output)
+    }
+
+    pub fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        self.0.local_addr()
+    }
+
+    pub fn defer_setup(&self) -> Result<bool, BtError> {
+        self.0.defer_setup()
+    }
+
+    pub fn set_defer_setup(&self, defer: bool) -> Result<(), BtError> {
+        self.0.set_defer_setup(defer)
+    }
+
+    pub fn security(&self) -> Result<SecurityLevel, BtError> {
+        self.0.security()
+    }
+
+    pub fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        self.0.set_security(level)
+    }
+
+    pub fn link_mode(&self) -> Result<LinkMode, BtError> {
+        self.0.link_mode()
+    }
+
+    pub fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        self.0.set_link_mode(mode)
+    }
+
+    pub fn send_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.send_buffer_size()
+    }
+
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_send_buffer_size(size)
+    }
+
+    pub fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.recv_buffer_size()
+    }
+
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_recv_buffer_size(size)
+    }
+
+    pub fn linger(&self) -> Result<Option<Duration>, BtError> {
+        self.0.linger()
+    }
+
+    pub fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        self.0.set_linger(timeout)
+    }
+
+    pub fn listen(&self, backlog: i32) -> Result<(), BtError> {
+        self.0.listen(backlog)
+    }
+
+    pub fn accept(&self) -> Result<Option<(BtSocket, BtAddr)>, BtError> {
+        Ok(self.0.accept()?.map(|(socket, addr)| (BtSocket(socket), addr)))
+    }
+}
+
+impl AsRawFd for BtListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl mio::event::Source for BtListener {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).deregister(registry)
+    }
+}
+
+// Registering the listener doesn't modify it, which lets `accept` wait on a shared reference.
+impl mio::event::Source for &BtListener {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).deregister(registry)
+    }
+}
+
+
+/// Connects a socket, which is either borrowed or owned by the connection process.
+#[derive(Debug)]
+pub struct BtSocketConnect<S: BorrowMut<BtSocket>> {
+    // Declared first, so that an aborted connection is shut down before an owned socket is closed
+    process: Box<dyn SocketConnect>,
+    socket: S,
+}
+
+impl<S: BorrowMut<BtSocket>> BtSocketConnect<S> {
+    pub fn new(mut socket: S, addr: BtAddr, service: BtUuid) -> Self {
+        let process = socket.borrow_mut().0.connect(addr, service);
+        BtSocketConnect { process, socket }
+    }
+
+    pub fn with_channel(mut socket: S, addr: BtAddr, channel: u8) -> Self {
+        let process = socket.borrow_mut().0.connect_channel(addr, channel);
+        BtSocketConnect { process, socket }
+    }
+
+    pub fn with_psm(mut socket: S, addr: BtAddr, psm: u16) -> Self {
+        let process = socket.borrow_mut().0.connect_psm(addr, psm);
+        BtSocketConnect { process, socket }
+    }
+
+    /// Returns the socket being connected.
+    pub fn socket(&self) -> &BtSocket {
+        self.socket.borrow()
+    }
+
+    /// Returns the socket, connected once `advance` has reached `BtAsync::Done`.
+    pub fn into_socket(self) -> S {
+        self.socket
+    }
+
+    pub fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.process.advance()
+    }
+
+    /// Returns the file descriptor the last `BtAsync::WaitFor` asked to wait for.
+    pub fn pollfd(&self) -> RawFd {
+        self.process.pollfd()
+    }
+}
+
+impl<S: BorrowMut<BtSocket>> mio::event::Source for BtSocketConnect<S> {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.pollfd()).deregister(registry)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BtServiceBrowse(Box<dyn ServiceBrowse>);
+
+impl BtServiceBrowse {
+    pub fn new(local_addr: BtAddr, addr: BtAddr) -> BtServiceBrowse {
+        BtServiceBrowse(current().browse_services(local_addr, addr))
+    }
+
+    pub fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    pub fn take_records(&mut self) -> Option<Vec<BtServiceRecord>> {
+        self.0.take_records()
+    }
+
+    /// Returns the file descriptor the last `BtAsync::WaitFor` asked to wait for.
+    pub fn pollfd(&self) -> RawFd {
+        self.0.pollfd()
+    }
+}
+
+impl mio::event::Source for BtServiceBrowse {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.pollfd()).deregister(registry)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BtServiceRegistration(Box<dyn ServiceRegistration>);
+
+impl BtServiceRegistration {
+    pub fn register(record: &BtServiceRecord) -> Result<BtServiceRegistration, BtError> {
+        Ok(BtServiceRegistration(current().register_service(record)?))
+    }
+
+    pub fn handle(&self) -> u32 {
+        self.0.handle()
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BtReadRemoteName(Box<dyn ReadRemoteName>);
+
+impl BtReadRemoteName {
+    pub fn new(adapter: Option<u16>, addr: BtAddr) -> BtReadRemoteName {
+        BtReadRemoteName(current().read_remote_name(adapter, addr, None))
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn with_inquiry_info(adapter: Option<u16>, addr: BtAddr, pscan_rep_mode: u8, clock_offset: Option<u16>) -> BtReadRemoteName {
+        BtReadRemoteName(current().read_remote_name(adapter, addr, Some((pscan_rep_mode, clock_offset))))
+    }
+
+    pub fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    pub fn take_name(&mut self) -> Option<String> {
+        self.0.take_name()
+    }
+
+    /// Returns the file descriptor the last `BtAsync::WaitFor` asked to wait for.
+    pub fn pollfd(&self) -> RawFd {
+        self.0.pollfd()
+    }
+}
+
+impl mio::event::Source for BtReadRemoteName {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.pollfd()).deregister(registry)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BtDiscovery(Box<dyn Discovery>);
+
+impl BtDiscovery {
+    pub fn start(options: &ScanOptions) -> Result<BtDiscovery, BtError> {
+        Ok(BtDiscovery(current().discover_devices(options)?))
+    }
+
+    pub fn next_event(&mut self) -> Result<Option<BtDiscoveryEvent>, BtError> {
+        self.0.next_event()
+    }
+
+    pub fn cancel(&mut self) -> Result<(), BtError> {
+        self.0.cancel()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.0.is_done()
+    }
+}
+
+impl AsRawFd for BtDiscovery {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl mio::event::Source for BtDiscovery {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).deregister(registry)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct HciSocket(Box<dyn Hci>);
+
+impl HciSocket {
+    pub fn open(adapter: Option<u16>) -> Result<HciSocket, BtError> {
+        Ok(HciSocket(current().open_hci_socket(adapter)?))
+    }
+
+    pub fn set_filter(&self, filter: &HciFilter) -> Result<(), BtError> {
+        self.0.set_filter(filter)
+    }
+
+    pub fn send(&self, command: &HciCommand) -> Result<(), BtError> {
+        self.0.send(command)
+    }
+
+    pub fn read_event(&self) -> Result<Option<HciEvent>, BtError> {
+        self.0.read_event()
+    }
+}
+
+impl AsRawFd for HciSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl mio::event::Source for HciSocket {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.0.as_raw_fd()).deregister(registry)
+    }
+}
+
+
+pub fn list_adapters() -> Result<Vec<BtAdapter>, BtError> {
+    current().list_adapters()
+}
+
+pub fn default_adapter() -> Result<BtAdapter, BtError> {
+    current().default_adapter()
+}
+
+pub fn read_local_name(adapter: u16) -> Result<String, BtError> {
+    current().read_local_name(adapter)
+}
+
+pub fn set_adapter_up(adapter: u16, up: bool) -> Result<(), BtError> {
+    current().set_adapter_up(adapter, up)
+}
+
+pub fn write_scan_enable(adapter: u16, discoverable: bool, connectable: bool) -> Result<(), BtError> {
+    current().write_scan_enable(adapter, discoverable, connectable)
+}
+
+pub fn write_local_name(adapter: u16, name: &str) -> Result<(), BtError> {
+    current().write_local_name(adapter, name)
+}
+
+pub fn write_class_of_device(adapter: u16, class: DeviceClass) -> Result<(), BtError> {
+    current().write_class_of_device(adapter, class)
+}
+
+pub fn write_inquiry_mode(adapter: Option<u16>, mode: InquiryMode) -> Result<(), BtError> {
+    current().write_inquiry_mode(adapter, mode)
+}
+
+pub fn scan_devices(options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+    current().scan_devices(options)
+}
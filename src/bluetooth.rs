@@ -2,10 +2,19 @@
 use std;
 use std::result::Result;
 use std::io::{Read, Write};
-use std::str;
+use std::str::{self, FromStr};
+use std::time::{Duration, Instant};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use mio;
 
 use platform;
+use sdp_data;
+use sdp_data::SdpValue;
+use eir::EirData;
+use hci_packet::{HciCommand, HciEvent, HciFilter};
+#[cfg(target_os = "linux")]
+use reactor::{BtReactor, BtServiceBrowseFuture, BtSocketConnectFuture};
 
 /// The bluetooth socket.
 ///
@@ -19,45 +28,30 @@ impl BtSocket {
         Ok(From::from(try!(platform::BtSocket::new(protocol))))
     }
 
+    /// Bind the socket to the local adapter `adapter`.
+    ///
+    /// A following connect establishes the connection, including the SDP query, through this
+    /// adapter. Unbound sockets leave the choice of the adapter to the operating system.
+    pub fn bind_adapter(&mut self, adapter: &BtAdapter) -> Result<(), BtError> {
+        self.0.bind(adapter.addr)
+    }
+
     /// Connect to the RFCOMM service on remote device with address `addr`. Channel will be
     /// determined through SDP protocol.
     ///
     /// This function can block for some seconds.
     pub fn connect(&mut self, addr: BtAddr) -> Result<(), BtError> {
-        // Create temporary `mio` event loop
-        let evtloop = mio::Poll::new().unwrap();
-        let token = mio::Token(0);
-        let mut events = mio::Events::with_capacity(2);
-
-        // Request a socket connection
-        let mut connect = self.0.connect(addr);
-
-        loop {
-            match try!(connect.advance()) {
-                BtAsync::WaitFor(evented, interest) => {
-                    let mut event_received = false;
-                    while !event_received {
-                        // Register this, single, event source
-                        evtloop.register(evented, token, interest, mio::PollOpt::oneshot()).unwrap();
-
-                        // Wait for it to transition to the requested state
-                        evtloop.poll(&mut events, None).unwrap();
-
-
-                        for event in events.iter() {
-                            if event.token() == token {
-                                event_received = true;
-                                evtloop.deregister(evented).unwrap();
-                            }
-                        }
-                    }
-                }
+        self.connect_service(addr, BtUuid::serial_port())
+    }
 
-                BtAsync::Done => {
-                    return Ok(());
-                }
-            }
-        }
+    /// Connect to the RFCOMM service on remote device with address `addr`, giving up after
+    /// `timeout`. Channel will be determined through SDP protocol.
+    ///
+    /// The timeout covers both the SDP query and the connection itself. If it expires, an error of
+    /// kind `BtErrorKind::Timeout` is returned and the connection attempt is aborted; create a new
+    /// socket to try again.
+    pub fn connect_timeout(&mut self, addr: BtAddr, timeout: Duration) -> Result<(), BtError> {
+        BtSocketConnect(self.0.connect(addr, BtUuid::serial_port())).wait_timeout(Some(timeout))
     }
 
     /// Connect to the RFCOMM service on remote device with address `addr`. Channel will be
@@ -68,7 +62,262 @@ impl BtSocket {
     /// will become writable however. It is highly recommended to combine this call with the usage
     /// of `mio` (or some higher level event loop) to get proper non-blocking behaviour.
     pub fn connect_async(&mut self, addr: BtAddr) -> BtSocketConnect {
-        BtSocketConnect(self.0.connect(addr))
+        self.connect_service_async(addr, BtUuid::serial_port())
+    }
+
+    /// Connect to the RFCOMM service with the service class `service` on remote device with
+    /// address `addr`. Channel will be determined through SDP protocol.
+    ///
+    /// This function can block for some seconds.
+    pub fn connect_service(&mut self, addr: BtAddr, service: BtUuid) -> Result<(), BtError> {
+        BtSocketConnect(self.0.connect(addr, service)).wait()
+    }
+
+    /// Connect to the RFCOMM service with the service class `service` on remote device with
+    /// address `addr`. Channel will be determined through SDP protocol.
+    ///
+    /// This function will return immediately, see `connect_async` for how to drive the connection
+    /// process.
+    pub fn connect_service_async(&mut self, addr: BtAddr, service: BtUuid) -> BtSocketConnect<'_> {
+        BtSocketConnect(self.0.connect(addr, service))
+    }
+
+    /// Connect to the given RFCOMM `channel` on remote device with address `addr`. No SDP lookup
+    /// is done, which is useful for devices with a fixed channel and without a working SDP server.
+    ///
+    /// This function can block for some seconds.
+    pub fn connect_channel(&mut self, addr: BtAddr, channel: u8) -> Result<(), BtError> {
+        BtSocketConnect(self.0.connect_channel(addr, channel)).wait()
+    }
+
+    /// Connect to the given RFCOMM `channel` on remote device with address `addr`. No SDP lookup
+    /// is done.
+    ///
+    /// This function will return immediately, see `connect_async` for how to drive the connection
+    /// process.
+    pub fn connect_channel_async(&mut self, addr: BtAddr, channel: u8) -> BtSocketConnect<'_> {
+        BtSocketConnect(self.0.connect_channel(addr, channel))
+    }
+
+    /// Connect an L2CAP socket to the protocol/service multiplexer `psm` on remote device with
+    /// address `addr`.
+    ///
+    /// This function can block for some seconds.
+    pub fn connect_psm(&mut self, addr: BtAddr, psm: u16) -> Result<(), BtError> {
+        BtSocketConnect(self.0.connect_psm(addr, psm)).wait()
+    }
+
+    /// Connect an L2CAP socket to the protocol/service multiplexer `psm` on remote device with
+    /// address `addr`.
+    ///
+    /// This function will return immediately, see `connect_async` for how to drive the connection
+    /// process.
+    pub fn connect_psm_async(&mut self, addr: BtAddr, psm: u16) -> BtSocketConnect<'_> {
+        BtSocketConnect(self.0.connect_psm(addr, psm))
+    }
+
+    /// Returns the incoming and outgoing MTU of an L2CAP socket.
+    ///
+    /// Once connected, these are the values negotiated with the remote device.
+    pub fn mtu(&self) -> Result<(u16, u16), BtError> {
+        self.0.mtu()
+    }
+
+    /// Set the largest packet an L2CAP socket accepts. Must be called before connecting.
+    pub fn set_input_mtu(&self, mtu: u16) -> Result<(), BtError> {
+        self.0.set_mtu(Some(mtu), None)
+    }
+
+    /// Set the largest packet an L2CAP socket sends. Must be called before connecting.
+    pub fn set_output_mtu(&self, mtu: u16) -> Result<(), BtError> {
+        self.0.set_mtu(None, Some(mtu))
+    }
+
+    /// Returns the address of the remote device and the channel the socket is connected to.
+    pub fn peer_addr(&self) -> Result<BtSocketAddr, BtError> {
+        self.0.peer_addr()
+    }
+
+    /// Returns the address of the local adapter and the channel the socket is bound to.
+    ///
+    /// The address is `BtAddr::any()` until the socket is bound or connected.
+    pub fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        self.0.local_addr()
+    }
+
+    /// Returns the security level the connection requires.
+    pub fn security(&self) -> Result<SecurityLevel, BtError> {
+        self.0.security()
+    }
+
+    /// Set the security level the connection requires. Must be called before connecting, the
+    /// connection fails if the level cannot be reached.
+    pub fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        self.0.set_security(level)
+    }
+
+    /// Returns the link mode flags of the connection.
+    pub fn link_mode(&self) -> Result<LinkMode, BtError> {
+        self.0.link_mode()
+    }
+
+    /// Set the link mode flags of the connection, the legacy alternative to `set_security`.
+    pub fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        self.0.set_link_mode(mode)
+    }
+
+    /// Returns the size of the send buffer in bytes (`SO_SNDBUF`).
+    pub fn send_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.send_buffer_size()
+    }
+
+    /// Set the size of the send buffer in bytes. The kernel doubles the value for its bookkeeping
+    /// overhead.
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_send_buffer_size(size)
+    }
+
+    /// Returns the size of the receive buffer in bytes (`SO_RCVBUF`).
+    pub fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.recv_buffer_size()
+    }
+
+    /// Set the size of the receive buffer in bytes. The kernel doubles the value for its
+    /// bookkeeping overhead.
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_recv_buffer_size(size)
+    }
+
+    /// Returns how long closing the socket waits for unsent data (`SO_LINGER`), `None` if closing
+    /// returns immediately.
+    pub fn linger(&self) -> Result<Option<Duration>, BtError> {
+        self.0.linger()
+    }
+
+    /// Set how long closing the socket waits for unsent data, in whole seconds. `None` returns
+    /// immediately and sends the data in the background.
+    pub fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        self.0.set_linger(timeout)
+    }
+
+    /// Switch the socket into or out of non-blocking mode. Reads and writes on a non-blocking
+    /// socket fail with `ErrorKind::WouldBlock` instead of waiting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), BtError> {
+        self.0.set_nonblocking(nonblocking)
+    }
+}
+
+/// Creates a `BtSocket` with options applied, ready for connecting.
+///
+/// ```rust,no_run
+/// # use bluetooth_serial_port::{BtProtocol, BtSocketBuilder, SecurityLevel};
+/// let mut socket = BtSocketBuilder::new(BtProtocol::RFCOMM)
+///     .security(SecurityLevel::High)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BtSocketBuilder {
+    protocol: BtProtocol,
+    adapter: Option<BtAddr>,
+    security: Option<SecurityLevel>,
+    link_mode: Option<LinkMode>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    linger: Option<Option<Duration>>,
+    input_mtu: Option<u16>,
+    output_mtu: Option<u16>,
+}
+
+impl BtSocketBuilder {
+    /// Create a builder for a socket of `protocol` without any options set.
+    pub fn new(protocol: BtProtocol) -> BtSocketBuilder {
+        BtSocketBuilder {
+            protocol,
+            adapter: None,
+            security: None,
+            link_mode: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            linger: None,
+            input_mtu: None,
+            output_mtu: None,
+        }
+    }
+
+    /// Bind the socket to the local adapter `adapter`, see `BtSocket::bind_adapter`.
+    pub fn adapter(mut self, adapter: &BtAdapter) -> BtSocketBuilder {
+        self.adapter = Some(adapter.addr);
+        self
+    }
+
+    /// Set the security level, see `BtSocket::set_security`.
+    pub fn security(mut self, level: SecurityLevel) -> BtSocketBuilder {
+        self.security = Some(level);
+        self
+    }
+
+    /// Set the link mode flags, see `BtSocket::set_link_mode`.
+    pub fn link_mode(mut self, mode: LinkMode) -> BtSocketBuilder {
+        self.link_mode = Some(mode);
+        self
+    }
+
+    /// Set the size of the send buffer, see `BtSocket::set_send_buffer_size`.
+    pub fn send_buffer_size(mut self, size: usize) -> BtSocketBuilder {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the receive buffer, see `BtSocket::set_recv_buffer_size`.
+    pub fn recv_buffer_size(mut self, size: usize) -> BtSocketBuilder {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the linger timeout, see `BtSocket::set_linger`.
+    pub fn linger(mut self, timeout: Option<Duration>) -> BtSocketBuilder {
+        self.linger = Some(timeout);
+        self
+    }
+
+    /// Set the largest packet an L2CAP socket accepts, see `BtSocket::set_input_mtu`.
+    pub fn input_mtu(mut self, mtu: u16) -> BtSocketBuilder {
+        self.input_mtu = Some(mtu);
+        self
+    }
+
+    /// Set the largest packet an L2CAP socket sends, see `BtSocket::set_output_mtu`.
+    pub fn output_mtu(mut self, mtu: u16) -> BtSocketBuilder {
+        self.output_mtu = Some(mtu);
+        self
+    }
+
+    /// Create the socket and apply the options.
+    pub fn build(&self) -> Result<BtSocket, BtError> {
+        let mut socket = BtSocket::new(self.protocol)?;
+        if let Some(addr) = self.adapter {
+            socket.0.bind(addr)?;
+        }
+        if let Some(level) = self.security {
+            socket.set_security(level)?;
+        }
+        if let Some(mode) = self.link_mode {
+            socket.set_link_mode(mode)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(timeout) = self.linger {
+            socket.set_linger(timeout)?;
+        }
+        if self.input_mtu.is_some() || self.output_mtu.is_some() {
+            socket.0.set_mtu(self.input_mtu, self.output_mtu)?;
+        }
+        Ok(socket)
     }
 }
 
@@ -78,17 +327,40 @@ impl From<platform::BtSocket> for BtSocket {
     }
 }
 
-impl mio::Evented for BtSocket {
-    fn register(&self, poll: &mio::Poll, token: mio::Token, interest: mio::Ready, opts: mio::PollOpt) -> std::io::Result<()> {
-        self.0.register(poll, token, interest, opts)
+#[cfg(target_os = "linux")]
+impl AsRawFd for BtSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl IntoRawFd for BtSocket {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FromRawFd for BtSocket {
+    /// Takes ownership of a connected or unconnected RFCOMM or L2CAP socket. The protocol is
+    /// determined from the socket.
+    unsafe fn from_raw_fd(fd: RawFd) -> BtSocket {
+        BtSocket(platform::BtSocket::from_raw_fd(fd))
+    }
+}
+
+impl mio::event::Source for BtSocket {
+    fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.register(registry, token, interests)
     }
 
-    fn reregister(&self, poll: &mio::Poll, token: mio::Token, interest: mio::Ready, opts: mio::PollOpt) -> std::io::Result<()> {
-        self.0.reregister(poll, token, interest, opts)
+    fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.reregister(registry, token, interests)
     }
 
-    fn deregister(&self, poll: &mio::Poll) -> std::io::Result<()> {
-        self.0.deregister(poll)
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        self.0.deregister(registry)
     }
 }
 
@@ -108,12 +380,203 @@ impl Write for BtSocket {
     }
 }
 
+impl Read for &BtSocket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (&self.0).read(buf)
+    }
+}
+
+impl Write for &BtSocket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        (&self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&self.0).flush()
+    }
+}
+
+
+/// A bluetooth socket waiting for incoming connections.
+///
+/// Can be used with `mio::Poll`: once the listener becomes readable, `try_accept` will yield the
+/// pending connection.
+#[derive(Debug)]
+pub struct BtListener(platform::BtListener);
+
+impl BtListener {
+    /// Create an (still) unbound listening socket.
+    pub fn new(protocol: BtProtocol) -> Result<BtListener, BtError> {
+        Ok(BtListener(platform::BtListener::new(protocol)?))
+    }
+
+    /// Bind the socket to the local adapter with address `addr` and the RFCOMM channel `channel`.
+    ///
+    /// Use `BtAddr::any()` to accept connections on all local adapters.
+    pub fn bind(&self, addr: BtAddr, channel: u8) -> Result<(), BtError> {
+        self.0.bind(addr, channel as u16)
+    }
+
+    /// Bind an L2CAP socket to the local adapter with address `addr` and the protocol/service
+    /// multiplexer `psm`.
+    ///
+    /// Dynamically assigned PSMs are odd numbers starting at 0x1001.
+    pub fn bind_psm(&self, addr: BtAddr, psm: u16) -> Result<(), BtError> {
+        self.0.bind(addr, psm)
+    }
+
+    /// Returns the incoming and outgoing MTU of an L2CAP socket.
+    pub fn mtu(&self) -> Result<(u16, u16), BtError> {
+        self.0.mtu()
+    }
+
+    /// Set the largest packet accepted L2CAP connections receive. Must be called before `listen`.
+    pub fn set_input_mtu(&self, mtu: u16) -> Result<(), BtError> {
+        self.0.set_mtu(Some(mtu), None)
+    }
+
+    /// Set the largest packet accepted L2CAP connections send. Must be called before `listen`.
+    pub fn set_output_mtu(&self, mtu: u16) -> Result<(), BtError> {
+        self.0.set_mtu(None, Some(mtu))
+    }
+
+    /// Returns the address of the local adapter and the channel or PSM the socket is bound to.
+    pub fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        self.0.local_addr()
+    }
+
+    /// Returns true if accepted connections are only confirmed once they are first read from.
+    pub fn defer_setup(&self) -> Result<bool, BtError> {
+        self.0.defer_setup()
+    }
+
+    /// Set whether accepted connections are only confirmed once they are first read from
+    /// (`BT_DEFER_SETUP`), which allows checking the remote device before it is connected.
+    /// Closing an accepted socket before reading rejects the connection. Must be called before
+    /// `listen`.
+    pub fn set_defer_setup(&self, defer: bool) -> Result<(), BtError> {
+        self.0.set_defer_setup(defer)
+    }
+
+    /// Returns the security level accepted connections require.
+    pub fn security(&self) -> Result<SecurityLevel, BtError> {
+        self.0.security()
+    }
+
+    /// Set the security level accepted connections require. Must be called before `listen`,
+    /// connections which cannot reach the level are rejected.
+    pub fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        self.0.set_security(level)
+    }
+
+    /// Returns the link mode flags of accepted connections.
+    pub fn link_mode(&self) -> Result<LinkMode, BtError> {
+        self.0.link_mode()
+    }
+
+    /// Set the link mode flags of accepted connections, the legacy alternative to `set_security`.
+    pub fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        self.0.set_link_mode(mode)
+    }
+
+    /// Returns the size of the send buffer in bytes (`SO_SNDBUF`).
+    pub fn send_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.send_buffer_size()
+    }
+
+    /// Set the size of the send buffer in bytes. The kernel doubles the value for its bookkeeping
+    /// overhead.
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_send_buffer_size(size)
+    }
+
+    /// Returns the size of the receive buffer in bytes (`SO_RCVBUF`).
+    pub fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        self.0.recv_buffer_size()
+    }
+
+    /// Set the size of the receive buffer in bytes. The kernel doubles the value for its
+    /// bookkeeping overhead.
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.0.set_recv_buffer_size(size)
+    }
+
+    /// Returns how long closing the listener waits for unsent data (`SO_LINGER`), `None` if closing
+    /// returns immediately.
+    pub fn linger(&self) -> Result<Option<Duration>, BtError> {
+        self.0.linger()
+    }
+
+    /// Set how long closing the listener waits for unsent data, in whole seconds. `None` returns
+    /// immediately and sends the data in the background.
+    pub fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        self.0.set_linger(timeout)
+    }
+
+    /// Start listening for incoming connections, queueing up to `backlog` of them.
+    pub fn listen(&self, backlog: i32) -> Result<(), BtError> {
+        self.0.listen(backlog)
+    }
+
+    /// Wait for an incoming connection and return the connected socket and the address of the
+    /// remote device.
+    ///
+    /// This function blocks until a remote device connects.
+    pub fn accept(&self) -> Result<(BtSocket, BtAddr), BtError> {
+        // Create temporary `mio` event loop
+        let mut evtloop = mio::Poll::new().unwrap();
+        let token = mio::Token(0);
+        let mut events = mio::Events::with_capacity(2);
+        let mut listener = &self.0;
+
+        loop {
+            if let Some(accepted) = self.try_accept()? {
+                return Ok(accepted);
+            }
+
+            // Wait for the next connection to arrive
+            evtloop.registry().register(&mut listener, token, mio::Interest::READABLE).unwrap();
+            evtloop.poll(&mut events, None).unwrap();
+            evtloop.registry().deregister(&mut listener).unwrap();
+        }
+    }
+
+    /// Accept a pending incoming connection without blocking.
+    ///
+    /// Returns `None` if no connection is currently pending. In that case wait for the listener to
+    /// become readable (using `mio`) and try again.
+    pub fn try_accept(&self) -> Result<Option<(BtSocket, BtAddr)>, BtError> {
+        Ok(self.0.accept()?.map(|(socket, addr)| (BtSocket::from(socket), addr)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for BtListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl mio::event::Source for BtListener {
+    fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        self.0.deregister(registry)
+    }
+}
+
 
 /// What needs to happen to advance to the next state an asynchronous process
-#[allow(missing_debug_implementations)] // `&mio::Evented` doesn't do `Debug`
+#[allow(missing_debug_implementations)] // `&mut mio::event::Source` doesn't do `Debug`
 pub enum BtAsync<'a> {
-    /// Caller needs to wait for the given `Evented` object to reach the given `Ready` state
-    WaitFor(&'a mio::Evented, mio::Ready),
+    /// Caller needs to wait for the given event source to become ready for the given `Interest`
+    WaitFor(&'a mut dyn mio::event::Source, mio::Interest),
 
     /// Asynchronous transaction has completed
     Done,
@@ -121,28 +584,586 @@ pub enum BtAsync<'a> {
 
 
 /// Manages the bluetooth connection process when used from an asynchronous client.
+///
+/// Dropping an unfinished connection process aborts it, including a running SDP query.
 #[derive(Debug)]
-pub struct BtSocketConnect<'a>(platform::BtSocketConnect<'a>);
+pub struct BtSocketConnect<'a>(platform::BtSocketConnect<&'a mut platform::BtSocket>);
+
+impl<'a> BtSocketConnect<'a> {
+    /// Advance the connection process to the next state
+    ///
+    /// Usage: When receiving a new `BtSocketConnect` instance call this function to get the
+    /// connection process started, then wait for the condition requested in `BtAsync` to apply
+    /// (by polling for it in a `mio.Poll` instance in general). Once the condition is met, invoke
+    /// this function again to advance to the next connect step. Repeat this process until you reach
+    /// `BtAsync::Done`, then discard this object and enjoy your established connection.
+    pub fn advance(&mut self) -> Result<BtAsync, BtError> {
+        self.0.advance()
+    }
+
+    /// Turn the connection process into a future, which waits for file descriptors through
+    /// `reactor` instead of `mio`.
+    ///
+    /// This allows connecting from any async runtime which can supply a `BtReactor`. The socket is
+    /// switched to non-blocking mode, so that neither the connection nor later reads and writes
+    /// stall the executor, and stays in it once connected.
+    #[cfg(target_os = "linux")]
+    pub fn with_reactor<R: BtReactor>(self, reactor: R) -> BtSocketConnectFuture<'a, R> {
+        let result = self.0.socket().set_nonblocking(true);
+        BtSocketConnectFuture::new(self, reactor, result)
+    }
+
+    /// Drive the connection process to completion using a temporary `mio` event loop.
+    fn wait(self) -> Result<(), BtError> {
+        self.wait_timeout(None)
+    }
+
+    /// Drive the connection process to completion using a temporary `mio` event loop, giving up
+    /// after `timeout`.
+    fn wait_timeout(mut self, timeout: Option<Duration>) -> Result<(), BtError> {
+        wait_until_done_timeout(&mut self, timeout)
+    }
+}
+
+impl<'a> Advance for BtSocketConnect<'a> {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn pollfd(&self) -> RawFd {
+        self.0.pollfd()
+    }
+}
+
+
+/// A connection process which owns its socket, for futures which cannot borrow one.
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+#[derive(Debug)]
+pub(crate) struct BtSocketConnectOwned(platform::BtSocketConnect<platform::BtSocket>);
+
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+impl BtSocketConnectOwned {
+    /// Connect to the RFCOMM service with the service class `service`, see
+    /// `BtSocket::connect_service`.
+    pub(crate) fn service(socket: BtSocket, addr: BtAddr, service: BtUuid) -> BtSocketConnectOwned {
+        BtSocketConnectOwned(platform::BtSocketConnect::new(socket.0, addr, service))
+    }
+
+    /// Connect to the RFCOMM `channel`, see `BtSocket::connect_channel`.
+    pub(crate) fn channel(socket: BtSocket, addr: BtAddr, channel: u8) -> BtSocketConnectOwned {
+        BtSocketConnectOwned(platform::BtSocketConnect::with_channel(socket.0, addr, channel))
+    }
+
+    /// Connect to the L2CAP `psm`, see `BtSocket::connect_psm`.
+    pub(crate) fn psm(socket: BtSocket, addr: BtAddr, psm: u16) -> BtSocketConnectOwned {
+        BtSocketConnectOwned(platform::BtSocketConnect::with_psm(socket.0, addr, psm))
+    }
+
+    /// Returns the socket, connected once `advance` has reached `BtAsync::Done`.
+    pub(crate) fn into_socket(self) -> BtSocket {
+        BtSocket(self.0.into_socket())
+    }
+}
+
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+impl Advance for BtSocketConnectOwned {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn pollfd(&self) -> RawFd {
+        self.0.pollfd()
+    }
+}
+
+
+/// An asynchronous process that is driven by `advance()` calls until it reaches `BtAsync::Done`.
+pub(crate) trait Advance {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError>;
+
+    /// Returns the file descriptor the last `BtAsync::WaitFor` asked to wait for.
+    #[cfg(target_os = "linux")]
+    fn pollfd(&self) -> RawFd;
+}
+
+/// Drive an asynchronous process to completion using a temporary `mio` event loop.
+fn wait_until_done<A: Advance>(process: &mut A) -> Result<(), BtError> {
+    wait_until_done_timeout(process, None)
+}
+
+/// Drive an asynchronous process to completion using a temporary `mio` event loop, giving up
+/// after `timeout`.
+pub(crate) fn wait_until_done_timeout<A: Advance>(process: &mut A, timeout: Option<Duration>) -> Result<(), BtError> {
+    // Create temporary `mio` event loop
+    let mut evtloop = mio::Poll::new().unwrap();
+    let token = mio::Token(0);
+    let mut events = mio::Events::with_capacity(2);
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    loop {
+        match process.advance()? {
+            BtAsync::WaitFor(evented, interest) => {
+                let mut event_received = false;
+                while !event_received {
+                    let remaining = match deadline {
+                        Some(deadline) => {
+                            let now = Instant::now();
+                            if now >= deadline {
+                                return Err(BtError::Kind(BtErrorKind::Timeout, Some(::libc::ETIMEDOUT as u32), "Operation timed out".to_string()));
+                            }
+                            Some(deadline - now)
+                        }
+                        None => None,
+                    };
+
+                    // Register this, single, event source
+                    evtloop.registry().register(evented, token, interest).unwrap();
+
+                    // Wait for it to transition to the requested state
+                    evtloop.poll(&mut events, remaining).unwrap();
+                    evtloop.registry().deregister(evented).unwrap();
+
+                    for event in events.iter() {
+                        if event.token() == token {
+                            event_received = true;
+                        }
+                    }
+                }
+            }
+
+            BtAsync::Done => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+
+/// Lists the SDP service records published by the remote device with address `addr`.
+///
+/// This function can block for some seconds.
+pub fn browse_services(addr: BtAddr) -> Result<Vec<BtServiceRecord>, BtError> {
+    let mut browse = browse_services_async(addr);
+    wait_until_done(&mut browse)?;
+    Ok(browse.take_records().unwrap())
+}
+
+/// Lists the SDP service records published by the remote device with address `addr`.
+///
+/// This function will return immediately, the returned `BtServiceBrowse` needs to be driven like
+/// `BtSocketConnect`.
+pub fn browse_services_async(addr: BtAddr) -> BtServiceBrowse {
+    BtServiceBrowse(platform::BtServiceBrowse::new(BtAddr::any(), addr))
+}
+
+/// Registers `record` with the SDP server of the local Bluetooth stack, so that remote devices can
+/// discover the service.
+///
+/// The record stays registered until the returned `BtServiceRegistration` is dropped.
+///
+/// On Linux, records are registered through the UNIX socket `/var/run/sdp`, which BlueZ 5 only
+/// provides if `bluetoothd` runs in compatibility mode (`--compat` or `-C`).
+pub fn register_service(record: &BtServiceRecord) -> Result<BtServiceRegistration, BtError> {
+    Ok(BtServiceRegistration(platform::BtServiceRegistration::register(record)?))
+}
+
+/// A service record registered with the local SDP server.
+///
+/// Unregisters the record when dropped.
+#[derive(Debug)]
+pub struct BtServiceRegistration(platform::BtServiceRegistration);
+
+impl BtServiceRegistration {
+    /// Returns the handle the local SDP server assigned to the record.
+    pub fn handle(&self) -> u32 {
+        self.0.handle()
+    }
+}
+
+/// Manages the SDP service browsing process when used from an asynchronous client.
+#[derive(Debug)]
+pub struct BtServiceBrowse(platform::BtServiceBrowse);
+
+impl BtServiceBrowse {
+    /// Advance the browsing process to the next state
+    ///
+    /// See `BtSocketConnect::advance` for the usage. Once `BtAsync::Done` has been reached, the
+    /// records can be retrieved using `take_records`.
+    pub fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    /// Returns the service records found on the remote device.
+    ///
+    /// Returns `None` if the browsing process has not completed yet or the records have already
+    /// been taken.
+    pub fn take_records(&mut self) -> Option<Vec<BtServiceRecord>> {
+        self.0.take_records()
+    }
+
+    /// Turn the browsing process into a future resolving to the service records, which waits for
+    /// file descriptors through `reactor` instead of `mio`.
+    #[cfg(target_os = "linux")]
+    pub fn with_reactor<R: BtReactor>(self, reactor: R) -> BtServiceBrowseFuture<R> {
+        BtServiceBrowseFuture::new(self, reactor)
+    }
+}
+
+impl Advance for BtServiceBrowse {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn pollfd(&self) -> RawFd {
+        self.0.pollfd()
+    }
+}
+
+
+/// Requests the name of the remote device with address `addr`.
+///
+/// This function can block for some seconds.
+pub fn read_remote_name(addr: BtAddr) -> Result<String, BtError> {
+    let mut request = read_remote_name_async(addr);
+    wait_until_done(&mut request)?;
+    Ok(request.take_name().unwrap())
+}
+
+/// Requests the name of the remote device with address `addr`.
+///
+/// This function will return immediately, the returned `BtReadRemoteName` needs to be driven like
+/// `BtSocketConnect`.
+pub fn read_remote_name_async(addr: BtAddr) -> BtReadRemoteName {
+    BtReadRemoteName(platform::BtReadRemoteName::new(None, addr))
+}
+
+/// Manages a remote name request when used from an asynchronous client.
+///
+/// Dropping an unfinished request cancels it.
+#[derive(Debug)]
+pub struct BtReadRemoteName(platform::BtReadRemoteName);
+
+impl BtReadRemoteName {
+    /// Advance the name request to the next state
+    ///
+    /// See `BtSocketConnect::advance` for the usage. Once `BtAsync::Done` has been reached, the
+    /// name can be retrieved using `take_name`.
+    pub fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    /// Returns the name of the remote device.
+    ///
+    /// Returns `None` if the request has not completed yet or the name has already been taken.
+    pub fn take_name(&mut self) -> Option<String> {
+        self.0.take_name()
+    }
+
+    /// Requests the name of `device`, found by an inquiry with `options`, through the same
+    /// adapter.
+    #[cfg(all(feature = "tokio", target_os = "linux"))]
+    pub(crate) fn for_device(options: &ScanOptions, device: &BtDevice) -> BtReadRemoteName {
+        BtReadRemoteName(platform::BtReadRemoteName::with_inquiry_info(options.adapter,
+                                                                        device.addr,
+                                                                        device.page_scan_repetition_mode.unwrap_or(0x01),
+                                                                        device.clock_offset))
+    }
+}
+
+impl Advance for BtReadRemoteName {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        self.0.advance()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn pollfd(&self) -> RawFd {
+        self.0.pollfd()
+    }
+}
+
+
+/// Finds a vector of Bluetooth devices in range.
+///
+/// This function blocks for some seconds.
+pub fn scan_devices() -> Result<Vec<BtDevice>, BtError> {
+    scan_devices_with(ScanOptions::default())
+}
+
+/// Finds a vector of Bluetooth devices in range, using the inquiry parameters in `options`.
+///
+/// This function blocks for at least the inquiry length.
+pub fn scan_devices_with(options: ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+    platform::scan_devices(&options)
+}
+
+/// Starts an inquiry which reports the devices in range as they respond.
+///
+/// The inquiry uses the length, maximum number of responses and access code in `options`. Names
+/// are not requested, use `read_remote_name_async` for that.
+pub fn discover_devices(options: ScanOptions) -> Result<BtDiscovery, BtError> {
+    Ok(BtDiscovery(platform::BtDiscovery::start(&options)?))
+}
+
+/// An event reported during a device discovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BtDiscoveryEvent {
+    /// A device responded to the inquiry.
+    InquiryResult(BtDevice),
+
+    /// A device responded to the inquiry, including the received signal strength.
+    InquiryResultWithRSSI(BtDevice),
+
+    /// A device responded to the inquiry, including the received signal strength and its extended
+    /// inquiry response. The raw response data follows the device, the parsed response is stored in
+    /// `BtDevice::eir`.
+    ExtendedInquiryResult(BtDevice, Vec<u8>),
+
+    /// The inquiry has finished, no further events follow.
+    InquiryComplete,
+}
+
+/// A running device discovery.
+///
+/// Register it with a `mio::Poll` for readable events and call `next_event` until it returns
+/// `None` whenever it becomes readable. Dropping an unfinished discovery cancels the inquiry.
+#[derive(Debug)]
+pub struct BtDiscovery(platform::BtDiscovery);
+
+impl BtDiscovery {
+    /// Returns the next discovery event, or `None` if no event is available right now.
+    pub fn next_event(&mut self) -> Result<Option<BtDiscoveryEvent>, BtError> {
+        self.0.next_event()
+    }
+
+    /// Cancels the inquiry. Events which have already been received are still reported.
+    pub fn cancel(&mut self) -> Result<(), BtError> {
+        self.0.cancel()
+    }
+
+    /// Returns true once the inquiry has finished and all events have been reported.
+    pub fn is_done(&self) -> bool {
+        self.0.is_done()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for BtDiscovery {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl mio::event::Source for BtDiscovery {
+    fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        self.0.deregister(registry)
+    }
+}
+
+/// A raw HCI socket to a local adapter, for sending commands and receiving events.
+///
+/// The socket is non-blocking: register it with a `mio::Poll` for readable events and call
+/// `read_event` until it returns `None`. Sending commands requires the `CAP_NET_RAW` capability.
+#[derive(Debug)]
+pub struct HciSocket(platform::HciSocket);
+
+impl HciSocket {
+    /// Opens an HCI socket to `adapter` which receives all events.
+    pub fn open(adapter: &BtAdapter) -> Result<HciSocket, BtError> {
+        Ok(HciSocket(platform::HciSocket::open(Some(adapter.id))?))
+    }
+
+    /// Opens an HCI socket to the default adapter which receives all events.
+    pub fn open_default() -> Result<HciSocket, BtError> {
+        Ok(HciSocket(platform::HciSocket::open(None)?))
+    }
+
+    /// Restricts the events the socket receives.
+    pub fn set_filter(&self, filter: &HciFilter) -> Result<(), BtError> {
+        self.0.set_filter(filter)
+    }
+
+    /// Sends a command to the adapter.
+    ///
+    /// Its result is reported by a later `HciEvent::CommandComplete` or `HciEvent::CommandStatus`.
+    pub fn send(&self, command: &HciCommand) -> Result<(), BtError> {
+        self.0.send(command)
+    }
+
+    /// Returns the next event, or `None` if no event is available right now.
+    pub fn read_event(&self) -> Result<Option<HciEvent>, BtError> {
+        self.0.read_event()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for HciSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl mio::event::Source for HciSocket {
+    fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> std::io::Result<()> {
+        self.0.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        self.0.deregister(registry)
+    }
+}
+
+/// Switches the local adapter to the given inquiry result format.
+///
+/// The mode stays active for later inquiries until it is changed again or the adapter is reset.
+/// Requires the `CAP_NET_ADMIN` capability.
+pub fn set_inquiry_mode(mode: InquiryMode) -> Result<(), BtError> {
+    platform::write_inquiry_mode(None, mode)
+}
+
+/// The format in which the adapter reports inquiry results.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InquiryMode {
+    /// Standard inquiry results without signal strength.
+    Standard = 0x00,
+
+    /// Inquiry results including the received signal strength.
+    Rssi = 0x01,
+
+    /// Inquiry results with signal strength, or extended inquiry results if the device provides
+    /// extended inquiry response data.
+    Extended = 0x02,
+}
+
+/// The inquiry access code which selects the devices responding to an inquiry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InquiryAccessCode {
+    /// General Inquiry Access Code: all discoverable devices respond.
+    General,
+
+    /// Limited Inquiry Access Code: only devices in limited discoverable mode respond.
+    Limited,
+}
+
+impl InquiryAccessCode {
+    /// Returns the lower address part of the access code in little-endian byte order.
+    #[doc(hidden)]
+    pub fn lap(&self) -> [u8; 3] {
+        match *self {
+            InquiryAccessCode::General => [0x33, 0x8B, 0x9E],
+            InquiryAccessCode::Limited => [0x00, 0x8B, 0x9E],
+        }
+    }
+}
+
+/// Parameters of a device scan.
+///
+/// The defaults are a 1.28 second inquiry with the general access code, up to 255 responses, a
+/// flushed inquiry cache and name resolution without timeout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub(crate) inquiry_length: u8,
+    pub(crate) max_responses: u8,
+    pub(crate) access_code: InquiryAccessCode,
+    pub(crate) flush_cache: bool,
+    pub(crate) resolve_names: bool,
+    pub(crate) name_timeout: Option<Duration>,
+    pub(crate) inquiry_mode: Option<InquiryMode>,
+    pub(crate) adapter: Option<u16>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions {
+            inquiry_length: 1,
+            max_responses: 255,
+            access_code: InquiryAccessCode::General,
+            flush_cache: true,
+            resolve_names: true,
+            name_timeout: None,
+            inquiry_mode: None,
+            adapter: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Create the default scan options.
+    pub fn new() -> ScanOptions {
+        ScanOptions::default()
+    }
+
+    /// Set the length of the inquiry.
+    ///
+    /// The length is rounded up to a multiple of 1.28 seconds and limited to 61.44 seconds.
+    pub fn inquiry_length(mut self, length: Duration) -> ScanOptions {
+        let millis = length.as_secs() * 1000 + length.subsec_millis() as u64;
+        self.inquiry_length = millis.div_ceil(1280).clamp(1, 48) as u8;
+        self
+    }
+
+    /// Set the maximum number of devices to report.
+    pub fn max_responses(mut self, max_responses: u8) -> ScanOptions {
+        self.max_responses = max_responses;
+        self
+    }
+
+    /// Set the inquiry access code.
+    pub fn access_code(mut self, access_code: InquiryAccessCode) -> ScanOptions {
+        self.access_code = access_code;
+        self
+    }
+
+    /// Set whether the inquiry cache of the adapter is flushed before the inquiry. If it is not
+    /// flushed, devices found by earlier inquiries may be reported again.
+    pub fn flush_cache(mut self, flush_cache: bool) -> ScanOptions {
+        self.flush_cache = flush_cache;
+        self
+    }
+
+    /// Set whether the names of the found devices are requested. If not, `BtDevice::name` is always
+    /// `None`.
+    pub fn resolve_names(mut self, resolve_names: bool) -> ScanOptions {
+        self.resolve_names = resolve_names;
+        self
+    }
 
-impl<'a> BtSocketConnect<'a> {
-    /// Advance the connection process to the next state
-    ///
-    /// Usage: When receiving a new `BtSocketConnect` instance call this function to get the
-    /// connection process started, then wait for the condition requested in `BtAsync` to apply
-    /// (by polling for it in a `mio.Poll` instance in general). Once the condition is met, invoke
-    /// this function again to advance to the next connect step. Repeat this process until you reach
-    /// `BtAsync::Done`, then discard this object and enjoy your established connection.
-    pub fn advance(&mut self) -> Result<BtAsync, BtError> {
-        self.0.advance()
+    /// Set how long to wait for the name of each device. `None` waits for as long as the adapter
+    /// permits. Devices which do not answer in time are reported without a name.
+    pub fn name_timeout(mut self, timeout: Option<Duration>) -> ScanOptions {
+        self.name_timeout = timeout;
+        self
     }
-}
 
+    /// Switch the adapter to `mode` before the inquiry, see `set_inquiry_mode`. By default the
+    /// current mode of the adapter is kept.
+    ///
+    /// `scan_devices_with` only reports the signal strength of the devices if a mode other than
+    /// `InquiryMode::Standard` is set here.
+    pub fn inquiry_mode(mut self, mode: InquiryMode) -> ScanOptions {
+        self.inquiry_mode = Some(mode);
+        self
+    }
 
-/// Finds a vector of Bluetooth devices in range.
-///
-/// This function blocks for some seconds.
-pub fn scan_devices() -> Result<Vec<BtDevice>, BtError> {
-    platform::scan_devices()
+    /// Set the local adapter which runs the inquiry. By default the operating system chooses one.
+    pub fn adapter(mut self, adapter: &BtAdapter) -> ScanOptions {
+        self.adapter = Some(adapter.id);
+        self
+    }
 }
 
 /// Represents an error which occurred in this library.
@@ -156,6 +1177,32 @@ pub enum BtError {
 
     /// This error only has a description.
     Desc(String),
+
+    /// An error of a known kind: the kind, the error code reported by the operating system if
+    /// there is one, and an explanation.
+    Kind(BtErrorKind, Option<u32>, String),
+}
+
+impl BtError {
+    /// Returns the kind of the error.
+    ///
+    /// The kind is determined where the error occurs, errors which do not fall into any of the
+    /// kinds are of kind `BtErrorKind::Other`.
+    pub fn kind(&self) -> BtErrorKind {
+        match *self {
+            BtError::Kind(kind, _, _) => kind,
+            BtError::Unknown | BtError::Errno(_, _) | BtError::Desc(_) => BtErrorKind::Other,
+        }
+    }
+
+    /// Returns the error code reported by the operating system, if any.
+    pub fn errno(&self) -> Option<u32> {
+        match *self {
+            BtError::Errno(errno, _) => Some(errno),
+            BtError::Kind(_, errno, _) => errno,
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for BtError {
@@ -170,16 +1217,91 @@ impl std::error::Error for BtError {
             &BtError::Unknown => "Unknown Bluetooth Error",
             &BtError::Errno(_, ref message) => message.as_str(),
             &BtError::Desc(ref message) => message.as_str(),
+            BtError::Kind(_, _, message) => message.as_str(),
         }
     }
 }
 
+impl From<BtError> for std::io::Error {
+    fn from(error: BtError) -> std::io::Error {
+        use std::io::ErrorKind;
+
+        let kind = match error.kind() {
+            BtErrorKind::AdapterNotFound => ErrorKind::NotFound,
+            BtErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            BtErrorKind::HostDown => ErrorKind::HostUnreachable,
+            BtErrorKind::ConnectionRefused => ErrorKind::ConnectionRefused,
+            BtErrorKind::Timeout => ErrorKind::TimedOut,
+            BtErrorKind::SdpProtocol(_) => ErrorKind::InvalidData,
+            BtErrorKind::ServiceNotFound => ErrorKind::NotFound,
+            BtErrorKind::InvalidAddress => ErrorKind::InvalidInput,
+            // Unclassified error codes still tell apart e.g. `WouldBlock` and `Interrupted`
+            BtErrorKind::Other => {
+                error.errno().map_or(ErrorKind::Other, |errno| std::io::Error::from_raw_os_error(errno as i32).kind())
+            }
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+/// The kind of a `BtError`, see `BtError::kind`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BtErrorKind {
+    /// The local Bluetooth adapter does not exist.
+    AdapterNotFound,
+
+    /// The process lacks the privileges for the operation.
+    PermissionDenied,
+
+    /// The remote device did not respond to the page, it is switched off or out of range.
+    HostDown,
+
+    /// The remote device rejected the connection.
+    ConnectionRefused,
+
+    /// The operation did not finish in time.
+    Timeout,
+
+    /// The SDP server of the remote device answered with an error response, whose error code is
+    /// given, or with a malformed response.
+    SdpProtocol(Option<u16>),
+
+    /// The remote device does not offer the requested service.
+    ServiceNotFound,
+
+    /// A string is not a valid Bluetooth address.
+    InvalidAddress,
+
+    /// Any other error.
+    Other,
+}
+
 
 /// A 6-byte long MAC address.
 #[repr(C, packed)]
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub struct BtAddr(pub [u8; 6]);
 
+impl FromStr for BtAddr {
+    type Err = BtError;
+
+    /// Converts a string of the format `XX:XX:XX:XX:XX:XX` to a `BtAddr`, failing with
+    /// `BtErrorKind::InvalidAddress`.
+    fn from_str(s: &str) -> Result<BtAddr, BtError> {
+        BtAddr::from_str(s).map_err(|_| BtError::Kind(BtErrorKind::InvalidAddress, None, format!("Invalid Bluetooth address `{}`", s)))
+    }
+}
+
+/// The address of one end of a connection: the device and the RFCOMM channel or L2CAP PSM.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BtSocketAddr {
+    /// The MAC address of the device.
+    pub addr: BtAddr,
+
+    /// The RFCOMM channel or the L2CAP PSM.
+    pub channel: u16,
+}
+
 impl std::fmt::Debug for BtAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f,
@@ -256,22 +1378,468 @@ impl BtAddr {
 }
 
 
+/// A Bluetooth UUID identifying a service class or protocol.
+///
+/// 16-bit and 32-bit UUIDs are shorthands for 128-bit UUIDs derived from the Bluetooth base UUID
+/// `00000000-0000-1000-8000-00805F9B34FB`. 128-bit UUIDs are stored in big-endian byte order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BtUuid {
+    /// A 16-bit UUID, e.g. `0x1101` for the Serial Port profile.
+    Uuid16(u16),
+
+    /// A 32-bit UUID.
+    Uuid32(u32),
+
+    /// A full 128-bit UUID.
+    Uuid128([u8; 16]),
+}
+
+const BT_BASE_UUID: [u8; 16] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0x80, 0x5F, 0x9B, 0x34, 0xFB];
+
+impl BtUuid {
+    /// The Serial Port profile (`0x1101`).
+    pub fn serial_port() -> BtUuid {
+        BtUuid::Uuid16(0x1101)
+    }
+
+    /// The Dial-Up Networking profile (`0x1103`).
+    pub fn dialup_networking() -> BtUuid {
+        BtUuid::Uuid16(0x1103)
+    }
+
+    /// The OBEX Object Push profile (`0x1105`).
+    pub fn obex_object_push() -> BtUuid {
+        BtUuid::Uuid16(0x1105)
+    }
+
+    /// Returns the full 128-bit form of this UUID in big-endian byte order.
+    pub fn to_uuid128(&self) -> [u8; 16] {
+        let short = match *self {
+            BtUuid::Uuid16(value) => value as u32,
+            BtUuid::Uuid32(value) => value,
+            BtUuid::Uuid128(bytes) => return bytes,
+        };
+
+        let mut bytes = BT_BASE_UUID;
+        bytes[0] = (short >> 24) as u8;
+        bytes[1] = (short >> 16) as u8;
+        bytes[2] = (short >> 8) as u8;
+        bytes[3] = short as u8;
+        bytes
+    }
+}
+
+impl FromStr for BtUuid {
+    type Err = ();
+
+    /// Converts a string of the format `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX` to a 128-bit `BtUuid`.
+    fn from_str(s: &str) -> Result<BtUuid, ()> {
+        let group_lengths = [8, 4, 4, 4, 12];
+        let mut bytes = [0u8; 16];
+        let mut i = 0;
+        let mut groups = 0;
+        for group in s.split('-') {
+            if groups == group_lengths.len() || group.len() != group_lengths[groups] {
+                return Err(());
+            }
+            for pair in group.as_bytes().chunks(2) {
+                let high = (pair[0] as char).to_digit(16).ok_or(())?;
+                let low = (pair[1] as char).to_digit(16).ok_or(())?;
+                bytes[i] = (high * 16 + low) as u8;
+                i += 1;
+            }
+            groups += 1;
+        }
+        if groups != group_lengths.len() {
+            return Err(());
+        }
+        Ok(BtUuid::Uuid128(bytes))
+    }
+}
+
+impl std::fmt::Display for BtUuid {
+    /// Formats `BtUuid` as a string of the format `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let b = self.to_uuid128();
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+               b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7], b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15])
+    }
+}
+
+
+/// An SDP service record published by a remote device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BtServiceRecord {
+    /// The handle of the record on the remote SDP server.
+    pub handle: u32,
+
+    /// The service classes this service is an instance of.
+    pub service_classes: Vec<BtUuid>,
+
+    /// The human-readable name of the service.
+    pub name: Option<String>,
+
+    /// A brief description of the service.
+    pub description: Option<String>,
+
+    /// The name of the person or organization providing the service.
+    pub provider: Option<String>,
+
+    /// The protocol stack which is used to access the service, lowest layer first.
+    pub protocols: Vec<BtProtocolDescriptor>,
+
+    /// The Bluetooth profiles this service conforms to.
+    pub profiles: Vec<BtProfileDescriptor>,
+}
+
+impl BtServiceRecord {
+    /// Create a record for a service with the service class `service_class` which is accessed
+    /// through RFCOMM channel `channel`.
+    ///
+    /// The record conforms to version 1.2 of the profile `service_class`, name, description and
+    /// provider are left empty.
+    pub fn rfcomm(service_class: BtUuid, channel: u8) -> BtServiceRecord {
+        BtServiceRecord {
+            handle: 0,
+            service_classes: vec![service_class],
+            name: None,
+            description: None,
+            provider: None,
+            protocols: vec![BtProtocolDescriptor {
+                                protocol: BtUuid::Uuid16(0x0100),
+                                parameter: None,
+                            },
+                            BtProtocolDescriptor {
+                                protocol: BtUuid::Uuid16(0x0003),
+                                parameter: Some(channel as u16),
+                            }],
+            profiles: vec![BtProfileDescriptor {
+                               profile: service_class,
+                               version: 0x0102,
+                           }],
+        }
+    }
+
+    /// Parses a record from its SDP attribute list.
+    pub fn from_sdp_value(attributes: &SdpValue) -> Result<BtServiceRecord, BtError> {
+        sdp_data::parse_service_record(attributes)
+    }
+
+    /// Returns the SDP attribute list of this record, as it is registered with an SDP server.
+    pub fn to_sdp_value(&self) -> SdpValue {
+        sdp_data::build_service_record(self)
+    }
+
+    /// Returns the RFCOMM channel of the service, if it is accessed through RFCOMM.
+    pub fn rfcomm_channel(&self) -> Option<u8> {
+        self.protocol_parameter(BtUuid::Uuid16(0x0003)).map(|channel| channel as u8)
+    }
+
+    /// Returns the L2CAP PSM of the service, if it is accessed through L2CAP and specifies one.
+    pub fn l2cap_psm(&self) -> Option<u16> {
+        self.protocol_parameter(BtUuid::Uuid16(0x0100))
+    }
+
+    fn protocol_parameter(&self, protocol: BtUuid) -> Option<u16> {
+        self.protocols
+            .iter()
+            .filter(|descriptor| descriptor.protocol.to_uuid128() == protocol.to_uuid128())
+            .filter_map(|descriptor| descriptor.parameter)
+            .next()
+    }
+}
+
+/// An entry in the protocol descriptor list of a `BtServiceRecord`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BtProtocolDescriptor {
+    /// The protocol, e.g. `0x0100` for L2CAP or `0x0003` for RFCOMM.
+    pub protocol: BtUuid,
+
+    /// The protocol specific parameter, e.g. the L2CAP PSM or the RFCOMM channel.
+    pub parameter: Option<u16>,
+}
+
+/// An entry in the profile descriptor list of a `BtServiceRecord`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BtProfileDescriptor {
+    /// The profile, e.g. `0x1101` for the Serial Port profile.
+    pub profile: BtUuid,
+
+    /// The profile version, major version in the upper and minor version in the lower byte.
+    pub version: u16,
+}
+
+/// A local Bluetooth adapter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BtAdapter {
+    /// The device id of the adapter, `N` in `hciN`.
+    pub id: u16,
+
+    /// The MAC address of the adapter.
+    pub addr: BtAddr,
+
+    /// The friendly name of the adapter, `None` if the adapter is down.
+    pub name: Option<String>,
+
+    /// True if the adapter is up.
+    pub up: bool,
+
+    /// The LMP features supported by the adapter, as a bit mask.
+    pub features: [u8; 8],
+}
+
+impl BtAdapter {
+    /// Lists all local adapters.
+    pub fn list() -> Result<Vec<BtAdapter>, BtError> {
+        platform::list_adapters()
+    }
+
+    /// Returns the adapter the operating system uses if none is chosen explicitly.
+    pub fn default_adapter() -> Result<BtAdapter, BtError> {
+        platform::default_adapter()
+    }
+
+    /// Returns the device name of the adapter, like `hci0`.
+    pub fn device_name(&self) -> String {
+        format!("hci{}", self.id)
+    }
+
+    /// Lists the SDP service records published by the remote device with address `addr`, querying
+    /// through this adapter.
+    ///
+    /// This function can block for some seconds.
+    pub fn browse_services(&self, addr: BtAddr) -> Result<Vec<BtServiceRecord>, BtError> {
+        let mut browse = self.browse_services_async(addr);
+        wait_until_done(&mut browse)?;
+        Ok(browse.take_records().unwrap())
+    }
+
+    /// Asynchronous version of `browse_services`, see `browse_services_async`.
+    pub fn browse_services_async(&self, addr: BtAddr) -> BtServiceBrowse {
+        BtServiceBrowse(platform::BtServiceBrowse::new(self.addr, addr))
+    }
+
+    /// Requests the name of the remote device with address `addr` through this adapter.
+    ///
+    /// This function can block for some seconds.
+    pub fn read_remote_name(&self, addr: BtAddr) -> Result<String, BtError> {
+        let mut request = self.read_remote_name_async(addr);
+        wait_until_done(&mut request)?;
+        Ok(request.take_name().unwrap())
+    }
+
+    /// Asynchronous version of `read_remote_name`, see `read_remote_name_async`.
+    pub fn read_remote_name_async(&self, addr: BtAddr) -> BtReadRemoteName {
+        BtReadRemoteName(platform::BtReadRemoteName::new(Some(self.id), addr))
+    }
+
+    /// Switches this adapter to the given inquiry result format, see `set_inquiry_mode`.
+    pub fn set_inquiry_mode(&self, mode: InquiryMode) -> Result<(), BtError> {
+        platform::write_inquiry_mode(Some(self.id), mode)
+    }
+
+    /// Brings the adapter up.
+    ///
+    /// Requires the `CAP_NET_ADMIN` capability, an error of kind `BtErrorKind::PermissionDenied` is
+    /// returned otherwise.
+    pub fn power_on(&mut self) -> Result<(), BtError> {
+        platform::set_adapter_up(self.id, true)?;
+        self.up = true;
+        self.name = platform::read_local_name(self.id).ok();
+        Ok(())
+    }
+
+    /// Brings the adapter down, closing all its connections.
+    ///
+    /// Requires the `CAP_NET_ADMIN` capability, an error of kind `BtErrorKind::PermissionDenied` is
+    /// returned otherwise.
+    pub fn power_off(&mut self) -> Result<(), BtError> {
+        platform::set_adapter_up(self.id, false)?;
+        self.up = false;
+        self.name = None;
+        Ok(())
+    }
+
+    /// Sets whether the adapter answers inquiries (`discoverable`) and accepts incoming
+    /// connections (`connectable`).
+    ///
+    /// The adapter must be up. Requires the `CAP_NET_RAW` capability.
+    pub fn set_scan_mode(&self, discoverable: bool, connectable: bool) -> Result<(), BtError> {
+        platform::write_scan_enable(self.id, discoverable, connectable)
+    }
+
+    /// Sets the friendly name remote devices see, at most 248 bytes of UTF-8.
+    ///
+    /// The adapter must be up. Requires the `CAP_NET_RAW` capability.
+    pub fn set_name(&mut self, name: &str) -> Result<(), BtError> {
+        platform::write_local_name(self.id, name)?;
+        self.name = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Sets the class of device remote devices see.
+    ///
+    /// The adapter must be up. Requires the `CAP_NET_RAW` capability.
+    pub fn set_class(&self, class: DeviceClass) -> Result<(), BtError> {
+        platform::write_class_of_device(self.id, class)
+    }
+}
+
 /// A device with its a name and address.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BtDevice {
-    /// The name of the device.
-    pub name: String,
+    /// The name of the device, `None` if it could not be determined.
+    pub name: Option<String>,
 
     /// The MAC address of the device.
     pub addr: BtAddr,
+
+    /// The class of device reported during the inquiry.
+    pub class: Option<DeviceClass>,
+
+    /// The page scan repetition mode reported during the inquiry.
+    pub page_scan_repetition_mode: Option<u8>,
+
+    /// The clock offset reported during the inquiry.
+    pub clock_offset: Option<u16>,
+
+    /// The received signal strength in dBm, if the inquiry reported it.
+    pub rssi: Option<i8>,
+
+    /// The extended inquiry response of the device, if it sent one.
+    pub eir: Option<EirData>,
+}
+
+/// The major device class, the main category of a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MajorDeviceClass {
+    /// Miscellaneous devices.
+    Miscellaneous,
+
+    /// Desktops, laptops, PDAs, etc.
+    Computer,
+
+    /// Cellular phones, cordless phones, modems, etc.
+    Phone,
+
+    /// LAN and network access points.
+    Networking,
+
+    /// Headsets, speakers, video displays, etc.
+    AudioVideo,
+
+    /// Mice, keyboards, joysticks, etc.
+    Peripheral,
+
+    /// Printers, scanners, cameras, displays.
+    Imaging,
+
+    /// Watches, glasses, etc.
+    Wearable,
+
+    /// Toys and games.
+    Toy,
+
+    /// Health devices like blood pressure monitors or thermometers.
+    Health,
+
+    /// Devices without a specified class, e.g. most serial adapters.
+    Uncategorized,
+
+    /// A reserved major device class.
+    Reserved(u8),
+}
+
+/// A major service class, a general category of services offered by a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ServiceClass {
+    /// Device is in limited discoverable mode.
+    LimitedDiscoverable,
+
+    /// Location identification.
+    Positioning,
+
+    /// LAN, ad hoc networks, etc.
+    Networking,
+
+    /// Printing, speakers, etc.
+    Rendering,
+
+    /// Scanners, microphones, etc.
+    Capturing,
+
+    /// OBEX object transfer, e.g. v-Inbox, v-Folder.
+    ObjectTransfer,
+
+    /// Speakers, microphones, headsets, etc.
+    Audio,
+
+    /// Cordless telephony, modems, headsets.
+    Telephony,
+
+    /// Web servers, WAP servers.
+    Information,
+}
+
+const SERVICE_CLASS_BITS: [(ServiceClass, u32); 9] = [(ServiceClass::LimitedDiscoverable, 13),
+                                                      (ServiceClass::Positioning, 16),
+                                                      (ServiceClass::Networking, 17),
+                                                      (ServiceClass::Rendering, 18),
+                                                      (ServiceClass::Capturing, 19),
+                                                      (ServiceClass::ObjectTransfer, 20),
+                                                      (ServiceClass::Audio, 21),
+                                                      (ServiceClass::Telephony, 22),
+                                                      (ServiceClass::Information, 23)];
+
+/// The 24-bit class of device, describing the type of a device and the services it offers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeviceClass(pub u32);
+
+impl DeviceClass {
+    /// Create a `DeviceClass` from its three bytes in little-endian byte order, as used by HCI.
+    pub fn from_bytes(bytes: [u8; 3]) -> DeviceClass {
+        DeviceClass(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16)
+    }
+
+    /// Returns the major device class.
+    pub fn major(&self) -> MajorDeviceClass {
+        match ((self.0 >> 8) & 0x1F) as u8 {
+            0x00 => MajorDeviceClass::Miscellaneous,
+            0x01 => MajorDeviceClass::Computer,
+            0x02 => MajorDeviceClass::Phone,
+            0x03 => MajorDeviceClass::Networking,
+            0x04 => MajorDeviceClass::AudioVideo,
+            0x05 => MajorDeviceClass::Peripheral,
+            0x06 => MajorDeviceClass::Imaging,
+            0x07 => MajorDeviceClass::Wearable,
+            0x08 => MajorDeviceClass::Toy,
+            0x09 => MajorDeviceClass::Health,
+            0x1F => MajorDeviceClass::Uncategorized,
+            major => MajorDeviceClass::Reserved(major),
+        }
+    }
+
+    /// Returns the minor device class, whose meaning depends on the major device class.
+    pub fn minor(&self) -> u8 {
+        ((self.0 >> 2) & 0x3F) as u8
+    }
+
+    /// Returns whether the device offers services of the major service class `service`.
+    pub fn has_service(&self, service: ServiceClass) -> bool {
+        SERVICE_CLASS_BITS.iter().any(|&(class, bit)| class == service && self.0 & (1 << bit) != 0)
+    }
+
+    /// Returns all major service classes the device offers.
+    pub fn services(&self) -> Vec<ServiceClass> {
+        SERVICE_CLASS_BITS.iter().filter(|&&(_, bit)| self.0 & (1 << bit) != 0).map(|&(class, _)| class).collect()
+    }
 }
 
 /// The Bluetooth protocol you can use with this libary.
-///
-/// Will probably be always `RFCOMM`.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BtProtocol {
-    // L2CAP = BTPROTO_L2CAP,
+    /// L2CAP connection to a bluetooth device, addressed by a protocol/service multiplexer (PSM).
+    L2CAP(L2capMode), // = BTPROTO_L2CAP
     // HCI = BTPROTO_HCI,
     // SCO = BTPROTO_SCO,
     // BNEP = BTPROTO_BNEP,
@@ -282,12 +1850,68 @@ pub enum BtProtocol {
     RFCOMM, // = BTPROTO_RFCOMM */
 }
 
+/// The security a connection requires, from the weakest to the strongest level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SecurityLevel {
+    /// No security at all, only used by SDP.
+    Sdp = 0,
+
+    /// Encryption is not required, the default.
+    Low = 1,
+
+    /// Encryption with an unauthenticated link key.
+    Medium = 2,
+
+    /// Encryption with an authenticated link key, protecting against man-in-the-middle attacks.
+    High = 3,
+
+    /// Like `High`, with Secure Connections and a 128-bit key.
+    Fips = 4,
+}
+
+/// Link mode flags of an RFCOMM or L2CAP connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkMode {
+    /// Request the master role.
+    pub master: bool,
+
+    /// Require authentication.
+    pub auth: bool,
+
+    /// Require encryption, which implies authentication.
+    pub encrypt: bool,
+
+    /// Require a trusted remote device.
+    pub trusted: bool,
+
+    /// Close the connection if the link is lost instead of reconnecting silently.
+    pub reliable: bool,
+
+    /// Require a 16-digit PIN or Simple Secure Pairing.
+    pub secure: bool,
+}
+
+/// The socket type of an L2CAP connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum L2capMode {
+    /// Packets are delivered as sent, like the L2CAP frames themselves.
+    SeqPacket,
+
+    /// A byte stream without packet boundaries.
+    Stream,
+}
+
 impl BtDevice {
     /// Create a new `BtDevice` manually from a name and addr.
     pub fn new(name: String, addr: BtAddr) -> BtDevice {
         BtDevice {
-            name: name,
+            name: Some(name),
             addr: addr,
+            class: None,
+            page_scan_repetition_mode: None,
+            clock_offset: None,
+            rssi: None,
+            eir: None,
         }
     }
 }
@@ -329,12 +1953,159 @@ mod tests {
         assert!(addr_string.eq_ignore_ascii_case(&BtAddr::from_str(addr_string).unwrap().to_string()));
     }
 
+    #[test]
+    fn btuuid_expands_to_uuid128() {
+        assert_eq!(BtUuid::serial_port().to_string(), "00001101-0000-1000-8000-00805F9B34FB");
+        assert_eq!(BtUuid::Uuid32(0x12345678).to_string(), "12345678-0000-1000-8000-00805F9B34FB");
+        assert_eq!(BtUuid::Uuid128([0xFF; 16]).to_uuid128(), [0xFF; 16]);
+    }
+
+    #[test]
+    fn btuuid_roundtrips_to_from_str() {
+        let uuid_string = "8ce255c0-200a-11e0-ac64-0800200c9a66";
+        let uuid = BtUuid::from_str(uuid_string).unwrap();
+        assert_eq!(uuid,
+                   BtUuid::Uuid128([0x8c, 0xe2, 0x55, 0xc0, 0x20, 0x0a, 0x11, 0xe0, 0xac, 0x64, 0x08, 0x00, 0x20, 0x0c, 0x9a, 0x66]));
+        assert!(uuid_string.eq_ignore_ascii_case(&uuid.to_string()));
+
+        let fail_strings = ["", "8ce255c0-200a-11e0-ac64", "8ce255c0-200a-11e0-ac64-0800200c9a66-00", "8ce255c0200a-11e0-ac64-0800200c9a66",
+                            "8ce255c0-200a-11e0-ac64-0800200c9aXX"];
+        for &s in &fail_strings {
+            assert!(BtUuid::from_str(s).is_err(), "Somehow managed to parse \"{}\" as an UUID?!", s);
+        }
+    }
+
+    #[test]
+    fn scan_options_round_inquiry_length() {
+        assert_eq!(ScanOptions::new().inquiry_length, 1);
+        assert_eq!(ScanOptions::new().inquiry_length(Duration::from_millis(0)).inquiry_length, 1);
+        assert_eq!(ScanOptions::new().inquiry_length(Duration::from_millis(1281)).inquiry_length, 2);
+        assert_eq!(ScanOptions::new().inquiry_length(Duration::from_secs(10)).inquiry_length, 8);
+        assert_eq!(ScanOptions::new().inquiry_length(Duration::from_secs(3600)).inquiry_length, 48);
+    }
+
+    #[test]
+    fn classifies_errors() {
+        let error = BtError::Kind(BtErrorKind::ConnectionRefused, Some(::libc::ECONNREFUSED as u32),
+                                  "Failed to connect() to target device: Connection refused".to_string());
+        assert_eq!(error.kind(), BtErrorKind::ConnectionRefused);
+        assert_eq!(error.errno(), Some(::libc::ECONNREFUSED as u32));
+        assert_eq!(error.to_string(), "Failed to connect() to target device: Connection refused");
+
+        // The error code alone does not tell the kind, it depends on the failed operation
+        assert_eq!(BtError::Errno(::libc::EINVAL as u32, String::new()).kind(), BtErrorKind::Other);
+        assert_eq!(BtError::Errno(::libc::ENOPROTOOPT as u32, String::new()).kind(), BtErrorKind::Other);
+        assert_eq!(BtError::Desc("SDP response is empty".to_string()).kind(), BtErrorKind::Other);
+
+        let error = "00:11:22:33:44".parse::<BtAddr>().unwrap_err();
+        assert_eq!(error.kind(), BtErrorKind::InvalidAddress);
+        assert_eq!(error.errno(), None);
+    }
+
+    #[test]
+    fn converts_errors_to_io_errors() {
+        use std::io::ErrorKind;
+
+        let error = std::io::Error::from(BtError::Kind(BtErrorKind::Timeout, Some(::libc::ETIMEDOUT as u32),
+                                                       "connect() timed out".to_string()));
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(error.to_string(), "connect() timed out");
+
+        let fixtures = [(BtErrorKind::AdapterNotFound, ErrorKind::NotFound),
+                        (BtErrorKind::PermissionDenied, ErrorKind::PermissionDenied),
+                        (BtErrorKind::HostDown, ErrorKind::HostUnreachable),
+                        (BtErrorKind::ConnectionRefused, ErrorKind::ConnectionRefused),
+                        (BtErrorKind::SdpProtocol(Some(0x0003)), ErrorKind::InvalidData),
+                        (BtErrorKind::ServiceNotFound, ErrorKind::NotFound),
+                        (BtErrorKind::InvalidAddress, ErrorKind::InvalidInput)];
+        for &(kind, io_kind) in &fixtures {
+            assert_eq!(std::io::Error::from(BtError::Kind(kind, None, String::new())).kind(), io_kind);
+        }
+
+        // Errors of no known kind convert through their error code
+        assert_eq!(std::io::Error::from(BtError::Errno(::libc::EAGAIN as u32, String::new())).kind(), ErrorKind::WouldBlock);
+        assert_eq!(std::io::Error::from(BtError::Desc(String::new())).kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn decodes_device_class() {
+        // Smartphone offering object transfer, audio and telephony
+        let class = DeviceClass::from_bytes([0x0C, 0x02, 0x7A]);
+        assert_eq!(class, DeviceClass(0x7A020C));
+        assert_eq!(class.major(), MajorDeviceClass::Phone);
+        assert_eq!(class.minor(), 3);
+        assert!(class.has_service(ServiceClass::Audio));
+        assert!(!class.has_service(ServiceClass::Positioning));
+        assert_eq!(class.services(),
+                   vec![ServiceClass::Networking, ServiceClass::Capturing, ServiceClass::ObjectTransfer, ServiceClass::Audio,
+                        ServiceClass::Telephony]);
+
+        // Typical serial adapter
+        let class = DeviceClass::from_bytes([0x00, 0x1F, 0x00]);
+        assert_eq!(class.major(), MajorDeviceClass::Uncategorized);
+        assert_eq!(class.services(), vec![]);
+    }
+
     #[cfg(not(feature = "test_without_hardware"))]
     #[test()]
     fn creates_rfcomm_socket() {
         BtSocket::new(BtProtocol::RFCOMM).unwrap();
     }
 
+    #[cfg(not(feature = "test_without_hardware"))]
+    #[test]
+    fn creates_rfcomm_listener() {
+        let listener = BtListener::new(BtProtocol::RFCOMM).unwrap();
+        listener.bind(BtAddr::any(), 0).unwrap();
+        listener.listen(1).unwrap();
+        assert!(listener.try_accept().unwrap().is_none());
+    }
+
+    #[cfg(not(feature = "test_without_hardware"))]
+    #[test]
+    fn creates_l2cap_listener() {
+        let listener = BtListener::new(BtProtocol::L2CAP(L2capMode::SeqPacket)).unwrap();
+        listener.bind_psm(BtAddr::any(), 0x1001).unwrap();
+        listener.set_input_mtu(1024).unwrap();
+        assert_eq!(listener.mtu().unwrap().0, 1024);
+        listener.listen(1).unwrap();
+        assert!(listener.try_accept().unwrap().is_none());
+    }
+
+    #[cfg(not(feature = "test_without_hardware"))]
+    #[test]
+    fn reports_socket_addresses() {
+        let listener = BtListener::new(BtProtocol::L2CAP(L2capMode::SeqPacket)).unwrap();
+        listener.bind_psm(BtAddr::any(), 0x1003).unwrap();
+        assert_eq!(listener.local_addr().unwrap().channel, 0x1003);
+
+        let socket = BtSocket::new(BtProtocol::L2CAP(L2capMode::SeqPacket)).unwrap();
+        assert!(socket.peer_addr().is_err());
+
+        // The protocol survives the round trip through a raw file descriptor
+        let socket = unsafe { BtSocket::from_raw_fd(socket.into_raw_fd()) };
+        assert_eq!(socket.local_addr().unwrap(), BtSocketAddr { addr: BtAddr::any(), channel: 0 });
+        assert!(socket.mtu().is_ok());
+    }
+
+    #[cfg(not(feature = "test_without_hardware"))]
+    #[test]
+    fn applies_socket_options() {
+        let mode = LinkMode { auth: true, encrypt: true, ..LinkMode::default() };
+        let socket = BtSocketBuilder::new(BtProtocol::RFCOMM)
+            .link_mode(mode)
+            .security(SecurityLevel::High)
+            .linger(Some(Duration::from_secs(2)))
+            .build()
+            .unwrap();
+        assert_eq!(socket.security().unwrap(), SecurityLevel::High);
+        assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(2)));
+
+        let listener = BtListener::new(BtProtocol::RFCOMM).unwrap();
+        listener.set_defer_setup(true).unwrap();
+        assert!(listener.defer_setup().unwrap());
+    }
+
     #[cfg(not(feature = "test_without_hardware"))]
     #[test()]
     fn scans_devices() {
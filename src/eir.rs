@@ -0,0 +1,264 @@
+use bluetooth::{BtError, BtUuid, DeviceClass};
+
+/// Data types of the AD structures evaluated by this library.
+mod data_type {
+    pub const FLAGS: u8 = 0x01;
+    pub const INCOMPLETE_UUID16_LIST: u8 = 0x02;
+    pub const COMPLETE_UUID16_LIST: u8 = 0x03;
+    pub const INCOMPLETE_UUID32_LIST: u8 = 0x04;
+    pub const COMPLETE_UUID32_LIST: u8 = 0x05;
+    pub const INCOMPLETE_UUID128_LIST: u8 = 0x06;
+    pub const COMPLETE_UUID128_LIST: u8 = 0x07;
+    pub const SHORTENED_LOCAL_NAME: u8 = 0x08;
+    pub const COMPLETE_LOCAL_NAME: u8 = 0x09;
+    pub const TX_POWER_LEVEL: u8 = 0x0A;
+    pub const CLASS_OF_DEVICE: u8 = 0x0D;
+    pub const MANUFACTURER_SPECIFIC_DATA: u8 = 0xFF;
+}
+
+/// A single AD structure of an extended inquiry response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EirStructure {
+    /// Discoverability and capability flags.
+    Flags(u8),
+
+    /// Service class UUIDs, `true` if the device lists all its services.
+    ServiceClasses(Vec<BtUuid>, bool),
+
+    /// The name of the device, `true` if the name is complete and not shortened.
+    LocalName(String, bool),
+
+    /// The transmit power level in dBm.
+    TxPowerLevel(i8),
+
+    /// The class of device.
+    ClassOfDevice(DeviceClass),
+
+    /// Manufacturer specific data, preceded by the company identifier.
+    ManufacturerData(u16, Vec<u8>),
+
+    /// A structure of a data type not evaluated by this library.
+    Unknown(u8, Vec<u8>),
+}
+
+impl EirStructure {
+    /// Splits an extended inquiry response into its AD structures.
+    ///
+    /// Parsing stops at the first structure of length zero, which marks the start of the padding.
+    pub fn parse_all(mut bytes: &[u8]) -> Result<Vec<EirStructure>, BtError> {
+        let mut structures = Vec::new();
+        while !bytes.is_empty() && bytes[0] != 0 {
+            let length = bytes[0] as usize;
+            if bytes.len() < 1 + length {
+                return Err(BtError::Desc("EIR structure exceeds data".to_string()));
+            }
+            structures.push(EirStructure::parse(bytes[1], &bytes[2..1 + length])?);
+            bytes = &bytes[1 + length..];
+        }
+        Ok(structures)
+    }
+
+    /// Parses the data of a single AD structure with the given data type.
+    pub fn parse(data_type: u8, data: &[u8]) -> Result<EirStructure, BtError> {
+        let invalid = || Err(BtError::Desc(format!("Invalid EIR structure of type 0x{:02X}", data_type)));
+
+        match data_type {
+            data_type::FLAGS => {
+                if data.is_empty() {
+                    return invalid();
+                }
+                Ok(EirStructure::Flags(data[0]))
+            }
+
+            data_type::INCOMPLETE_UUID16_LIST | data_type::COMPLETE_UUID16_LIST => {
+                if !data.len().is_multiple_of(2) {
+                    return invalid();
+                }
+                let uuids = data.chunks(2).map(|uuid| BtUuid::Uuid16(uuid[0] as u16 | (uuid[1] as u16) << 8)).collect();
+                Ok(EirStructure::ServiceClasses(uuids, data_type == data_type::COMPLETE_UUID16_LIST))
+            }
+
+            data_type::INCOMPLETE_UUID32_LIST | data_type::COMPLETE_UUID32_LIST => {
+                if !data.len().is_multiple_of(4) {
+                    return invalid();
+                }
+                let uuids = data.chunks(4)
+                    .map(|uuid| BtUuid::Uuid32(uuid[0] as u32 | (uuid[1] as u32) << 8 | (uuid[2] as u32) << 16 | (uuid[3] as u32) << 24))
+                    .collect();
+                Ok(EirStructure::ServiceClasses(uuids, data_type == data_type::COMPLETE_UUID32_LIST))
+            }
+
+            data_type::INCOMPLETE_UUID128_LIST | data_type::COMPLETE_UUID128_LIST => {
+                if !data.len().is_multiple_of(16) {
+                    return invalid();
+                }
+                let uuids = data.chunks(16)
+                    .map(|uuid| {
+                        // Transmitted in little-endian byte order
+                        let mut bytes = [0u8; 16];
+                        bytes.copy_from_slice(uuid);
+                        bytes.reverse();
+                        BtUuid::Uuid128(bytes)
+                    })
+                    .collect();
+                Ok(EirStructure::ServiceClasses(uuids, data_type == data_type::COMPLETE_UUID128_LIST))
+            }
+
+            data_type::SHORTENED_LOCAL_NAME | data_type::COMPLETE_LOCAL_NAME => {
+                let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+                Ok(EirStructure::LocalName(String::from_utf8_lossy(&data[..end]).into_owned(),
+                                           data_type == data_type::COMPLETE_LOCAL_NAME))
+            }
+
+            data_type::TX_POWER_LEVEL => {
+                if data.len() != 1 {
+                    return invalid();
+                }
+                Ok(EirStructure::TxPowerLevel(data[0] as i8))
+            }
+
+            data_type::CLASS_OF_DEVICE => {
+                if data.len() != 3 {
+                    return invalid();
+                }
+                Ok(EirStructure::ClassOfDevice(DeviceClass::from_bytes([data[0], data[1], data[2]])))
+            }
+
+            data_type::MANUFACTURER_SPECIFIC_DATA => {
+                if data.len() < 2 {
+                    return invalid();
+                }
+                Ok(EirStructure::ManufacturerData(data[0] as u16 | (data[1] as u16) << 8, data[2..].to_vec()))
+            }
+
+            _ => Ok(EirStructure::Unknown(data_type, data.to_vec())),
+        }
+    }
+}
+
+/// The information a device published in its extended inquiry response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EirData {
+    /// Discoverability and capability flags.
+    pub flags: Option<u8>,
+
+    /// The name of the device, possibly shortened.
+    pub name: Option<String>,
+
+    /// True if `name` is the complete name of the device.
+    pub name_complete: bool,
+
+    /// The service classes the device announced.
+    pub service_classes: Vec<BtUuid>,
+
+    /// True if `service_classes` contains all services of the device.
+    pub service_classes_complete: bool,
+
+    /// The transmit power level in dBm.
+    pub tx_power_level: Option<i8>,
+
+    /// The class of device.
+    pub class: Option<DeviceClass>,
+
+    /// Manufacturer specific data as pairs of company identifier and data.
+    pub manufacturer_data: Vec<(u16, Vec<u8>)>,
+}
+
+impl EirData {
+    /// Parses an extended inquiry response.
+    pub fn parse(bytes: &[u8]) -> Result<EirData, BtError> {
+        let mut eir = EirData::default();
+        let mut all_service_lists_complete = true;
+        for structure in EirStructure::parse_all(bytes)? {
+            match structure {
+                EirStructure::Flags(flags) => eir.flags = Some(flags),
+                EirStructure::ServiceClasses(uuids, complete) => {
+                    eir.service_classes.extend(uuids);
+                    all_service_lists_complete = all_service_lists_complete && complete;
+                    eir.service_classes_complete = all_service_lists_complete;
+                }
+                EirStructure::LocalName(name, complete) => {
+                    // Prefer the complete name if a device sends both
+                    if complete || eir.name.is_none() {
+                        eir.name = Some(name);
+                        eir.name_complete = complete;
+                    }
+                }
+                EirStructure::TxPowerLevel(level) => eir.tx_power_level = Some(level),
+                EirStructure::ClassOfDevice(class) => eir.class = Some(class),
+                EirStructure::ManufacturerData(company, data) => eir.manufacturer_data.push((company, data)),
+                EirStructure::Unknown(..) => {}
+            }
+        }
+        Ok(eir)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_eir_structures() {
+        let fixtures: Vec<(&[u8], EirStructure)> =
+            vec![(&[0x02, 0x01, 0x06], EirStructure::Flags(0x06)),
+                 (&[0x05, 0x03, 0x01, 0x11, 0x05, 0x11],
+                  EirStructure::ServiceClasses(vec![BtUuid::Uuid16(0x1101), BtUuid::Uuid16(0x1105)], true)),
+                 (&[0x05, 0x04, 0x78, 0x56, 0x34, 0x12], EirStructure::ServiceClasses(vec![BtUuid::Uuid32(0x12345678)], false)),
+                 (&[0x05, 0x08, b'P', b'h', b'o', b'n'], EirStructure::LocalName("Phon".to_string(), false)),
+                 (&[0x02, 0x0A, 0xF8], EirStructure::TxPowerLevel(-8)),
+                 (&[0x04, 0x0D, 0x0C, 0x02, 0x5A], EirStructure::ClassOfDevice(DeviceClass(0x5A020C))),
+                 (&[0x05, 0xFF, 0x4C, 0x00, 0x01, 0x02], EirStructure::ManufacturerData(0x004C, vec![0x01, 0x02])),
+                 (&[0x03, 0x16, 0xAA, 0xBB], EirStructure::Unknown(0x16, vec![0xAA, 0xBB]))];
+
+        for (bytes, structure) in fixtures {
+            assert_eq!(EirStructure::parse_all(bytes).unwrap(), vec![structure]);
+        }
+    }
+
+    #[test]
+    fn parses_uuid128_lists() {
+        let mut bytes = vec![0x11, 0x07];
+        let mut uuid = BtUuid::serial_port().to_uuid128();
+        uuid.reverse();
+        bytes.extend_from_slice(&uuid);
+
+        assert_eq!(EirStructure::parse_all(&bytes).unwrap(),
+                   vec![EirStructure::ServiceClasses(vec![BtUuid::Uuid128(BtUuid::serial_port().to_uuid128())], true)]);
+    }
+
+    #[test]
+    fn parses_padded_eir_data() {
+        let mut bytes = vec![0x02, 0x01, 0x1A,
+                             0x04, 0x08, b'M', b'y', b' ',
+                             0x09, 0x09, b'M', b'y', b' ', b'P', b'h', b'o', b'n', b'e',
+                             0x03, 0x02, 0x0A, 0x11,
+                             0x03, 0x03, 0x01, 0x11,
+                             0x02, 0x0A, 0x04];
+        bytes.resize(240, 0);
+
+        let eir = EirData::parse(&bytes).unwrap();
+        assert_eq!(eir.flags, Some(0x1A));
+        assert_eq!(eir.name, Some("My Phone".to_string()));
+        assert!(eir.name_complete);
+        assert_eq!(eir.service_classes, vec![BtUuid::Uuid16(0x110A), BtUuid::Uuid16(0x1101)]);
+        assert!(!eir.service_classes_complete);
+        assert_eq!(eir.tx_power_level, Some(4));
+        assert_eq!(eir.class, None);
+        assert!(eir.manufacturer_data.is_empty());
+
+        assert_eq!(EirData::parse(&[0; 240]).unwrap(), EirData::default());
+    }
+
+    #[test]
+    fn rejects_malformed_eir_data() {
+        // Structure longer than the data
+        assert!(EirStructure::parse_all(&[0x05, 0x09, b'a']).is_err());
+        // Odd length of a 16 bit UUID list
+        assert!(EirStructure::parse_all(&[0x04, 0x03, 0x01, 0x11, 0x05]).is_err());
+        assert!(EirStructure::parse_all(&[0x01, 0x0A]).is_err());
+        assert!(EirStructure::parse_all(&[0x02, 0x0D, 0x0C]).is_err());
+        assert!(EirStructure::parse_all(&[0x02, 0xFF, 0x4C]).is_err());
+    }
+}
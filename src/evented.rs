@@ -0,0 +1,90 @@
+//! `mio::Evented` implementations for event loops still on mio 0.6, enabled by the `mio06` feature.
+//!
+//! The state machines register the file descriptor their last `BtAsync::WaitFor` asked to wait
+//! for. Its `Interest` converts to the matching `Ready` with `mio06_ready`.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use mio;
+use mio06::{Evented, Poll, PollOpt, Ready, Token};
+use mio06::unix::EventedFd;
+
+use bluetooth::{Advance, BtDiscovery, BtListener, BtReadRemoteName, BtServiceBrowse, BtSocket, BtSocketConnect,
+                HciSocket};
+
+/// Converts the interest of a `BtAsync::WaitFor` to the readiness of mio 0.6.
+pub fn mio06_ready(interest: mio::Interest) -> Ready {
+    let mut ready = Ready::empty();
+    if interest.is_readable() {
+        ready.insert(Ready::readable());
+    }
+    if interest.is_writable() {
+        ready.insert(Ready::writable());
+    }
+    ready
+}
+
+/// Implements `Evented` for `$ty` by registering the file descriptor returned by `$fd`.
+macro_rules! evented_fd {
+    (<$($lt:lifetime),*> $ty:ty, $fd:ident) => (
+        impl<$($lt),*> Evented for $ty {
+            fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+                EventedFd(&self.$fd()).register(poll, token, interest, opts)
+            }
+
+            fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+                EventedFd(&self.$fd()).reregister(poll, token, interest, opts)
+            }
+
+            fn deregister(&self, poll: &Poll) -> io::Result<()> {
+                EventedFd(&self.$fd()).deregister(poll)
+            }
+        }
+    );
+    ($ty:ty, $fd:ident) => (evented_fd!(<> $ty, $fd););
+}
+
+evented_fd!(BtSocket, as_raw_fd);
+evented_fd!(BtListener, as_raw_fd);
+evented_fd!(BtDiscovery, as_raw_fd);
+evented_fd!(HciSocket, as_raw_fd);
+evented_fd!(<'a> BtSocketConnect<'a>, pollfd);
+evented_fd!(BtServiceBrowse, pollfd);
+evented_fd!(BtReadRemoteName, pollfd);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+    use mio06::Events;
+
+    #[test]
+    fn converts_interest_to_ready() {
+        assert_eq!(mio06_ready(mio::Interest::READABLE), Ready::readable());
+        assert_eq!(mio06_ready(mio::Interest::WRITABLE), Ready::writable());
+        assert_eq!(mio06_ready(mio::Interest::READABLE | mio::Interest::WRITABLE),
+                   Ready::readable() | Ready::writable());
+    }
+
+    #[test]
+    fn registers_sockets_with_mio06() {
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+        let socket = unsafe { BtSocket::from_raw_fd(socket.into_raw_fd()) };
+        let poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(2);
+        poll.register(&socket, Token(3), Ready::readable(), PollOpt::level()).unwrap();
+
+        poll.poll(&mut events, Some(Duration::from_secs(0))).unwrap();
+        assert!(events.is_empty());
+
+        peer.write_all(b"x").unwrap();
+        poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+        let event = events.iter().next().unwrap();
+        assert_eq!(event.token(), Token(3));
+        assert!(event.readiness().is_readable());
+    }
+}
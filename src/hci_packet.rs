@@ -0,0 +1,682 @@
+use bluetooth::{BtAddr, BtDevice, BtError, DeviceClass, InquiryAccessCode, InquiryMode};
+use eir::EirData;
+
+/// Longest name an adapter or remote device can have, in bytes.
+const HCI_MAX_NAME_LENGTH: usize = 248;
+
+/// Returns the address in the little-endian byte order used by HCI.
+fn addr_to_hci(addr: BtAddr) -> [u8; 6] {
+    let mut bytes = addr.0;
+    bytes.reverse();
+    bytes
+}
+
+/// Reads an address in the little-endian byte order used by HCI.
+fn addr_from_hci(bytes: &[u8]) -> BtAddr {
+    let mut addr = BtAddr::any();
+    addr.0.copy_from_slice(&bytes[..6]);
+    addr.0.reverse();
+    addr
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+/// Reads a name padded with NUL bytes.
+fn read_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Creates a device from the fields shared by all inquiry result formats.
+fn inquiry_device(addr: &[u8], pscan_rep_mode: u8, class: &[u8], clock_offset: &[u8], rssi: Option<i8>) -> BtDevice {
+    BtDevice {
+        name: None,
+        addr: addr_from_hci(addr),
+        class: Some(DeviceClass::from_bytes([class[0], class[1], class[2]])),
+        page_scan_repetition_mode: Some(pscan_rep_mode),
+        clock_offset: Some(read_u16(clock_offset)),
+        rssi,
+        eir: None,
+    }
+}
+
+
+/// A command sent to the Bluetooth controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HciCommand {
+    /// Resets the controller.
+    Reset,
+
+    /// Reads the address of the controller.
+    ReadBdAddr,
+
+    /// Reads the HCI and LMP versions of the controller.
+    ReadLocalVersion,
+
+    /// Reads the friendly name of the controller.
+    ReadLocalName,
+
+    /// Sets the friendly name of the controller, at most 248 bytes of UTF-8.
+    WriteLocalName(String),
+
+    /// Sets whether the controller answers inquiries and accepts incoming connections.
+    WriteScanEnable {
+        /// Answer inquiries.
+        discoverable: bool,
+
+        /// Accept incoming connections.
+        connectable: bool,
+    },
+
+    /// Sets the class of device of the controller.
+    WriteClassOfDevice(DeviceClass),
+
+    /// Sets the format of inquiry results.
+    WriteInquiryMode(InquiryMode),
+
+    /// Starts an inquiry.
+    Inquiry {
+        /// The devices which should respond.
+        access_code: InquiryAccessCode,
+
+        /// The length of the inquiry in units of 1.28 seconds, 1 to 48.
+        length: u8,
+
+        /// The number of responses after which the inquiry ends, 0 for unlimited.
+        max_responses: u8,
+    },
+
+    /// Cancels a running inquiry.
+    InquiryCancel,
+
+    /// Requests the name of a remote device.
+    RemoteNameRequest {
+        /// The address of the remote device.
+        addr: BtAddr,
+
+        /// The page scan repetition mode reported by the inquiry, R1 (1) if unknown.
+        page_scan_repetition_mode: u8,
+
+        /// The clock offset reported by the inquiry.
+        clock_offset: Option<u16>,
+    },
+
+    /// Cancels a running remote name request.
+    RemoteNameRequestCancel(BtAddr),
+
+    /// Any other command, given by its opcode and parameters.
+    Other(u16, Vec<u8>),
+}
+
+impl HciCommand {
+    /// Packet indicator of command packets.
+    pub const PACKET_TYPE: u8 = 0x01;
+
+    /// Opcode of `HciCommand::Inquiry`.
+    pub const INQUIRY: u16 = 0x0401;
+    /// Opcode of `HciCommand::InquiryCancel`.
+    pub const INQUIRY_CANCEL: u16 = 0x0402;
+    /// Opcode of `HciCommand::RemoteNameRequest`.
+    pub const REMOTE_NAME_REQUEST: u16 = 0x0419;
+    /// Opcode of `HciCommand::RemoteNameRequestCancel`.
+    pub const REMOTE_NAME_REQUEST_CANCEL: u16 = 0x041A;
+    /// Opcode of `HciCommand::Reset`.
+    pub const RESET: u16 = 0x0C03;
+    /// Opcode of `HciCommand::WriteLocalName`.
+    pub const WRITE_LOCAL_NAME: u16 = 0x0C13;
+    /// Opcode of `HciCommand::ReadLocalName`.
+    pub const READ_LOCAL_NAME: u16 = 0x0C14;
+    /// Opcode of `HciCommand::WriteScanEnable`.
+    pub const WRITE_SCAN_ENABLE: u16 = 0x0C1A;
+    /// Opcode of `HciCommand::WriteClassOfDevice`.
+    pub const WRITE_CLASS_OF_DEVICE: u16 = 0x0C24;
+    /// Opcode of `HciCommand::WriteInquiryMode`.
+    pub const WRITE_INQUIRY_MODE: u16 = 0x0C45;
+    /// Opcode of `HciCommand::ReadLocalVersion`.
+    pub const READ_LOCAL_VERSION: u16 = 0x1001;
+    /// Opcode of `HciCommand::ReadBdAddr`.
+    pub const READ_BD_ADDR: u16 = 0x1009;
+
+    /// Returns the opcode of the command.
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            HciCommand::Reset => HciCommand::RESET,
+            HciCommand::ReadBdAddr => HciCommand::READ_BD_ADDR,
+            HciCommand::ReadLocalVersion => HciCommand::READ_LOCAL_VERSION,
+            HciCommand::ReadLocalName => HciCommand::READ_LOCAL_NAME,
+            HciCommand::WriteLocalName(_) => HciCommand::WRITE_LOCAL_NAME,
+            HciCommand::WriteScanEnable { .. } => HciCommand::WRITE_SCAN_ENABLE,
+            HciCommand::WriteClassOfDevice(_) => HciCommand::WRITE_CLASS_OF_DEVICE,
+            HciCommand::WriteInquiryMode(_) => HciCommand::WRITE_INQUIRY_MODE,
+            HciCommand::Inquiry { .. } => HciCommand::INQUIRY,
+            HciCommand::InquiryCancel => HciCommand::INQUIRY_CANCEL,
+            HciCommand::RemoteNameRequest { .. } => HciCommand::REMOTE_NAME_REQUEST,
+            HciCommand::RemoteNameRequestCancel(_) => HciCommand::REMOTE_NAME_REQUEST_CANCEL,
+            HciCommand::Other(opcode, _) => opcode,
+        }
+    }
+
+    /// Returns the encoded parameters of the command.
+    pub fn parameters(&self) -> Result<Vec<u8>, BtError> {
+        let parameters = match self {
+            &HciCommand::Reset |
+            &HciCommand::ReadBdAddr |
+            &HciCommand::ReadLocalVersion |
+            &HciCommand::ReadLocalName |
+            &HciCommand::InquiryCancel => vec![],
+
+            HciCommand::WriteLocalName(name) => {
+                if name.len() > HCI_MAX_NAME_LENGTH {
+                    return Err(BtError::Desc(format!("Adapter name exceeds {} bytes", HCI_MAX_NAME_LENGTH)));
+                }
+                let mut parameters = name.as_bytes().to_vec();
+                parameters.resize(HCI_MAX_NAME_LENGTH, 0);
+                parameters
+            }
+
+            &HciCommand::WriteScanEnable { discoverable, connectable } => {
+                let mut scan_enable = 0;
+                if discoverable {
+                    scan_enable |= 0x01;
+                }
+                if connectable {
+                    scan_enable |= 0x02;
+                }
+                vec![scan_enable]
+            }
+
+            &HciCommand::WriteClassOfDevice(class) => vec![class.0 as u8, (class.0 >> 8) as u8, (class.0 >> 16) as u8],
+
+            &HciCommand::WriteInquiryMode(mode) => vec![mode as u8],
+
+            &HciCommand::Inquiry { access_code, length, max_responses } => {
+                let mut parameters = access_code.lap().to_vec();
+                parameters.push(length);
+                parameters.push(max_responses);
+                parameters
+            }
+
+            &HciCommand::RemoteNameRequest { addr, page_scan_repetition_mode, clock_offset } => {
+                let mut parameters = addr_to_hci(addr).to_vec();
+                parameters.push(page_scan_repetition_mode);
+                parameters.push(0);
+                // Bit 15 marks the clock offset as valid
+                let clock_offset = clock_offset.map(|offset| offset | 0x8000).unwrap_or(0);
+                parameters.push(clock_offset as u8);
+                parameters.push((clock_offset >> 8) as u8);
+                parameters
+            }
+
+            &HciCommand::RemoteNameRequestCancel(addr) => addr_to_hci(addr).to_vec(),
+
+            HciCommand::Other(_, parameters) => {
+                if parameters.len() > 255 {
+                    return Err(BtError::Desc("HCI command parameters exceed 255 bytes".to_string()));
+                }
+                parameters.clone()
+            }
+        };
+        Ok(parameters)
+    }
+
+    /// Encodes the command as a packet for an HCI socket, including the packet indicator.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BtError> {
+        let parameters = self.parameters()?;
+        let opcode = self.opcode();
+        let mut packet = vec![HciCommand::PACKET_TYPE, opcode as u8, (opcode >> 8) as u8, parameters.len() as u8];
+        packet.extend_from_slice(&parameters);
+        Ok(packet)
+    }
+}
+
+
+/// The versions reported by `HciCommand::ReadLocalVersion`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LocalVersion {
+    /// The supported HCI version.
+    pub hci_version: u8,
+
+    /// The HCI revision, specific to the manufacturer.
+    pub hci_revision: u16,
+
+    /// The supported LMP version.
+    pub lmp_version: u8,
+
+    /// The company identifier of the manufacturer.
+    pub manufacturer: u16,
+
+    /// The LMP subversion, specific to the manufacturer.
+    pub lmp_subversion: u16,
+}
+
+/// The return parameters of a successfully completed command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandResult {
+    /// The command returns nothing beyond its status.
+    Empty,
+
+    /// The address of the controller.
+    BdAddr(BtAddr),
+
+    /// The versions of the controller.
+    LocalVersion(LocalVersion),
+
+    /// The friendly name of the controller.
+    LocalName(String),
+
+    /// The return parameters of a command not evaluated by this library.
+    Unknown(Vec<u8>),
+}
+
+impl CommandResult {
+    /// Parses the return parameters following the status of the command `opcode`.
+    fn parse(opcode: u16, parameters: &[u8]) -> Result<CommandResult, BtError> {
+        let invalid = || Err(BtError::Desc(format!("Malformed return parameters of HCI command 0x{:04X}", opcode)));
+
+        match opcode {
+            HciCommand::READ_BD_ADDR => {
+                if parameters.len() < 6 {
+                    return invalid();
+                }
+                Ok(CommandResult::BdAddr(addr_from_hci(parameters)))
+            }
+
+            HciCommand::READ_LOCAL_VERSION => {
+                if parameters.len() < 8 {
+                    return invalid();
+                }
+                Ok(CommandResult::LocalVersion(LocalVersion {
+                    hci_version: parameters[0],
+                    hci_revision: read_u16(&parameters[1..]),
+                    lmp_version: parameters[3],
+                    manufacturer: read_u16(&parameters[4..]),
+                    lmp_subversion: read_u16(&parameters[6..]),
+                }))
+            }
+
+            HciCommand::READ_LOCAL_NAME => Ok(CommandResult::LocalName(read_name(parameters))),
+
+            _ if parameters.is_empty() => Ok(CommandResult::Empty),
+
+            _ => Ok(CommandResult::Unknown(parameters.to_vec())),
+        }
+    }
+}
+
+
+/// An event received from the Bluetooth controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HciEvent {
+    /// An inquiry has finished.
+    InquiryComplete {
+        /// Zero on success, an HCI error code otherwise.
+        status: u8,
+    },
+
+    /// Devices responded to an inquiry.
+    InquiryResult(Vec<BtDevice>),
+
+    /// Devices responded to an inquiry, including the received signal strength.
+    InquiryResultWithRssi(Vec<BtDevice>),
+
+    /// A device responded to an inquiry with its extended inquiry response, which follows as raw
+    /// data and is stored parsed in `BtDevice::eir`.
+    ExtendedInquiryResult(BtDevice, Vec<u8>),
+
+    /// A remote name request has finished.
+    RemoteNameRequestComplete {
+        /// Zero on success, an HCI error code otherwise.
+        status: u8,
+
+        /// The address of the remote device.
+        addr: BtAddr,
+
+        /// The name of the remote device, empty if the request failed.
+        name: String,
+    },
+
+    /// A command has finished.
+    CommandComplete {
+        /// The number of commands the controller accepts now.
+        num_packets: u8,
+
+        /// The opcode of the command.
+        opcode: u16,
+
+        /// Zero on success, an HCI error code otherwise.
+        status: u8,
+
+        /// The return parameters, `CommandResult::Empty` if the command failed.
+        result: CommandResult,
+    },
+
+    /// A command has been accepted or rejected by the controller, its completion is reported by
+    /// another event.
+    CommandStatus {
+        /// Zero if the command is being executed, an HCI error code otherwise.
+        status: u8,
+
+        /// The number of commands the controller accepts now.
+        num_packets: u8,
+
+        /// The opcode of the command.
+        opcode: u16,
+    },
+
+    /// An event not evaluated by this library, given by its event code and parameters.
+    Unknown(u8, Vec<u8>),
+}
+
+impl HciEvent {
+    /// Packet indicator of event packets.
+    pub const PACKET_TYPE: u8 = 0x04;
+
+    /// Event code of `HciEvent::InquiryComplete`.
+    pub const INQUIRY_COMPLETE: u8 = 0x01;
+    /// Event code of `HciEvent::InquiryResult`.
+    pub const INQUIRY_RESULT: u8 = 0x02;
+    /// Event code of `HciEvent::RemoteNameRequestComplete`.
+    pub const REMOTE_NAME_REQUEST_COMPLETE: u8 = 0x07;
+    /// Event code of `HciEvent::CommandComplete`.
+    pub const COMMAND_COMPLETE: u8 = 0x0E;
+    /// Event code of `HciEvent::CommandStatus`.
+    pub const COMMAND_STATUS: u8 = 0x0F;
+    /// Event code of `HciEvent::InquiryResultWithRssi`.
+    pub const INQUIRY_RESULT_WITH_RSSI: u8 = 0x22;
+    /// Event code of `HciEvent::ExtendedInquiryResult`.
+    pub const EXTENDED_INQUIRY_RESULT: u8 = 0x2F;
+
+    /// Parses an event packet read from an HCI socket, including the packet indicator.
+    pub fn parse(packet: &[u8]) -> Result<HciEvent, BtError> {
+        if packet.len() < 3 || packet[0] != HciEvent::PACKET_TYPE {
+            return Err(BtError::Desc("Not an HCI event packet".to_string()));
+        }
+        let params = &packet[3..];
+        if params.len() != packet[2] as usize {
+            return Err(BtError::Desc("Truncated HCI event".to_string()));
+        }
+        let invalid = |name: &str| Err(BtError::Desc(format!("Malformed {} event", name)));
+
+        match packet[1] {
+            HciEvent::INQUIRY_COMPLETE => {
+                if params.is_empty() {
+                    return invalid("inquiry complete");
+                }
+                Ok(HciEvent::InquiryComplete { status: params[0] })
+            }
+
+            HciEvent::INQUIRY_RESULT => {
+                // bdaddr, pscan_rep_mode, pscan_period_mode, pscan_mode, dev_class, clock_offset
+                if params.is_empty() || params.len() != 1 + params[0] as usize * 14 {
+                    return invalid("inquiry result");
+                }
+                let devices = params[1..]
+                    .chunks(14)
+                    .map(|response| inquiry_device(&response[0..6], response[6], &response[9..12], &response[12..14], None))
+                    .collect();
+                Ok(HciEvent::InquiryResult(devices))
+            }
+
+            HciEvent::INQUIRY_RESULT_WITH_RSSI => {
+                // bdaddr, pscan_rep_mode, pscan_period_mode, dev_class, clock_offset, rssi. Some
+                // controllers additionally report pscan_mode after pscan_period_mode.
+                if params.is_empty() {
+                    return invalid("inquiry result with RSSI");
+                }
+                let num_responses = params[0] as usize;
+                let devices = if num_responses > 0 && params.len() == 1 + num_responses * 15 {
+                    params[1..]
+                        .chunks(15)
+                        .map(|response| inquiry_device(&response[0..6], response[6], &response[9..12], &response[12..14], Some(response[14] as i8)))
+                        .collect()
+                } else if params.len() == 1 + num_responses * 14 {
+                    params[1..]
+                        .chunks(14)
+                        .map(|response| inquiry_device(&response[0..6], response[6], &response[8..11], &response[11..13], Some(response[13] as i8)))
+                        .collect()
+                } else {
+                    return invalid("inquiry result with RSSI");
+                };
+                Ok(HciEvent::InquiryResultWithRssi(devices))
+            }
+
+            HciEvent::EXTENDED_INQUIRY_RESULT => {
+                // Always a single response: bdaddr, pscan_rep_mode, pscan_period_mode, dev_class,
+                // clock_offset, rssi, 240 bytes of extended inquiry response data
+                if params.len() < 15 || params[0] != 1 {
+                    return invalid("extended inquiry result");
+                }
+                let response = &params[1..];
+                let mut device = inquiry_device(&response[0..6], response[6], &response[8..11], &response[11..13], Some(response[13] as i8));
+                // A malformed response only loses the extended information, not the device
+                device.eir = EirData::parse(&response[14..]).ok();
+                device.name = device.eir.as_ref().and_then(|eir| eir.name.clone());
+                Ok(HciEvent::ExtendedInquiryResult(device, response[14..].to_vec()))
+            }
+
+            HciEvent::REMOTE_NAME_REQUEST_COMPLETE => {
+                if params.len() < 7 {
+                    return invalid("remote name request complete");
+                }
+                Ok(HciEvent::RemoteNameRequestComplete {
+                    status: params[0],
+                    addr: addr_from_hci(&params[1..7]),
+                    name: read_name(&params[7..]),
+                })
+            }
+
+            HciEvent::COMMAND_COMPLETE => {
+                if params.len() < 3 {
+                    return invalid("command complete");
+                }
+                let opcode = read_u16(&params[1..]);
+                // Status is the first return parameter, the NOP command after a reset has none
+                let status = params.get(3).cloned().unwrap_or(0);
+                let result = if status == 0 && params.len() > 3 {
+                    CommandResult::parse(opcode, &params[4..])?
+                } else {
+                    CommandResult::Empty
+                };
+                Ok(HciEvent::CommandComplete {
+                    num_packets: params[0],
+                    opcode,
+                    status,
+                    result,
+                })
+            }
+
+            HciEvent::COMMAND_STATUS => {
+                if params.len() < 4 {
+                    return invalid("command status");
+                }
+                Ok(HciEvent::CommandStatus {
+                    status: params[0],
+                    num_packets: params[1],
+                    opcode: read_u16(&params[2..]),
+                })
+            }
+
+            code => Ok(HciEvent::Unknown(code, params.to_vec())),
+        }
+    }
+}
+
+
+/// Selects the packets an HCI socket receives.
+///
+/// A new filter passes nothing, add the event codes to receive with `event`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct HciFilter {
+    pub(crate) type_mask: u32,
+    pub(crate) event_mask: [u32; 2],
+    pub(crate) opcode: u16,
+}
+
+impl HciFilter {
+    /// Create a filter which passes nothing.
+    pub fn new() -> HciFilter {
+        HciFilter::default()
+    }
+
+    /// Create a filter which passes all events.
+    pub fn all_events() -> HciFilter {
+        HciFilter {
+            type_mask: 1 << HciEvent::PACKET_TYPE,
+            event_mask: [0xFFFFFFFF; 2],
+            opcode: 0,
+        }
+    }
+
+    /// Pass events with the event code `code`.
+    pub fn event(mut self, code: u8) -> HciFilter {
+        self.type_mask |= 1 << HciEvent::PACKET_TYPE;
+        self.event_mask[(code >> 5) as usize] |= 1 << (code & 0x1F);
+        self
+    }
+
+    /// Only pass Command Complete and Command Status events of the command `opcode`.
+    pub fn opcode(mut self, opcode: u16) -> HciFilter {
+        self.opcode = opcode;
+        self
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_commands() {
+        assert_eq!(HciCommand::Reset.to_bytes().unwrap(), vec![0x01, 0x03, 0x0C, 0x00]);
+        assert_eq!(HciCommand::ReadBdAddr.to_bytes().unwrap(), vec![0x01, 0x09, 0x10, 0x00]);
+        assert_eq!(HciCommand::ReadLocalVersion.to_bytes().unwrap(), vec![0x01, 0x01, 0x10, 0x00]);
+
+        let inquiry = HciCommand::Inquiry {
+            access_code: InquiryAccessCode::General,
+            length: 8,
+            max_responses: 0,
+        };
+        assert_eq!(inquiry.to_bytes().unwrap(),
+                   vec![0x01, 0x01, 0x04, 0x05, 0x33, 0x8B, 0x9E, 0x08, 0x00]);
+
+        let name_request = HciCommand::RemoteNameRequest {
+            addr: BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            page_scan_repetition_mode: 0x01,
+            clock_offset: Some(0x1234),
+        };
+        assert_eq!(name_request.to_bytes().unwrap(),
+                   vec![0x01, 0x19, 0x04, 0x0A, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0x01, 0x00, 0x34, 0x92]);
+
+        let scan_enable = HciCommand::WriteScanEnable {
+            discoverable: true,
+            connectable: true,
+        };
+        assert_eq!(scan_enable.to_bytes().unwrap(), vec![0x01, 0x1A, 0x0C, 0x01, 0x03]);
+        assert_eq!(HciCommand::WriteClassOfDevice(DeviceClass(0x5A020C)).to_bytes().unwrap(),
+                   vec![0x01, 0x24, 0x0C, 0x03, 0x0C, 0x02, 0x5A]);
+        assert_eq!(HciCommand::Other(0xFC01, vec![0xAA]).to_bytes().unwrap(), vec![0x01, 0x01, 0xFC, 0x01, 0xAA]);
+    }
+
+    #[test]
+    fn builds_local_name_parameters() {
+        let parameters = HciCommand::WriteLocalName("Gateway".to_string()).parameters().unwrap();
+        assert_eq!(parameters.len(), HCI_MAX_NAME_LENGTH);
+        assert_eq!(&parameters[..8], b"Gateway\0");
+        assert!(parameters[8..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(HciCommand::WriteLocalName("a".repeat(HCI_MAX_NAME_LENGTH)).parameters().unwrap().len(),
+                   HCI_MAX_NAME_LENGTH);
+        assert!(HciCommand::WriteLocalName("a".repeat(HCI_MAX_NAME_LENGTH + 1)).parameters().is_err());
+    }
+
+    #[test]
+    fn parses_command_events() {
+        let packet = [0x04, 0x0E, 0x0A, 0x01, 0x09, 0x10, 0x00, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00];
+        assert_eq!(HciEvent::parse(&packet).unwrap(),
+                   HciEvent::CommandComplete {
+                       num_packets: 1,
+                       opcode: HciCommand::READ_BD_ADDR,
+                       status: 0,
+                       result: CommandResult::BdAddr(BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])),
+                   });
+
+        let packet = [0x04, 0x0E, 0x0C, 0x01, 0x01, 0x10, 0x00, 0x08, 0x34, 0x12, 0x08, 0x0F, 0x00, 0x78, 0x56];
+        assert_eq!(HciEvent::parse(&packet).unwrap(),
+                   HciEvent::CommandComplete {
+                       num_packets: 1,
+                       opcode: HciCommand::READ_LOCAL_VERSION,
+                       status: 0,
+                       result: CommandResult::LocalVersion(LocalVersion {
+                           hci_version: 8,
+                           hci_revision: 0x1234,
+                           lmp_version: 8,
+                           manufacturer: 0x000F,
+                           lmp_subversion: 0x5678,
+                       }),
+                   });
+
+        let packet = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+        assert_eq!(HciEvent::parse(&packet).unwrap(),
+                   HciEvent::CommandComplete {
+                       num_packets: 1,
+                       opcode: HciCommand::RESET,
+                       status: 0,
+                       result: CommandResult::Empty,
+                   });
+
+        // Failed command: return parameters are not evaluated
+        let packet = [0x04, 0x0E, 0x04, 0x01, 0x09, 0x10, 0x01];
+        match HciEvent::parse(&packet).unwrap() {
+            HciEvent::CommandComplete { status, result, .. } => {
+                assert_eq!(status, 0x01);
+                assert_eq!(result, CommandResult::Empty);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let packet = [0x04, 0x0F, 0x04, 0x00, 0x01, 0x19, 0x04];
+        assert_eq!(HciEvent::parse(&packet).unwrap(),
+                   HciEvent::CommandStatus {
+                       status: 0,
+                       num_packets: 1,
+                       opcode: HciCommand::REMOTE_NAME_REQUEST,
+                   });
+    }
+
+    #[test]
+    fn parses_remote_name_request_complete() {
+        let mut packet = vec![0x04, 0x07, 0xFF, 0x00, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00];
+        packet.extend_from_slice(b"My Phone");
+        packet.resize(3 + 255, 0);
+
+        assert_eq!(HciEvent::parse(&packet).unwrap(),
+                   HciEvent::RemoteNameRequestComplete {
+                       status: 0,
+                       addr: BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+                       name: "My Phone".to_string(),
+                   });
+    }
+
+    #[test]
+    fn rejects_malformed_events() {
+        // Command packet
+        assert!(HciEvent::parse(&[0x01, 0x03, 0x0C, 0x00]).is_err());
+        // Length field exceeds packet
+        assert!(HciEvent::parse(&[0x04, 0x0E, 0x04, 0x01]).is_err());
+        assert!(HciEvent::parse(&[0x04, 0x0F, 0x01, 0x00]).is_err());
+        assert!(HciEvent::parse(&[0x04, 0x0E, 0x05, 0x01, 0x09, 0x10, 0x00, 0x55]).is_err());
+
+        assert_eq!(HciEvent::parse(&[0x04, 0x3E, 0x01, 0x02]).unwrap(), HciEvent::Unknown(0x3E, vec![0x02]));
+    }
+
+    #[test]
+    fn builds_filters() {
+        let filter = HciFilter::new().event(HciEvent::COMMAND_COMPLETE).event(HciEvent::EXTENDED_INQUIRY_RESULT);
+        assert_eq!(filter.type_mask, 1 << 4);
+        assert_eq!(filter.event_mask, [1 << 0x0E, 1 << (0x2F - 32)]);
+        assert_eq!(HciFilter::new().type_mask, 0);
+    }
+}
@@ -1,4 +1,4 @@
-//! Interact with Bluetooth devices via RFCOMM channels.
+//! Interact with Bluetooth devices via RFCOMM channels and L2CAP.
 #![deny(missing_docs,
         missing_debug_implementations, missing_copy_implementations,
         trivial_casts, trivial_numeric_casts,
@@ -10,17 +10,57 @@
 extern crate enum_primitive;
 
 extern crate mio;
+#[cfg(all(feature = "mio06", target_os = "linux"))]
+extern crate mio06;
 extern crate nix;
 extern crate libc;
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+extern crate tokio;
 
 mod bluetooth;
 pub use bluetooth::*;
 
+mod sdp_data;
+pub use sdp_data::SdpValue;
+
+mod eir;
+pub use eir::{EirData, EirStructure};
+
+mod hci_packet;
+pub use hci_packet::{CommandResult, HciCommand, HciEvent, HciFilter, LocalVersion};
+
+#[cfg(target_os = "linux")]
+mod reactor;
+#[cfg(target_os = "linux")]
+pub use reactor::{BtInterest, BtReactor, BtServiceBrowseFuture, BtSocketConnectFuture};
+
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+mod stream;
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+pub use stream::{scan_devices_async, BtScanDevices, BtStream, BtStreamAccept, BtStreamConnect, BtStreamListener};
+#[cfg(all(feature = "tokio", target_os = "linux"))]
+pub use stream::{BtOwnedReadHalf, BtOwnedWriteHalf, BtReadHalf, BtWriteHalf};
+
+#[cfg(all(feature = "mio06", target_os = "linux"))]
+mod evented;
+#[cfg(all(feature = "mio06", target_os = "linux"))]
+pub use evented::mio06_ready;
+
 // ////////////////////////////////////
 // Linux implementation of functions
 #[cfg(target_os = "linux")]
 mod linux;
 
+// Dispatch from the public types to the stack installed for the current thread
+#[cfg(target_os = "linux")]
+mod backend;
+
+// In-memory stack which tests can install in place of the Linux implementation
+#[cfg(all(target_os = "linux", feature = "mock"))]
+mod mock;
+#[cfg(all(target_os = "linux", feature = "mock"))]
+pub use mock::{MockPeer, MockStack};
+
 #[cfg(target_os = "windows")]
 #[allow(unused_variables)] // TODO: remove warnings
 mod windows;
@@ -28,7 +68,7 @@ mod windows;
 mod platform {
 
     #[cfg(target_os = "linux")]
-    pub use linux::*;
+    pub use backend::*;
 
     #[cfg(target_os = "windows")]
     pub use windows::*;
@@ -4,14 +4,24 @@ extern crate mio;
 
 
 use super::ffi::*;
-use super::socket::create_error_from_last;
+use super::socket::{create_error_from_errno, create_error_from_last, create_error_of_kind, AF_BLUETOOTH, BtProtocolBlueZ};
 
-use bluetooth::{BtAddr, BtDevice, BtError};
+use backend::{Discovery, Hci, ReadRemoteName};
+use bluetooth::{Advance, BtAdapter, BtAddr, BtAsync, BtDevice, BtDiscoveryEvent, BtError, BtErrorKind, DeviceClass, InquiryMode, ScanOptions};
+use hci_packet::{CommandResult, HciCommand, HciEvent, HciFilter};
 
 use self::libc::close;
-use std::os::raw::*;
-use std::ffi::CStr;
+use std;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::mem::{self, size_of};
 use std::ptr;
+use std::os::raw::*;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+use mio::{Interest, Poll, Registry};
+use mio::unix::SourceFd;
 
 
 #[repr(C, packed)]
@@ -32,8 +42,65 @@ impl ::std::default::Default for InquiryInfo {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+struct hci_filter {
+    type_mask: uint32_t,
+    event_mask: [uint32_t; 2],
+    opcode: uint16_t,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+struct hci_dev_req {
+    dev_id: uint16_t,
+    dev_opt: uint32_t,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+struct hci_dev_list_req {
+    dev_num: uint16_t,
+    dev_req: [hci_dev_req; HCI_MAX_DEV],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+struct hci_dev_info {
+    dev_id: uint16_t,
+    name: [uint8_t; 8],
+    bdaddr: BtAddr,
+    flags: uint32_t,
+    type_: uint8_t,
+    features: [uint8_t; 8],
+    pkt_type: uint32_t,
+    link_policy: uint32_t,
+    link_mode: uint32_t,
+    acl_mtu: uint16_t,
+    acl_pkts: uint16_t,
+    sco_mtu: uint16_t,
+    sco_pkts: uint16_t,
+    stat: [uint32_t; 10],
+}
+
 const IREQ_CACHE_FLUSH: c_long = 1;
 
+const HCI_MAX_DEV: usize = 16;
+const HCI_UP: u32 = 0;
+
+// _IOW('H', 201, int), _IOW('H', 202, int), _IOR('H', 210, int) and _IOR('H', 211, int)
+const HCIDEVUP: c_ulong = 0x400448C9;
+const HCIDEVDOWN: c_ulong = 0x400448CA;
+const HCIGETDEVLIST: c_ulong = 0x800448D2;
+const HCIGETDEVINFO: c_ulong = 0x800448D3;
+
+const SOL_HCI: c_int = 0;
+const HCI_FILTER: c_int = 2;
+
 // BlueZ funcitons
 #[cfg(target_os = "linux")]
 #[link(name="bluetooth")]
@@ -43,63 +110,870 @@ extern "C" {
 
     // The inquiry last at most for "1.28 * timout" seconds
     fn hci_inquiry(device_id: c_int, timeout: c_int, max_rsp: c_int, lap: *const u8, inquiry_info: *mut *mut InquiryInfo, flags: c_long) -> c_int;
+}
+
+/// Creates an error for a failed privileged operation, naming the missing capability if the
+/// privileges are lacking.
+fn create_privileged_error(message: &str, capability: &str, errno: i32) -> BtError {
+    if errno == libc::EPERM || errno == libc::EACCES {
+        BtError::Kind(BtErrorKind::PermissionDenied,
+                      Some(errno as u32),
+                      format!("{}: Permission denied, requires the {} capability", message, capability))
+    } else {
+        create_adapter_error(message, errno)
+    }
+}
+
+/// Creates an error for a failed operation on a local adapter, which reports `ENODEV` if the
+/// adapter does not exist.
+fn create_adapter_error(message: &str, errno: i32) -> BtError {
+    if errno == libc::ENODEV {
+        create_error_of_kind(BtErrorKind::AdapterNotFound, message, errno)
+    } else {
+        create_error_from_errno(message, errno)
+    }
+}
+
+/// Creates an error for a failed HCI command or procedure, translating the HCI status code to the
+/// error code BlueZ reports for it.
+fn create_hci_status_error(message: &str, status: u8) -> BtError {
+    let message = format!("{} with status 0x{:02X}", message, status);
+    let (errno, kind) = match status {
+        0x01 => (libc::EBADRQC, BtErrorKind::Other),
+        0x02 => (libc::ENOTCONN, BtErrorKind::Other),
+        // Page timeout
+        0x04 => (libc::EHOSTDOWN, BtErrorKind::HostDown),
+        // Authentication failure, PIN or key missing
+        0x05 | 0x06 => (libc::EACCES, BtErrorKind::PermissionDenied),
+        // Connection timeout, LMP response timeout
+        0x08 | 0x22 => (libc::ETIMEDOUT, BtErrorKind::Timeout),
+        0x0C => (libc::EBUSY, BtErrorKind::Other),
+        // Connection rejected due to limited resources, security reasons or unacceptable address
+        0x0D..=0x0F => (libc::ECONNREFUSED, BtErrorKind::ConnectionRefused),
+        0x12 => (libc::EINVAL, BtErrorKind::Other),
+        _ => return BtError::Desc(message),
+    };
+    match kind {
+        BtErrorKind::Other => create_error_from_errno(&message, errno),
+        kind => create_error_of_kind(kind, &message, errno),
+    }
+}
+
+/// Returns the device id of `adapter`, or of the default adapter if `adapter` is `None`.
+fn device_id(adapter: Option<u16>) -> Result<c_int, BtError> {
+    match adapter {
+        Some(id) => Ok(id as c_int),
+        None => {
+            let device_id = unsafe { hci_get_route(ptr::null_mut()) };
+            if device_id < 0 {
+                Err(create_adapter_error("hci_get_route(): No local bluetooth adapter found", nix::errno::errno()))
+            } else {
+                Ok(device_id)
+            }
+        }
+    }
+}
+
+/// Installs `filter` on an HCI socket.
+fn set_filter(socket: &UnixStream, filter: &HciFilter) -> Result<(), BtError> {
+    let filter = hci_filter {
+        type_mask: filter.type_mask,
+        event_mask: filter.event_mask,
+        opcode: filter.opcode.to_le(),
+    };
+    if unsafe { libc::setsockopt(socket.as_raw_fd(), SOL_HCI, HCI_FILTER, ptr::addr_of!(filter).cast(), size_of::<hci_filter>() as u32) } < 0 {
+        return Err(create_error_from_last("setsockopt(): Setting HCI filter failed"));
+    }
+    Ok(())
+}
+
+/// Opens a non-blocking HCI socket to the given adapter which only receives what `filter` passes.
+fn open_hci_socket(adapter: Option<u16>, filter: &HciFilter) -> Result<UnixStream, BtError> {
+    let device_id = device_id(adapter)?;
+
+    let fd = unsafe { hci_open_dev(device_id) };
+    if fd < 0 {
+        return Err(create_adapter_error("hci_open_dev(): Opening local bluetooth adapter failed", nix::errno::errno()));
+    }
+    let socket = unsafe { UnixStream::from_raw_fd(fd) };
+    set_filter(&socket, filter)?;
+
+    socket.set_nonblocking(true).map_err(|error| create_error_from_errno("fcntl()", error.raw_os_error().unwrap_or(0)))?;
+    Ok(socket)
+}
+
+/// Sends an HCI command packet.
+fn send_command(mut socket: &UnixStream, command: &HciCommand) -> Result<(), BtError> {
+    let packet = command.to_bytes()?;
+    match socket.write_all(&packet) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(create_privileged_error("Sending HCI command failed", "CAP_NET_RAW", error.raw_os_error().unwrap_or(0))),
+    }
+}
+
+/// Reads the next packet from an HCI socket and parses it if it is an event.
+///
+/// Returns `None` if no packet is available on a non-blocking socket, and `Some(None)` for
+/// packets which are not events.
+fn read_event(mut socket: &UnixStream) -> Result<Option<Option<HciEvent>>, BtError> {
+    let mut packet = [0u8; 260];
+    let size = match socket.read(&mut packet) {
+        Ok(size) => size,
+        Err(ref error) if error.kind() == ErrorKind::WouldBlock => return Ok(None),
+        Err(error) => return Err(create_error_from_errno("Reading HCI event failed", error.raw_os_error().unwrap_or(0))),
+    };
+    if size < 1 || packet[0] != HciEvent::PACKET_TYPE {
+        return Ok(Some(None));
+    }
+    Ok(Some(Some(HciEvent::parse(&packet[..size])?)))
+}
+
+/// Sends an HCI command and waits for its Command Complete event.
+fn execute_command(adapter: Option<u16>, command: &HciCommand) -> Result<CommandResult, BtError> {
+    let opcode = command.opcode();
+    let filter = HciFilter::new().event(HciEvent::COMMAND_COMPLETE).event(HciEvent::COMMAND_STATUS).opcode(opcode);
+    let socket = open_hci_socket(adapter, &filter)?;
+    socket.set_nonblocking(false)
+        .and_then(|_| socket.set_read_timeout(Some(Duration::from_secs(2))))
+        .map_err(|error| create_error_from_errno("Configuring HCI socket failed", error.raw_os_error().unwrap_or(0)))?;
+    send_command(&socket, command)?;
+
+    loop {
+        let event = match read_event(&socket) {
+            Ok(Some(event)) => event,
+            // A blocking socket only reports EAGAIN when the read timeout expires
+            Ok(None) => return Err(create_error_of_kind(BtErrorKind::Timeout, "Waiting for HCI command completion failed", libc::ETIMEDOUT)),
+            Err(error) => return Err(error),
+        };
+
+        match event {
+            Some(HciEvent::CommandComplete { opcode: completed, status, result, .. }) if completed == opcode => {
+                if status != 0 {
+                    return Err(create_hci_status_error(&format!("HCI command 0x{:04X} failed", opcode), status));
+                }
+                return Ok(result);
+            }
+            Some(HciEvent::CommandStatus { opcode: pending, status, .. }) if pending == opcode && status != 0 => {
+                return Err(create_hci_status_error(&format!("HCI command 0x{:04X} failed", opcode), status));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Switches the given adapter to the given inquiry result format.
+pub fn write_inquiry_mode(adapter: Option<u16>, mode: InquiryMode) -> Result<(), BtError> {
+    execute_command(adapter, &HciCommand::WriteInquiryMode(mode))?;
+    Ok(())
+}
+
+/// Reads the friendly name of an adapter which is up.
+pub fn read_local_name(adapter: u16) -> Result<String, BtError> {
+    match execute_command(Some(adapter), &HciCommand::ReadLocalName)? {
+        CommandResult::LocalName(name) => Ok(name),
+        _ => Err(BtError::Desc("Malformed local name".to_string())),
+    }
+}
+
+/// Sets whether an adapter is discoverable and connectable.
+pub fn write_scan_enable(adapter: u16, discoverable: bool, connectable: bool) -> Result<(), BtError> {
+    let command = HciCommand::WriteScanEnable {
+        discoverable,
+        connectable,
+    };
+    execute_command(Some(adapter), &command)?;
+    Ok(())
+}
+
+/// Sets the friendly name of an adapter.
+pub fn write_local_name(adapter: u16, name: &str) -> Result<(), BtError> {
+    execute_command(Some(adapter), &HciCommand::WriteLocalName(name.to_string()))?;
+    Ok(())
+}
+
+/// Sets the class of device of an adapter.
+pub fn write_class_of_device(adapter: u16, class: DeviceClass) -> Result<(), BtError> {
+    execute_command(Some(adapter), &HciCommand::WriteClassOfDevice(class))?;
+    Ok(())
+}
+
+/// Opens a raw HCI socket which is not bound to any adapter, for the adapter ioctls.
+fn open_control_socket() -> Result<UnixStream, BtError> {
+    let fd = unsafe { libc::socket(AF_BLUETOOTH, libc::SOCK_RAW | libc::SOCK_CLOEXEC, BtProtocolBlueZ::HCI as i32) };
+    if fd < 0 {
+        return Err(create_error_from_last("Failed to create HCI socket"));
+    }
+    Ok(unsafe { UnixStream::from_raw_fd(fd) })
+}
+
+/// Brings an adapter up or down.
+pub fn set_adapter_up(adapter: u16, up: bool) -> Result<(), BtError> {
+    let socket = open_control_socket()?;
+    let (request, message) = if up {
+        (HCIDEVUP, "ioctl(HCIDEVUP): Bringing local bluetooth adapter up failed")
+    } else {
+        (HCIDEVDOWN, "ioctl(HCIDEVDOWN): Bringing local bluetooth adapter down failed")
+    };
+
+    if unsafe { libc::ioctl(socket.as_raw_fd(), request, adapter as c_int) } < 0 {
+        let errno = nix::errno::errno();
+        // The adapter already is in the requested state
+        if up && errno == libc::EALREADY {
+            return Ok(());
+        }
+        return Err(create_privileged_error(message, "CAP_NET_ADMIN", errno));
+    }
+    Ok(())
+}
+
+/// Lists all local adapters known to the kernel.
+pub fn list_adapters() -> Result<Vec<BtAdapter>, BtError> {
+    let socket = open_control_socket()?;
+
+    let mut list: hci_dev_list_req = unsafe { mem::zeroed() };
+    list.dev_num = HCI_MAX_DEV as u16;
+    if unsafe { libc::ioctl(socket.as_raw_fd(), HCIGETDEVLIST, &mut list) } < 0 {
+        return Err(create_error_from_last("ioctl(HCIGETDEVLIST): Listing local bluetooth adapters failed"));
+    }
+
+    let mut adapters = Vec::with_capacity(list.dev_num as usize);
+    for request in &list.dev_req[..list.dev_num as usize] {
+        let mut info: hci_dev_info = unsafe { mem::zeroed() };
+        info.dev_id = request.dev_id;
+        if unsafe { libc::ioctl(socket.as_raw_fd(), HCIGETDEVINFO, &mut info) } < 0 {
+            return Err(create_error_from_last("ioctl(HCIGETDEVINFO): Reading local bluetooth adapter failed"));
+        }
+
+        let up = info.flags & (1 << HCI_UP) != 0;
+        adapters.push(BtAdapter {
+            id: info.dev_id,
+            addr: info.bdaddr.convert_host_byteorder(),
+            // Only an adapter which is up can answer commands
+            name: if up { read_local_name(info.dev_id).ok() } else { None },
+            up,
+            features: info.features,
+        });
+    }
+
+    Ok(adapters)
+}
+
+/// Returns the adapter which is used if none is selected explicitly.
+pub fn default_adapter() -> Result<BtAdapter, BtError> {
+    let id = device_id(None)? as u16;
+    match list_adapters()?.into_iter().find(|adapter| adapter.id == id) {
+        Some(adapter) => Ok(adapter),
+        None => Err(create_error_of_kind(BtErrorKind::AdapterNotFound, "No local bluetooth adapter found", libc::ENODEV)),
+    }
+}
+
+/// Maps an HCI event received during an inquiry to discovery events.
+///
+/// A single inquiry result event can report several devices. Unrelated events and packets which
+/// are not events yield no discovery events.
+fn parse_discovery_event(packet: &[u8]) -> Result<Vec<BtDiscoveryEvent>, BtError> {
+    if packet.is_empty() || packet[0] != HciEvent::PACKET_TYPE {
+        return Ok(vec![]);
+    }
+
+    match HciEvent::parse(packet)? {
+        HciEvent::CommandStatus { opcode: HciCommand::INQUIRY, status, .. } if status != 0 => {
+            Err(create_hci_status_error("Inquiry rejected", status))
+        }
+        HciEvent::InquiryComplete { status } => {
+            if status != 0 {
+                return Err(create_hci_status_error("Inquiry failed", status));
+            }
+            Ok(vec![BtDiscoveryEvent::InquiryComplete])
+        }
+        HciEvent::InquiryResult(devices) => Ok(devices.into_iter().map(BtDiscoveryEvent::InquiryResult).collect()),
+        HciEvent::InquiryResultWithRssi(devices) => Ok(devices.into_iter().map(BtDiscoveryEvent::InquiryResultWithRSSI).collect()),
+        HciEvent::ExtendedInquiryResult(device, data) => Ok(vec![BtDiscoveryEvent::ExtendedInquiryResult(device, data)]),
+        _ => Ok(vec![]),
+    }
+}
+
+
+/// Parses an HCI event packet belonging to a remote name request for `addr`.
+///
+/// Returns `None` for events which do not finish the request.
+fn parse_name_event(addr: BtAddr, packet: &[u8]) -> Option<Result<String, BtError>> {
+    match HciEvent::parse(packet) {
+        Ok(HciEvent::CommandStatus { opcode: HciCommand::REMOTE_NAME_REQUEST, status, .. }) if status != 0 => {
+            Some(Err(create_hci_status_error("Remote name request rejected", status)))
+        }
+
+        Ok(HciEvent::RemoteNameRequestComplete { status, addr: completed, name }) if completed == addr => {
+            if status != 0 {
+                return Some(Err(create_hci_status_error("Remote name request failed", status)));
+            }
+            Some(Ok(name))
+        }
+
+        _ => None,
+    }
+}
+
+
+#[derive(Debug)]
+enum BtReadRemoteNameState {
+    New,
+    WaitForName,
+    Done,
+}
+
+#[derive(Debug)]
+pub struct BtReadRemoteName {
+    adapter: Option<u16>,
+    addr: BtAddr,
+    pscan_rep_mode: u8,
+    clock_offset: Option<u16>,
+    socket: Option<UnixStream>,
+    pollfd: RawFd,
+    state: BtReadRemoteNameState,
+    name: Option<String>,
+}
+
+impl BtReadRemoteName {
+    pub fn new(adapter: Option<u16>, addr: BtAddr) -> Self {
+        // Page scan repetition mode R1 is the most common one
+        BtReadRemoteName::with_inquiry_info(adapter, addr, 0x01, None)
+    }
+
+    pub fn with_inquiry_info(adapter: Option<u16>, addr: BtAddr, pscan_rep_mode: u8, clock_offset: Option<u16>) -> Self {
+        BtReadRemoteName {
+            adapter,
+            addr,
+            pscan_rep_mode,
+            clock_offset,
+            socket: None,
+            pollfd: 0,
+            state: BtReadRemoteNameState::New,
+            name: None,
+        }
+    }
+}
+
+impl Advance for BtReadRemoteName {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        match self.state {
+            BtReadRemoteNameState::New => {
+                let filter = HciFilter::new().event(HciEvent::COMMAND_STATUS).event(HciEvent::REMOTE_NAME_REQUEST_COMPLETE);
+                let socket = open_hci_socket(self.adapter, &filter)?;
+
+                let command = HciCommand::RemoteNameRequest {
+                    addr: self.addr,
+                    page_scan_repetition_mode: self.pscan_rep_mode,
+                    clock_offset: self.clock_offset,
+                };
+                send_command(&socket, &command)?;
+
+                self.pollfd = socket.as_raw_fd();
+                self.socket = Some(socket);
+                self.state = BtReadRemoteNameState::WaitForName;
+                Ok(BtAsync::WaitFor(self, Interest::READABLE))
+            }
+
+            BtReadRemoteNameState::WaitForName => {
+                let mut packet = [0u8; 260];
+                loop {
+                    let size = match self.socket.as_ref().unwrap().read(&mut packet) {
+                        Ok(size) => size,
+                        Err(ref error) if error.kind() == ErrorKind::WouldBlock => return Ok(BtAsync::WaitFor(self, Interest::READABLE)),
+                        Err(error) => return Err(create_error_from_errno("Reading HCI event failed", error.raw_os_error().unwrap_or(0))),
+                    };
+                    if let Some(result) = parse_name_event(self.addr, &packet[..size]) {
+                        self.socket = None;
+                        self.state = BtReadRemoteNameState::Done;
+                        self.name = Some(result?);
+                        return Ok(BtAsync::Done);
+                    }
+                }
+            }
 
-    fn hci_read_remote_name(socket: c_int, addr: *const BtAddr, max_len: c_int, name: *mut c_char, timeout_ms: c_int) -> c_int;
+            BtReadRemoteNameState::Done => {
+                panic!("Trying advance `BtReadRemoteName` from `Done` state");
+            }
+        }
+    }
+
+    fn pollfd(&self) -> RawFd {
+        self.pollfd
+    }
+}
+
+impl ReadRemoteName for BtReadRemoteName {
+    fn take_name(&mut self) -> Option<String> {
+        self.name.take()
+    }
+}
+
+impl Drop for BtReadRemoteName {
+    fn drop(&mut self) {
+        // Abort a still pending request, so the adapter does not keep paging the device
+        if let Some(ref socket) = self.socket {
+            let _ = send_command(socket, &HciCommand::RemoteNameRequestCancel(self.addr));
+        }
+    }
+}
+
+impl mio::event::Source for BtReadRemoteName {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).deregister(registry)
+    }
+}
+
+#[derive(Debug)]
+enum BtDiscoveryState {
+    Inquiry,
+    Done,
+}
+
+#[derive(Debug)]
+pub struct BtDiscovery {
+    socket: UnixStream,
+    state: BtDiscoveryState,
+    events: VecDeque<BtDiscoveryEvent>,
+}
+
+impl BtDiscovery {
+    pub fn start(options: &ScanOptions) -> Result<BtDiscovery, BtError> {
+        if let Some(mode) = options.inquiry_mode {
+            write_inquiry_mode(options.adapter, mode)?;
+        }
+
+        let filter = HciFilter::new()
+            .event(HciEvent::INQUIRY_COMPLETE)
+            .event(HciEvent::INQUIRY_RESULT)
+            .event(HciEvent::COMMAND_STATUS)
+            .event(HciEvent::INQUIRY_RESULT_WITH_RSSI)
+            .event(HciEvent::EXTENDED_INQUIRY_RESULT);
+        let socket = open_hci_socket(options.adapter, &filter)?;
+
+        let command = HciCommand::Inquiry {
+            access_code: options.access_code,
+            length: options.inquiry_length,
+            max_responses: options.max_responses,
+        };
+        send_command(&socket, &command)?;
+
+        Ok(BtDiscovery {
+            socket,
+            state: BtDiscoveryState::Inquiry,
+            events: VecDeque::new(),
+        })
+    }
 }
 
-pub fn scan_devices() -> Result<Vec<BtDevice>, BtError> {
-    let device_id = unsafe { hci_get_route(0 as *mut BtAddr) };
-    if device_id < 0 {
-        return Err(create_error_from_last("hci_get_route(): No local bluetooth adapter found"));
+impl Discovery for BtDiscovery {
+    fn next_event(&mut self) -> Result<Option<BtDiscoveryEvent>, BtError> {
+        let mut packet = [0u8; 260];
+        while self.events.is_empty() {
+            if let BtDiscoveryState::Done = self.state {
+                return Ok(None);
+            }
+
+            let size = match (&self.socket).read(&mut packet) {
+                Ok(size) => size,
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(error) => return Err(create_error_from_errno("Reading HCI event failed", error.raw_os_error().unwrap_or(0))),
+            };
+
+            let events = match parse_discovery_event(&packet[..size]) {
+                Ok(events) => events,
+                Err(error) => {
+                    self.state = BtDiscoveryState::Done;
+                    return Err(error);
+                }
+            };
+            for event in events {
+                if event == BtDiscoveryEvent::InquiryComplete {
+                    self.state = BtDiscoveryState::Done;
+                }
+                self.events.push_back(event);
+            }
+        }
+
+        Ok(self.events.pop_front())
+    }
+
+    fn cancel(&mut self) -> Result<(), BtError> {
+        if let BtDiscoveryState::Inquiry = self.state {
+            self.state = BtDiscoveryState::Done;
+            send_command(&self.socket, &HciCommand::InquiryCancel)?;
+        }
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        match self.state {
+            BtDiscoveryState::Done => self.events.is_empty(),
+            BtDiscoveryState::Inquiry => false,
+        }
     }
 
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl Drop for BtDiscovery {
+    fn drop(&mut self) {
+        let _ = self.cancel();
+    }
+}
+
+#[derive(Debug)]
+pub struct HciSocket {
+    socket: UnixStream,
+}
+
+impl HciSocket {
+    pub fn open(adapter: Option<u16>) -> Result<HciSocket, BtError> {
+        Ok(HciSocket { socket: open_hci_socket(adapter, &HciFilter::all_events())? })
+    }
+}
+
+impl Hci for HciSocket {
+    fn set_filter(&self, filter: &HciFilter) -> Result<(), BtError> {
+        set_filter(&self.socket, filter)
+    }
+
+    fn send(&self, command: &HciCommand) -> Result<(), BtError> {
+        send_command(&self.socket, command)
+    }
+
+    fn read_event(&self) -> Result<Option<HciEvent>, BtError> {
+        loop {
+            match read_event(&self.socket)? {
+                Some(Some(event)) => return Ok(Some(event)),
+                // Skip packets which are not events
+                Some(None) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// Reads the name of a remote device, giving up after `timeout`.
+///
+/// Returns `None` if the name could not be determined.
+fn read_remote_name_timeout(mut request: BtReadRemoteName, timeout: Option<Duration>) -> Option<String> {
+    let mut evtloop = Poll::new().unwrap();
+    let token = mio::Token(0);
+    let mut events = mio::Events::with_capacity(2);
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    loop {
+        match request.advance() {
+            Ok(BtAsync::WaitFor(evented, interest)) => {
+                evtloop.registry().register(evented, token, interest).unwrap();
+                let remaining = deadline.map(|deadline| {
+                    let now = Instant::now();
+                    if deadline > now { deadline - now } else { Duration::from_secs(0) }
+                });
+                evtloop.poll(&mut events, remaining).unwrap();
+                evtloop.registry().deregister(evented).unwrap();
+
+                if events.is_empty() {
+                    // Timeout expired
+                    return None;
+                }
+            }
+            Ok(BtAsync::Done) => return request.take_name(),
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Runs an inquiry through the kernel, which only reports the standard inquiry result fields.
+fn inquire(options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+    let device_id = device_id(options.adapter)?;
+
     let local_socket = unsafe { hci_open_dev(device_id) };
     if local_socket < 0 {
-        return Err(create_error_from_last("hci_open_dev(): Opening local bluetooth adapter failed"));
+        return Err(create_adapter_error("hci_open_dev(): Opening local bluetooth adapter failed", nix::errno::errno()));
     }
 
-    let mut inquiry_infos = ::std::vec::from_elem(InquiryInfo::default(), 256);
+    // The adapter treats a maximum of 0 responses as "unlimited", which is 255 responses
+    let max_responses = if options.max_responses == 0 { 255 } else { options.max_responses };
+    let mut inquiry_infos = ::std::vec::from_elem(InquiryInfo::default(), max_responses as usize);
 
-    let timeout = 1; // 1.28 sec
-    let flags = IREQ_CACHE_FLUSH;
+    let lap = options.access_code.lap();
+    let flags = if options.flush_cache { IREQ_CACHE_FLUSH } else { 0 };
     let number_responses = unsafe {
         hci_inquiry(device_id,
-                    timeout,
+                    options.inquiry_length as c_int,
                     inquiry_infos.len() as c_int,
-                    ptr::null(),
+                    lap.as_ptr(),
                     &mut ::std::mem::transmute(&mut inquiry_infos[0]),
                     flags)
     };
     if number_responses < 0 {
+        unsafe { close(local_socket) };
         return Err(create_error_from_last("hci_inquiry(): Scanning remote bluetooth devices failed"));
     }
 
     inquiry_infos.truncate(number_responses as usize);
 
-    let mut devices = Vec::with_capacity(inquiry_infos.len());
-    for inquiry_info in &inquiry_infos {
-        let mut cname = [0; 256];
-        let name = if unsafe {
-            hci_read_remote_name(local_socket,
-                                 &inquiry_info.bdaddr,
-                                 cname.len() as c_int,
-                                 &mut cname[0],
-                                 0)
-        } < 0 {
-            "[unknown]".to_string()
-        } else {
-            unsafe { CStr::from_ptr(&cname[0]) }.to_string_lossy().into_owned()
-        };
+    if unsafe { close(local_socket) } < 0 {
+        return Err(create_error_from_last("close()"));
+    }
 
-        devices.push(BtDevice {
-            name: name,
+    Ok(inquiry_infos.iter().map(|inquiry_info| {
+        BtDevice {
+            name: None,
             addr: inquiry_info.bdaddr.convert_host_byteorder(),
-        })
+            class: Some(DeviceClass::from_bytes(inquiry_info.dev_class)),
+            page_scan_repetition_mode: Some(inquiry_info.pscan_rep_mode),
+            clock_offset: Some(u16::from_le(inquiry_info.clock_offset)),
+            rssi: None,
+            eir: None,
+        }
+    }).collect())
+}
+
+/// Runs an inquiry on a raw HCI socket, which also reports the fields of the RSSI and extended
+/// inquiry result formats.
+fn discover(options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+    let mut discovery = BtDiscovery::start(options)?;
+    let mut evtloop = Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(2);
+    evtloop.registry().register(&mut SourceFd(&discovery.as_raw_fd()), mio::Token(0), Interest::READABLE).unwrap();
+
+    let mut devices: Vec<BtDevice> = Vec::new();
+    while !discovery.is_done() {
+        evtloop.poll(&mut events, None).unwrap();
+        while let Some(event) = discovery.next_event()? {
+            let device = match event {
+                BtDiscoveryEvent::InquiryResult(device) => device,
+                BtDiscoveryEvent::InquiryResultWithRSSI(device) => device,
+                BtDiscoveryEvent::ExtendedInquiryResult(device, _) => device,
+                BtDiscoveryEvent::InquiryComplete => continue,
+            };
+
+            // Devices can respond more than once during an inquiry
+            match devices.iter().position(|known| known.addr == device.addr) {
+                Some(index) => devices[index] = device,
+                None => devices.push(device),
+            }
+        }
     }
 
-    if unsafe { close(local_socket) } < 0 {
-        return Err(create_error_from_last("close()"));
+    Ok(devices)
+}
+
+pub fn scan_devices(options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+    let mut devices = match options.inquiry_mode {
+        None => inquire(options)?,
+        Some(InquiryMode::Standard) => {
+            write_inquiry_mode(options.adapter, InquiryMode::Standard)?;
+            inquire(options)?
+        }
+        // Only the raw HCI socket receives the signal strength
+        Some(_) => discover(options)?,
+    };
+
+    if options.resolve_names {
+        for device in &mut devices {
+            // The complete name from the extended inquiry response saves the name request
+            if device.eir.as_ref().is_some_and(|eir| eir.name_complete) {
+                continue;
+            }
+            let request = BtReadRemoteName::with_inquiry_info(options.adapter,
+                                                              device.addr,
+                                                              device.page_scan_repetition_mode.unwrap_or(0x01),
+                                                              device.clock_offset);
+            device.name = read_remote_name_timeout(request, options.name_timeout);
+        }
     }
 
     Ok(devices)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_complete_event(status: u8, addr: [u8; 6], name: &[u8]) -> Vec<u8> {
+        let mut packet = vec![HciEvent::PACKET_TYPE, HciEvent::REMOTE_NAME_REQUEST_COMPLETE, 0, status];
+        packet.extend_from_slice(&addr);
+        packet.extend_from_slice(name);
+        packet.resize(3 + 255, 0);
+        packet[2] = 255;
+        packet
+    }
+
+    #[test]
+    fn reports_missing_privileges() {
+        let error = create_privileged_error("ioctl(HCIDEVUP)", "CAP_NET_ADMIN", libc::EPERM);
+        assert_eq!(error.kind(), BtErrorKind::PermissionDenied);
+        assert_eq!(error.errno(), Some(libc::EPERM as u32));
+        assert!(error.to_string().contains("CAP_NET_ADMIN"));
+
+        let error = create_privileged_error("ioctl(HCIDEVUP)", "CAP_NET_ADMIN", libc::ENODEV);
+        assert_eq!(error.kind(), BtErrorKind::AdapterNotFound);
+        assert_eq!(error.errno(), Some(libc::ENODEV as u32));
+
+        let error = create_privileged_error("ioctl(HCIDEVUP)", "CAP_NET_ADMIN", libc::EBUSY);
+        assert_eq!(error.kind(), BtErrorKind::Other);
+        assert_eq!(error.errno(), Some(libc::EBUSY as u32));
+    }
+
+    #[test]
+    fn translates_hci_status_codes() {
+        let error = create_hci_status_error("Remote name request failed", 0x04);
+        assert_eq!(error.kind(), BtErrorKind::HostDown);
+        assert_eq!(error.errno(), Some(libc::EHOSTDOWN as u32));
+        assert!(error.to_string().starts_with("Remote name request failed with status 0x04: "));
+
+        assert_eq!(create_hci_status_error("HCI command 0x0C13 failed", 0x0F).kind(), BtErrorKind::ConnectionRefused);
+        assert_eq!(create_hci_status_error("HCI command 0x0C13 failed", 0x0C).kind(), BtErrorKind::Other);
+        assert_eq!(create_hci_status_error("HCI command 0x0C13 failed", 0x3E),
+                   BtError::Desc("HCI command 0x0C13 failed with status 0x3E".to_string()));
+    }
+
+    #[test]
+    fn matches_kernel_struct_layout() {
+        assert_eq!(size_of::<hci_dev_info>(), 92);
+        assert_eq!(size_of::<hci_dev_list_req>(), 4 + HCI_MAX_DEV * 8);
+        assert_eq!(size_of::<hci_filter>(), 16);
+    }
+
+    #[test]
+    fn parses_remote_name_events() {
+        let addr = BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let wire_addr = [0x55, 0x44, 0x33, 0x22, 0x11, 0x00];
+
+        let packet = name_complete_event(0x00, wire_addr, b"My Phone");
+        assert_eq!(parse_name_event(addr, &packet).unwrap().unwrap(), "My Phone");
+
+        // Completion for another device
+        let packet = name_complete_event(0x00, [0; 6], b"Other");
+        assert!(parse_name_event(addr, &packet).is_none());
+
+        // Page timeout
+        let packet = name_complete_event(0x04, wire_addr, b"");
+        assert!(parse_name_event(addr, &packet).unwrap().is_err());
+
+        // Successful command status just means the request is pending
+        let status = [HciEvent::PACKET_TYPE, HciEvent::COMMAND_STATUS, 4, 0x00, 0x01, 0x19, 0x04];
+        assert!(parse_name_event(addr, &status).is_none());
+
+        let status = [HciEvent::PACKET_TYPE, HciEvent::COMMAND_STATUS, 4, 0x0C, 0x01, 0x19, 0x04];
+        assert!(parse_name_event(addr, &status).unwrap().is_err());
+
+        assert!(parse_name_event(addr, &[HciEvent::PACKET_TYPE]).is_none());
+    }
+
+    #[test]
+    fn parses_inquiry_result_events() {
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::INQUIRY_RESULT, 15, 1,
+                      0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0x01, 0x00, 0x00,
+                      0x0C, 0x02, 0x5A, 0x34, 0x12];
+        let events = parse_discovery_event(&packet).unwrap();
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            BtDiscoveryEvent::InquiryResult(ref device) => {
+                assert_eq!(device.addr, BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+                assert_eq!(device.name, None);
+                assert_eq!(device.class, Some(DeviceClass(0x5A020C)));
+                assert_eq!(device.page_scan_repetition_mode, Some(1));
+                assert_eq!(device.clock_offset, Some(0x1234));
+            }
+            ref event => panic!("unexpected event {:?}", event),
+        }
+
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::INQUIRY_COMPLETE, 1, 0x00];
+        assert_eq!(parse_discovery_event(&packet).unwrap(), vec![BtDiscoveryEvent::InquiryComplete]);
+
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::COMMAND_STATUS, 4, 0x0C, 0x01, 0x01, 0x04];
+        assert!(parse_discovery_event(&packet).is_err());
+
+        // Unrelated events
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::COMMAND_STATUS, 4, 0x00, 0x01, 0x01, 0x04];
+        assert!(parse_discovery_event(&packet).unwrap().is_empty());
+
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::INQUIRY_RESULT, 15, 2, 0x55];
+        assert!(parse_discovery_event(&packet).is_err());
+    }
+
+    #[test]
+    fn parses_inquiry_result_with_rssi_events() {
+        // Two responses in the standard format
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::INQUIRY_RESULT_WITH_RSSI, 29, 2,
+                      0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0x01, 0x00, 0x0C, 0x02, 0x5A, 0x34, 0x12, 0xC4,
+                      0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x02, 0x00, 0x04, 0x01, 0x24, 0x00, 0x00, 0x05];
+        let events = parse_discovery_event(&packet).unwrap();
+        assert_eq!(events.len(), 2);
+        match events[0] {
+            BtDiscoveryEvent::InquiryResultWithRSSI(ref device) => {
+                assert_eq!(device.addr, BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+                assert_eq!(device.class, Some(DeviceClass(0x5A020C)));
+                assert_eq!(device.page_scan_repetition_mode, Some(1));
+                assert_eq!(device.clock_offset, Some(0x1234));
+                assert_eq!(device.rssi, Some(-60));
+            }
+            ref event => panic!("unexpected event {:?}", event),
+        }
+        match events[1] {
+            BtDiscoveryEvent::InquiryResultWithRSSI(ref device) => {
+                assert_eq!(device.addr, BtAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]));
+                assert_eq!(device.class, Some(DeviceClass(0x240104)));
+                assert_eq!(device.rssi, Some(5));
+            }
+            ref event => panic!("unexpected event {:?}", event),
+        }
+
+        // A response in the format including pscan_mode
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::INQUIRY_RESULT_WITH_RSSI, 16, 1,
+                      0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0x01, 0x00, 0x00, 0x0C, 0x02, 0x5A, 0x34, 0x12, 0xB0];
+        match parse_discovery_event(&packet).unwrap()[0] {
+            BtDiscoveryEvent::InquiryResultWithRSSI(ref device) => {
+                assert_eq!(device.class, Some(DeviceClass(0x5A020C)));
+                assert_eq!(device.clock_offset, Some(0x1234));
+                assert_eq!(device.rssi, Some(-80));
+            }
+            ref event => panic!("unexpected event {:?}", event),
+        }
+
+        let packet = [HciEvent::PACKET_TYPE, HciEvent::INQUIRY_RESULT_WITH_RSSI, 3, 1, 0x55, 0x44];
+        assert!(parse_discovery_event(&packet).is_err());
+    }
+
+    #[test]
+    fn parses_extended_inquiry_result_events() {
+        let mut packet = vec![HciEvent::PACKET_TYPE, HciEvent::EXTENDED_INQUIRY_RESULT, 255, 1,
+                              0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0x01, 0x00, 0x0C, 0x02, 0x5A, 0x34, 0x12, 0xD8];
+        let eir = [0x05, 0x09, b'T', b'e', b's', b't'];
+        packet.extend_from_slice(&eir);
+        packet.resize(3 + 255, 0);
+
+        match parse_discovery_event(&packet).unwrap()[0] {
+            BtDiscoveryEvent::ExtendedInquiryResult(ref device, ref data) => {
+                assert_eq!(device.addr, BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]));
+                assert_eq!(device.rssi, Some(-40));
+                assert_eq!(device.name, Some("Test".to_string()));
+                assert!(device.eir.as_ref().unwrap().name_complete);
+                assert_eq!(data.len(), 240);
+                assert_eq!(&data[..6], &eir);
+            }
+            ref event => panic!("unexpected event {:?}", event),
+        }
+
+        // Truncated packet
+        assert!(parse_discovery_event(&packet[..20]).is_err());
+    }
+}
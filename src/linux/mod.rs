@@ -6,5 +6,86 @@ mod sdp;
 mod hci;
 mod socket;
 
-pub use self::socket::{BtSocket, BtSocketConnect};
-pub use self::hci::scan_devices;
+use backend::{Discovery, Hci, Listener, ReadRemoteName, ServiceBrowse, ServiceRegistration, Socket, Stack};
+use bluetooth::{BtAdapter, BtAddr, BtDevice, BtError, BtProtocol, BtServiceRecord, DeviceClass, InquiryMode, ScanOptions};
+use std::os::unix::io::RawFd;
+
+/// The BlueZ sockets of the Linux kernel.
+#[derive(Debug)]
+pub struct LinuxStack;
+
+impl Stack for LinuxStack {
+    fn socket(&self, protocol: BtProtocol) -> Result<Box<dyn Socket>, BtError> {
+        Ok(Box::new(socket::BtSocket::new(protocol)?))
+    }
+
+    unsafe fn socket_from_raw_fd(&self, fd: RawFd) -> Box<dyn Socket> {
+        Box::new(socket::BtSocket::from_raw_fd(fd))
+    }
+
+    fn listener(&self, protocol: BtProtocol) -> Result<Box<dyn Listener>, BtError> {
+        Ok(Box::new(socket::BtListener::new(protocol)?))
+    }
+
+    fn browse_services(&self, local_addr: BtAddr, addr: BtAddr) -> Box<dyn ServiceBrowse> {
+        Box::new(sdp::BtServiceBrowse::new(local_addr, addr))
+    }
+
+    fn register_service(&self, record: &BtServiceRecord) -> Result<Box<dyn ServiceRegistration>, BtError> {
+        Ok(Box::new(sdp::BtServiceRegistration::register(record)?))
+    }
+
+    fn read_remote_name(&self, adapter: Option<u16>, addr: BtAddr, inquiry_info: Option<(u8, Option<u16>)>)
+            -> Box<dyn ReadRemoteName> {
+        Box::new(match inquiry_info {
+            Some((pscan_rep_mode, clock_offset)) => {
+                hci::BtReadRemoteName::with_inquiry_info(adapter, addr, pscan_rep_mode, clock_offset)
+            }
+            None => hci::BtReadRemoteName::new(adapter, addr),
+        })
+    }
+
+    fn discover_devices(&self, options: &ScanOptions) -> Result<Box<dyn Discovery>, BtError> {
+        Ok(Box::new(hci::BtDiscovery::start(options)?))
+    }
+
+    fn scan_devices(&self, options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+        hci::scan_devices(options)
+    }
+
+    fn open_hci_socket(&self, adapter: Option<u16>) -> Result<Box<dyn Hci>, BtError> {
+        Ok(Box::new(hci::HciSocket::open(adapter)?))
+    }
+
+    fn list_adapters(&self) -> Result<Vec<BtAdapter>, BtError> {
+        hci::list_adapters()
+    }
+
+    fn default_adapter(&self) -> Result<BtAdapter, BtError> {
+        hci::default_adapter()
+    }
+
+    fn read_local_name(&self, adapter: u16) -> Result<String, BtError> {
+        hci::read_local_name(adapter)
+    }
+
+    fn set_adapter_up(&self, adapter: u16, up: bool) -> Result<(), BtError> {
+        hci::set_adapter_up(adapter, up)
+    }
+
+    fn write_scan_enable(&self, adapter: u16, discoverable: bool, connectable: bool) -> Result<(), BtError> {
+        hci::write_scan_enable(adapter, discoverable, connectable)
+    }
+
+    fn write_local_name(&self, adapter: u16, name: &str) -> Result<(), BtError> {
+        hci::write_local_name(adapter, name)
+    }
+
+    fn write_class_of_device(&self, adapter: u16, class: DeviceClass) -> Result<(), BtError> {
+        hci::write_class_of_device(adapter, class)
+    }
+
+    fn write_inquiry_mode(&self, adapter: Option<u16>, mode: InquiryMode) -> Result<(), BtError> {
+        hci::write_inquiry_mode(adapter, mode)
+    }
+}
@@ -1,448 +1,728 @@
-extern crate enum_primitive;
+extern crate libc;
+extern crate mio;
+extern crate nix;
 
-use super::ffi::*;
-use super::socket::create_error_from_errno;
-use super::socket::create_error_from_last;
+use super::socket::{AF_BLUETOOTH, BtProtocolBlueZ, create_connect_error, create_error_from_errno, create_error_from_last,
+                    sockaddr_l2};
 
-use bluetooth::{BtAddr, BtError};
+use backend::{ServiceBrowse, ServiceRegistration};
+use bluetooth::{Advance, BtAddr, BtAsync, BtError, BtErrorKind, BtServiceRecord, BtUuid};
+use sdp_data::{SdpServiceClass, SdpValue, build_service_record, parse_service_record};
 
-use std::mem;
+use std;
+use std::io::{ErrorKind, Read, Write};
+use std::mem::size_of;
 use std::ptr;
-use std::slice;
-use std::os::raw::*;
 use std::os::unix;
-use enum_primitive::FromPrimitive;
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-#[derive(Debug)]
-struct sdp_session_t {
-    sock: unix::io::RawFd,
-    state: c_int,
-    local: c_int,
-    flags: c_int,
-    tid: uint16_t,
-    priv_: *mut c_void,
-}
-impl ::std::default::Default for sdp_session_t {
-    fn default() -> Self {
-        unsafe { ::std::mem::zeroed() }
-    }
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+use mio::{Interest, Registry};
+use mio::unix::SourceFd;
+
+/// The L2CAP PSM of the SDP server.
+const SDP_PSM: u16 = 0x0001;
+
+/// Maximum number of attribute bytes the server may return in a single response PDU.
+const SDP_MAX_ATTR_BYTES: u16 = 0xFFFF;
+
+/// Maximum size of an SDP response PDU we are able to receive.
+const SDP_MAX_PDU_SIZE: usize = 0x10000;
+
+/// Maximum total size of the attribute lists of a service search, which may span many responses.
+const SDP_MAX_RESPONSE_SIZE: usize = 0x100000;
+
+/// Path of the UNIX socket of the local BlueZ SDP server.
+const SDP_UNIX_PATH: &str = "/var/run/sdp";
+
+/// How long to wait for each request and response on the UNIX socket of the local SDP server.
+const SDP_UNIX_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum SdpPduId {
+    ErrorResponse = 0x01,
+    ServiceSearchAttributeRequest = 0x06,
+    ServiceSearchAttributeResponse = 0x07,
+    ServiceRemoveRequest = 0x73,
+    ServiceRemoveResponse = 0x74,
+    ServiceRegisterRequest = 0x75,
+    ServiceRegisterResponse = 0x76,
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-#[derive(Debug)]
-struct uuid_union_t {
-    _bindgen_data_: [u32; 4usize],
+/// Builds a PDU from the PDU ID, the transaction ID and the parameters.
+fn build_pdu(pdu_id: SdpPduId, tid: u16, params: &[u8]) -> Vec<u8> {
+    let mut pdu = vec![pdu_id as u8, (tid >> 8) as u8, tid as u8, (params.len() >> 8) as u8, params.len() as u8];
+    pdu.extend_from_slice(params);
+    pdu
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-#[derive(Debug)]
-struct uuid_t {
-    pub type_: uint8_t,
-    pub value: uuid_union_t,
-}
-impl ::std::default::Default for uuid_t {
-    fn default() -> Self {
-        unsafe { ::std::mem::zeroed() }
-    }
+/// Builds a ServiceSearchAttributeRequest PDU searching for `service` and requesting all attributes.
+fn build_search_attr_request(tid: u16, service: BtUuid, continuation: &[u8]) -> Vec<u8> {
+    let mut params = Vec::new();
+    SdpValue::Sequence(vec![SdpValue::Uuid(service)]).encode(&mut params);
+    params.push((SDP_MAX_ATTR_BYTES >> 8) as u8);
+    params.push(SDP_MAX_ATTR_BYTES as u8);
+    SdpValue::Sequence(vec![SdpValue::Uint32(0x0000FFFF)]).encode(&mut params);
+    params.push(continuation.len() as u8);
+    params.extend_from_slice(continuation);
+
+    build_pdu(SdpPduId::ServiceSearchAttributeRequest, tid, &params)
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-#[derive(Debug)]
-struct sdp_list_t {
-    next: *mut sdp_list_t,
-    data: *mut c_void,
-}
-impl ::std::default::Default for sdp_list_t {
-    fn default() -> Self {
-        unsafe { ::std::mem::zeroed() }
-    }
+/// Creates an error for a malformed response of an SDP server.
+fn create_protocol_error(message: &str) -> BtError {
+    BtError::Kind(BtErrorKind::SdpProtocol(None), None, message.to_string())
 }
 
-#[derive(Copy, Clone)]
-#[repr(u32)]
-#[derive(Debug)]
-#[allow(dead_code)]
-pub enum SdpAttrReqType {
-    Individual = 1,
-    Range = 2,
+/// Creates an error for an error response of an SDP server with error code `code`.
+fn create_error_response_error(message: &str, code: u16) -> BtError {
+    BtError::Kind(BtErrorKind::SdpProtocol(Some(code)), None, format!("{} (0x{:04X})", message, code))
 }
 
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-#[derive(Debug)]
-struct sdp_record_t {
-    pub handle: uint32_t,
-    pub pattern: *mut sdp_list_t,
-    pub attrlist: *mut sdp_list_t,
-    pub svclass: uuid_t,
-    _bindgen_padding_0_: [u8; 4usize],
-}
-impl ::std::default::Default for sdp_record_t {
-    fn default() -> Self {
-        unsafe { ::std::mem::zeroed() }
+/// Parses a ServiceSearchAttributeResponse PDU with transaction ID `tid`.
+///
+/// Returns the (partial) attribute lists and the continuation state.
+fn parse_search_attr_response(tid: u16, pdu: &[u8]) -> Result<(&[u8], &[u8]), BtError> {
+    fn make_status_error(message: &str) -> BtError {
+        create_protocol_error(&format!("SDP service search: {}", message))
     }
-}
 
+    let read_u16 = |data: &[u8]| ((data[0] as u16) << 8) | data[1] as u16;
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-#[derive(Debug)]
-struct sdp_val_union_t {
-    _bindgen_data_: [u64; 3usize],
-}
-impl sdp_val_union_t {
-    unsafe fn uint8(&mut self) -> *mut uint8_t {
-        let raw: *mut u8 = ::std::mem::transmute(&self._bindgen_data_);
-        ::std::mem::transmute(raw.offset(0))
+    if pdu.len() < 5 {
+        return Err(make_status_error("PDU too short"));
     }
-    unsafe fn uuid(&mut self) -> *mut uuid_t {
-        let raw: *mut u8 = ::std::mem::transmute(&self._bindgen_data_);
-        ::std::mem::transmute(raw.offset(0))
+    if read_u16(&pdu[1..3]) != tid {
+        return Err(make_status_error("Unexpected transaction ID"));
     }
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-#[derive(Debug)]
-struct sdp_data_t {
-    dtd: uint8_t,
-    attr_id: uint16_t,
-    val: sdp_val_union_t,
-    next: *mut sdp_data_t,
-    unit_size: c_int,
-    _bindgen_padding_0_: [u8; 4usize],
-}
-impl ::std::default::Default for sdp_data_t {
-    fn default() -> Self {
-        unsafe { ::std::mem::zeroed() }
+    let params = &pdu[5..];
+    if params.len() != read_u16(&pdu[3..5]) as usize {
+        return Err(make_status_error("Invalid parameter length"));
     }
-}
 
-enum_from_primitive!{
-    enum SdpPdu {
-        DataNil            = 0x00,
-        Uint8               = 0x08,
-        Uint16              = 0x09,
-        Uint32              = 0x0A,
-        Uint64              = 0x0B,
-        Uint128             = 0x0C,
-        Int8                = 0x10,
-        Int16               = 0x11,
-        Int32               = 0x12,
-        Int64               = 0x13,
-        Int128              = 0x14,
-        UuidUnspec         = 0x18,
-        Uuid16              = 0x19,
-        Uuid32              = 0x1A,
-        Uuid128             = 0x1C,
-        TextStrUnspec     = 0x20,
-        TextStr8           = 0x25,
-        TextStr16          = 0x26,
-        TextStr32          = 0x27,
-        Bool                = 0x28,
-        SeqUnspec          = 0x30,
-        Seq8                = 0x35,
-        Seq16               = 0x36,
-        Seq32               = 0x37,
-        AltUnspec          = 0x38,
-        Alt8                = 0x3D,
-        Alt16               = 0x3E,
-        Alt32               = 0x3F,
-        UrlStrUnspec      = 0x40,
-        UrlStr8            = 0x45,
-        UrlStr16           = 0x46,
-        UrlStr32           = 0x47,
+    if pdu[0] == SdpPduId::ErrorResponse as u8 {
+        if params.len() < 2 {
+            return Err(make_status_error("PDU too short"));
+        }
+        let code = read_u16(params);
+        let message = match code {
+            0x0001 => "Invalid version".to_string(),
+            0x0002 => "Invalid record handle".to_string(),
+            0x0003 => "Invalid syntax".to_string(),
+            0x0004 => "Invalid PDU size".to_string(),
+            0x0005 => "Invalid CState".to_string(),
+            0x0006 => "Insufficient resources".to_string(),
+            _ => "Unknown error".to_string(),
+        };
+        return Err(create_error_response_error(&format!("SDP service search: {}", message), code));
+    } else if pdu[0] != SdpPduId::ServiceSearchAttributeResponse as u8 {
+        return Err(make_status_error("Unexpected PDU"));
     }
-}
 
-#[allow(dead_code)]
-enum SdpConnectFlags {
-    RetryIfBusy = 0x01,
-    WaitOnClose = 0x02,
-    NonBlocking = 0x04,
-    LargeMtu = 0x08,
-}
+    if params.len() < 2 {
+        return Err(make_status_error("PDU too short"));
+    }
+    let byte_count = read_u16(params) as usize;
+    if params.len() < 2 + byte_count + 1 {
+        return Err(make_status_error("PDU too short"));
+    }
+    let attribute_lists = &params[2..2 + byte_count];
+    let continuation = &params[2 + byte_count + 1..];
+    if continuation.len() != params[2 + byte_count] as usize || continuation.len() > 16 {
+        return Err(make_status_error("Invalid continuation state"));
+    }
 
-enum SdpProfile {
-    SerialPort = 0x1101,
+    Ok((attribute_lists, continuation))
 }
 
-enum SdpProtoUuid {
-    Rfcomm = 0x0003,
-}
+fn parse_response(response: &[u8]) -> Result<Vec<BtServiceRecord>, BtError> {
+    if response.is_empty() {
+        return Ok(Vec::new());
+    }
 
-#[cfg(target_os = "linux")]
-#[link(name="bluetooth")]
-extern "C" {
-    fn sdp_connect(src: *const BtAddr, dst: *const BtAddr, flags: uint32_t) -> *mut sdp_session_t;
-
-    fn sdp_uuid16_create(uuid: *mut uuid_t, data: uint16_t) -> *mut uuid_t;
-    fn sdp_list_append(list: *mut sdp_list_t, d: *mut c_void) -> *mut sdp_list_t;
-
-    fn sdp_service_search_attr_async(session: *mut sdp_session_t,
-                                     search: *const sdp_list_t,
-                                     reqtype: SdpAttrReqType,
-                                     attrid_list: *const sdp_list_t)
-                                     -> c_int;
-    fn sdp_process(session: *mut sdp_session_t) -> c_int;
-    fn sdp_get_error(session: *mut sdp_session_t) -> c_int;
-    fn sdp_set_notify(session: *mut sdp_session_t,
-                      func: Option<unsafe extern "C" fn(u8, u16, *const u8, usize, *mut c_void)>,
-                      udata: *mut c_void)
-                      -> c_int;
-
-    fn sdp_extract_seqtype(buf: *const u8, bufsize: c_int, dtdp: *mut u8, size: *mut c_int) -> c_int;
-    fn sdp_extract_pdu(pdata: *const u8, bufsize: c_int, scanned: *mut c_int) -> *mut sdp_record_t;
-
-    fn sdp_get_access_protos(rec: *const sdp_record_t, protos: *mut *mut sdp_list_t) -> c_int;
-    fn sdp_uuid_to_proto(uuid: *mut uuid_t) -> c_int;
-
-    fn sdp_list_free(list: *mut sdp_list_t, free_func: *const c_void);
-    fn sdp_close(session: *mut sdp_session_t) -> c_int;
-    fn sdp_record_free(rec: *mut sdp_record_t);
+    // Response is a sequence of sequence(s) for one or
+    // more data element sequence(s) representing services
+    // for which attributes are returned
+    match SdpValue::decode(response)?.0 {
+        SdpValue::Sequence(records) => records.iter().map(parse_service_record).collect(),
+        _ => Err(create_protocol_error("SDP response is not a data element sequence")),
+    }
 }
 
 
 #[derive(Debug)]
-enum QueryRFCOMMChannelState {
+enum QueryServicesState {
     New,
     Connecting,
+    SendRequest,
     WaitForData,
     Done,
 }
 
 #[derive(Debug)]
-pub enum QueryRFCOMMChannelStatus {
+pub enum QueryServicesStatus {
     WaitReadable(unix::io::RawFd),
     WaitWritable(unix::io::RawFd),
-    Done(u8),
+    Done(Vec<BtServiceRecord>),
 }
 
+/// Retrieves all service records matching a service class UUID from a remote SDP server.
+///
+/// Talks the SDP client protocol directly over an L2CAP connection to the server.
 #[derive(Debug)]
-pub struct QueryRFCOMMChannel {
+pub struct QueryServices {
+    local_addr: BtAddr,
     addr: BtAddr,
-    session: *mut sdp_session_t,
-    state: QueryRFCOMMChannelState,
+    service: BtUuid,
+    socket: Option<UnixStream>,
+    state: QueryServicesState,
 
-    response: Option<Result<u8, BtError>>,
+    tid: u16,
+    continuation: Vec<u8>,
+    response: Vec<u8>,
 }
-impl QueryRFCOMMChannel {
-    pub fn new(addr: BtAddr) -> Self {
-        QueryRFCOMMChannel {
-            addr: addr,
-            session: ptr::null_mut(),
-            state: QueryRFCOMMChannelState::New,
-
-            response: None,
+impl QueryServices {
+    pub fn new(local_addr: BtAddr, addr: BtAddr, service: BtUuid) -> Self {
+        QueryServices {
+            local_addr,
+            addr,
+            service,
+            socket: None,
+            state: QueryServicesState::New,
+
+            tid: 0,
+            continuation: Vec::new(),
+            response: Vec::new(),
         }
     }
 
-    unsafe extern "C" fn notify_cb(_: u8, status: u16, rsp: *const u8, size: usize, this_ptr: *mut c_void) {
-        fn make_status_error(message: &str) -> BtError {
-            BtError::Desc(format!("sdp_service_search_attr_async(): Protocol error: {}",
-                                  message))
+    /// Runs the query over an already connected `socket`.
+    #[cfg(test)]
+    fn with_socket(socket: UnixStream, service: BtUuid) -> Self {
+        let mut query = QueryServices::new(BtAddr::any(), BtAddr::any(), service);
+        query.socket = Some(socket);
+        query.state = QueryServicesState::SendRequest;
+        query
+    }
+
+    fn connect(&mut self) -> Result<(), BtError> {
+        let fd = unsafe {
+            libc::socket(AF_BLUETOOTH,
+                         libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK,
+                         BtProtocolBlueZ::L2CAP as i32)
+        };
+        if fd < 0 {
+            return Err(create_error_from_last("Failed to create L2CAP socket for SDP"));
+        }
+        self.socket = Some(unsafe { UnixStream::from_raw_fd(fd) });
+
+        // Leaving the socket unbound lets the kernel choose the adapter
+        if self.local_addr != BtAddr::any() {
+            let local_address = sockaddr_l2::new(self.local_addr, 0);
+            if unsafe {
+                libc::bind(fd,
+                           ptr::addr_of!(local_address).cast(),
+                           size_of::<sockaddr_l2>() as u32)
+            } < 0 {
+                return Err(create_connect_error("Failed to bind() L2CAP socket for SDP to local adapter", nix::errno::errno()));
+            }
         }
 
-        let this = &mut *(this_ptr as *mut Self);
-
-        this.response = Some(match status {
-            0 => Self::parse_response(slice::from_raw_parts(rsp, size)),
-            
-            0x0001 => // SDP_INVALID_VERSION
-                Err(make_status_error("Invalid version")),
-            0x0002 => // SDP_INVALID_RECORD_HANDLE
-                Err(make_status_error("Invalid record handle")),
-            0x0003 => // SDP_INVALID_SYNTAX
-                Err(make_status_error("Invalid syntax")),
-            0x0004 => // SDP_INVALID_PDU_SIZE
-                Err(make_status_error("Invalid PDU size")),
-            0x0005 => // SDP_INVALID_CSTATE
-                Err(make_status_error("Invalid CState")),
-            _      =>
-                Err(create_error_from_errno(
-                        "sdp_service_search_attr_async(): Service record search failed",
-                        sdp_get_error(this.session)
-                ))
-        });
+        let full_address = sockaddr_l2::new(self.addr, SDP_PSM);
+        if unsafe {
+            libc::connect(fd,
+                          ptr::addr_of!(full_address).cast(),
+                          size_of::<sockaddr_l2>() as u32)
+        } < 0 && nix::Errno::last() != nix::Errno::EINPROGRESS {
+            return Err(create_connect_error("sdp_connect(): Bluetooth device not accessible", nix::errno::errno()));
+        }
+
+        Ok(())
     }
 
-    fn parse_response(response: &[u8]) -> Result<u8, BtError> {
-        let mut data_type: u8 = 0;
-        let mut seqlen: c_int = 0;
+    fn fd(&self) -> unix::io::RawFd {
+        self.socket.as_ref().expect("SDP socket not connected").as_raw_fd()
+    }
 
-        // Response is a sequence of sequence(s) for one or
-        // more data element sequence(s) representing services
-        // for which attributes are returned
-        let mut scanned = unsafe {
-            sdp_extract_seqtype(response.as_ptr(),
-                                response.len() as i32,
-                                &mut data_type,
-                                &mut seqlen)
-        };
+    pub fn advance(&mut self) -> Result<QueryServicesStatus, BtError> {
+        loop {
+            match self.state {
+                QueryServicesState::New => {
+                    self.connect()?;
 
-        let mut channel: Option<u8> = None;
-        if scanned > 0 && seqlen > 0 {
-            let mut pdata = unsafe { response.as_ptr().offset(scanned as isize) };
-            let mut pdata_len = (response.len() as i32) - scanned;
-
-            while scanned < (response.len() as i32) && pdata_len > 0 {
-                let mut record_size: c_int = 0;
-                let record = unsafe { sdp_extract_pdu(pdata, pdata_len, &mut record_size) };
-                if record.is_null() {
-                    return Err(BtError::Desc("sdp_extract_pdu() returned NULL during parsing".to_string()));
-                } else if record_size < 1 {
-                    unsafe { sdp_record_free(record) };
-                    break;
+                    self.state = QueryServicesState::Connecting;
+                    return Ok(QueryServicesStatus::WaitWritable(self.fd()));
                 }
 
-                scanned += record_size;
-                pdata = unsafe { pdata.offset(record_size as isize) };
-                pdata_len = pdata_len - record_size;
-
-                // get a list of the protocol sequences
-                let mut proto_list: *mut sdp_list_t = ptr::null_mut();
-                if unsafe { sdp_get_access_protos(record, &mut proto_list) } == 0 {
-                    let mut p = proto_list;
-
-                    while p != ptr::null_mut() {
-                        let mut pds: *mut sdp_list_t = unsafe { mem::transmute((*p).data) };
-
-                        // go through each protocol list of the protocol sequence
-                        while pds != ptr::null_mut() {
-
-                            // check the protocol attributes
-                            let mut d: *mut sdp_data_t = unsafe { mem::transmute((*pds).data) };
-                            let mut proto: Option<c_int> = None;
-                            while d != ptr::null_mut() {
-                                match SdpPdu::from_u8(unsafe { *d }.dtd)
-                                    .unwrap_or_else(|| /* something that does not do anything = */ SdpPdu::DataNil) {
-                                    SdpPdu::Uuid16 | SdpPdu::Uuid32 | SdpPdu::Uuid128 => {
-                                        proto = Some(unsafe { sdp_uuid_to_proto((*d).val.uuid()) });
-                                    }
-                                    SdpPdu::Uint8 => {
-                                        if proto == Some(SdpProtoUuid::Rfcomm as c_int) {
-                                            channel = Some(unsafe { *(*d).val.uint8() });
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                                d = unsafe { *d }.next;
-                            }
-
-                            pds = unsafe { *pds }.next;
-                        }
+                QueryServicesState::Connecting => {
+                    // Socket became writable – check whether the connection has actually been established
+                    let mut error: libc::c_int = 0;
+                    let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+                    if unsafe { libc::getsockopt(self.fd(), libc::SOL_SOCKET, libc::SO_ERROR, ptr::addr_of_mut!(error).cast(), &mut len) } < 0 {
+                        return Err(create_error_from_last("getsockopt() failed"));
+                    } else if error != 0 {
+                        return Err(create_connect_error("sdp_connect(): Bluetooth device not accessible", error));
+                    }
+
+                    self.state = QueryServicesState::SendRequest;
+                }
 
-                        unsafe { sdp_list_free(mem::transmute((*p).data), ptr::null()) };
-                        p = unsafe { *p }.next;
+                QueryServicesState::SendRequest => {
+                    let request = build_search_attr_request(self.tid, self.service, &self.continuation);
+                    match self.socket.as_ref().unwrap().write(&request) {
+                        Ok(_) => self.state = QueryServicesState::WaitForData,
+                        Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
+                            return Ok(QueryServicesStatus::WaitWritable(self.fd()));
+                        }
+                        Err(error) => {
+                            return Err(create_error_from_errno("SDP service search: Sending service record search request failed",
+                                                               error.raw_os_error().unwrap_or(0)));
+                        }
                     }
+                }
+
+                QueryServicesState::WaitForData => {
+                    let mut pdu = vec![0u8; SDP_MAX_PDU_SIZE];
+                    let size = match self.socket.as_ref().unwrap().read(&mut pdu) {
+                        Ok(0) => return Err(BtError::Desc("SDP service search: Connection closed by remote device".to_string())),
+                        Ok(size) => size,
+                        Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
+                            return Ok(QueryServicesStatus::WaitReadable(self.fd()));
+                        }
+                        Err(error) => {
+                            return Err(create_error_from_errno("SDP service search: Service record search failed",
+                                                               error.raw_os_error().unwrap_or(0)));
+                        }
+                    };
 
-                    unsafe { sdp_list_free(proto_list, ptr::null()) };
+                    self.continuation = {
+                        let (attribute_lists, continuation) = parse_search_attr_response(self.tid, &pdu[..size])?;
+                        // A remote server could keep sending continuations forever
+                        if self.response.len() + attribute_lists.len() > SDP_MAX_RESPONSE_SIZE {
+                            return Err(create_protocol_error("SDP service search: Response too large"));
+                        }
+                        self.response.extend_from_slice(attribute_lists);
+                        continuation.to_vec()
+                    };
+                    self.tid = self.tid.wrapping_add(1);
+
+                    if self.continuation.is_empty() {
+                        // Transaction completed – close session
+                        self.socket = None;
+                        self.state = QueryServicesState::Done;
+                        return Ok(QueryServicesStatus::Done(parse_response(&self.response)?));
+                    } else {
+                        // More data available, request the next part
+                        self.state = QueryServicesState::SendRequest;
+                    }
                 }
 
-                unsafe { sdp_record_free(record) };
+                QueryServicesState::Done => {
+                    panic!("Trying advance `QueryServices` from `Done` state");
+                }
             }
         }
+    }
+}
 
-        match channel {
-            Some(idx) => Ok(idx),
-            None => Err(BtError::Desc("No RFCOMM service on remote device".to_string())),
-        }
+
+#[derive(Debug)]
+pub enum QueryRFCOMMChannelStatus {
+    WaitReadable(unix::io::RawFd),
+    WaitWritable(unix::io::RawFd),
+    Done(u8),
+}
+
+/// Determines the RFCOMM channel of a service on a remote device.
+#[derive(Debug)]
+pub struct QueryRFCOMMChannel {
+    query: QueryServices,
+}
+impl QueryRFCOMMChannel {
+    pub fn new(local_addr: BtAddr, addr: BtAddr, service: BtUuid) -> Self {
+        QueryRFCOMMChannel { query: QueryServices::new(local_addr, addr, service) }
+    }
+
+    /// Runs the query over an already connected `socket`.
+    #[cfg(test)]
+    pub fn with_socket(socket: UnixStream, service: BtUuid) -> Self {
+        QueryRFCOMMChannel { query: QueryServices::with_socket(socket, service) }
     }
 
     pub fn advance(&mut self) -> Result<QueryRFCOMMChannelStatus, BtError> {
-        macro_rules! get_fd {
-            () => {
-                {
-                    assert!(!self.session.is_null());
-                    unsafe { (*self.session).sock }
+        match self.query.advance()? {
+            QueryServicesStatus::WaitReadable(fd) => Ok(QueryRFCOMMChannelStatus::WaitReadable(fd)),
+            QueryServicesStatus::WaitWritable(fd) => Ok(QueryRFCOMMChannelStatus::WaitWritable(fd)),
+            QueryServicesStatus::Done(records) => {
+                match records.iter().filter_map(|record| record.rfcomm_channel()).next() {
+                    Some(channel) => Ok(QueryRFCOMMChannelStatus::Done(channel)),
+                    None => Err(BtError::Kind(BtErrorKind::ServiceNotFound, None, "No RFCOMM service on remote device".to_string())),
                 }
             }
-        };
+        }
+    }
+}
 
-        match &self.state {
-            &QueryRFCOMMChannelState::New => {
-                let flags = SdpConnectFlags::NonBlocking as u32;
-                self.session = unsafe { sdp_connect(&BtAddr::any(), &self.addr, flags) };
-                if self.session == ptr::null_mut() {
-                    return Err(create_error_from_last("sdp_connect(): Bluetooth device not accessible"));
-                }
 
-                self.state = QueryRFCOMMChannelState::Connecting;
-                Ok(QueryRFCOMMChannelStatus::WaitWritable(get_fd!()))
+#[derive(Debug)]
+pub struct BtServiceBrowse {
+    pollfd: unix::io::RawFd,
+    query: QueryServices,
+    records: Option<Vec<BtServiceRecord>>,
+}
+impl BtServiceBrowse {
+    pub fn new(local_addr: BtAddr, addr: BtAddr) -> Self {
+        BtServiceBrowse {
+            pollfd: 0,
+            query: QueryServices::new(local_addr.convert_host_byteorder(),
+                                      addr.convert_host_byteorder(),
+                                      BtUuid::Uuid16(SdpServiceClass::PublicBrowseGroup as u16)),
+            records: None,
+        }
+    }
+}
+
+impl Advance for BtServiceBrowse {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        match self.query.advance()? {
+            QueryServicesStatus::WaitReadable(fd) => {
+                self.pollfd = fd;
+                Ok(BtAsync::WaitFor(self, Interest::READABLE))
+            }
+            QueryServicesStatus::WaitWritable(fd) => {
+                self.pollfd = fd;
+                Ok(BtAsync::WaitFor(self, Interest::WRITABLE))
+            }
+            QueryServicesStatus::Done(records) => {
+                self.records = Some(records);
+                Ok(BtAsync::Done)
             }
+        }
+    }
 
-            &QueryRFCOMMChannelState::Connecting => {
-                // specify the UUID of the application we're searching for
-                let mut service_uuid = uuid_t::default();
-                unsafe { sdp_uuid16_create(&mut service_uuid, SdpProfile::SerialPort as u16) };
-                let search_list = unsafe { sdp_list_append(ptr::null_mut(), mem::transmute(&mut service_uuid)) };
-
-                // specify that we want a list of all the matching applications' attributes
-                let mut range = 0x0000FFFFu32;
-                let attrid_list = unsafe { sdp_list_append(ptr::null_mut(), mem::transmute(&mut range)) };
-
-                // register for notification once all data has been parsed
-                let this_ptr: *mut Self = self;
-                unsafe { sdp_set_notify(self.session, Some(Self::notify_cb), this_ptr as *mut c_void) };
-
-                // get a list of service records that have the serial port UUID
-                let result = unsafe {
-                    let status = sdp_service_search_attr_async(self.session,
-                                                               search_list,
-                                                               SdpAttrReqType::Range,
-                                                               attrid_list);
-                    if status < 0 {
-                        Err(create_error_from_last("sdp_service_search_attr_async(): Sending service record search request failed"))
-                    } else {
-                        Ok(())
-                    }
-                };
+    fn pollfd(&self) -> unix::io::RawFd {
+        self.pollfd
+    }
+}
 
-                // make sure data is freed before acting upon the result of the previous operation
-                unsafe { sdp_list_free(search_list, ptr::null()) };
-                unsafe { sdp_list_free(attrid_list, ptr::null()) };
+impl ServiceBrowse for BtServiceBrowse {
+    fn take_records(&mut self) -> Option<Vec<BtServiceRecord>> {
+        self.records.take()
+    }
+}
 
-                // quit if sending service request failed
-                try!(result);
+impl mio::event::Source for BtServiceBrowse {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).register(registry, token, interests)
+    }
 
-                self.state = QueryRFCOMMChannelState::WaitForData;
-                Ok(QueryRFCOMMChannelStatus::WaitReadable(get_fd!()))
-            }
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).reregister(registry, token, interests)
+    }
 
-            &QueryRFCOMMChannelState::WaitForData => {
-                let status = unsafe { sdp_process(self.session) };
-                if status < 0 {
-                    // Transaction completed – parsing function should have already been called
-                    assert!(self.response.is_some());
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).deregister(registry)
+    }
+}
 
-                    // Unregister callback function
-                    unsafe { sdp_set_notify(self.session, None, ptr::null_mut()) };
 
-                    // Close session
-                    if unsafe { sdp_close(self.session) } < 0 {
-                        return Err(create_error_from_last("sdp_close()"));
-                    }
+/// A service record registered with the local SDP server through its UNIX socket.
+///
+/// BlueZ also removes the record once the socket gets closed, so it is kept open until the
+/// registration is dropped.
+#[derive(Debug)]
+pub struct BtServiceRegistration {
+    socket: UnixStream,
+    handle: u32,
+    tid: u16,
+}
+impl BtServiceRegistration {
+    pub fn register(record: &BtServiceRecord) -> Result<BtServiceRegistration, BtError> {
+        BtServiceRegistration::register_at(SDP_UNIX_PATH, record)
+    }
 
-                    self.state = QueryRFCOMMChannelState::Done;
-                    match self.response.take().unwrap() {
-                        Ok(channel) => Ok(QueryRFCOMMChannelStatus::Done(channel)),
-                        Err(error) => Err(error),
-                    }
-                } else {
-                    // Transaction ongoing
-                    Ok(QueryRFCOMMChannelStatus::WaitReadable(get_fd!()))
+    fn register_at<P: AsRef<Path>>(path: P, record: &BtServiceRecord) -> Result<BtServiceRegistration, BtError> {
+        let socket = UnixStream::connect(path).map_err(|error| {
+            let errno = error.raw_os_error().unwrap_or(0);
+            match errno {
+                // BlueZ 5 only offers the socket in compatibility mode
+                libc::ENOENT | libc::ECONNREFUSED => {
+                    create_error_from_errno("Failed to connect to local SDP server, bluetoothd needs to run with --compat",
+                                            errno)
                 }
+                _ => create_error_from_errno("Failed to connect to local SDP server", errno),
             }
+        })?;
+        BtServiceRegistration::register_with(socket, record)
+    }
+
+    fn register_with(socket: UnixStream, record: &BtServiceRecord) -> Result<BtServiceRegistration, BtError> {
+        // A stuck server must not block the thread dropping the registration forever
+        socket.set_read_timeout(Some(SDP_UNIX_TIMEOUT))
+                   .and_then(|()| socket.set_write_timeout(Some(SDP_UNIX_TIMEOUT)))
+                   .map_err(|error| {
+                       create_error_from_errno("Failed to configure local SDP server socket", error.raw_os_error().unwrap_or(0))
+                   })?;
+
+        let mut registration = BtServiceRegistration {
+            socket,
+            handle: 0,
+            tid: 0,
+        };
+
+        // No flags, record is registered for all local adapters
+        let mut params = vec![0x00];
+        build_service_record(record).encode(&mut params);
+
+        let response = registration.transact(SdpPduId::ServiceRegisterRequest, SdpPduId::ServiceRegisterResponse, &params)?;
+        if response.len() < 4 {
+            return Err(create_protocol_error("Service registration: PDU too short"));
+        }
+        registration.handle = response[..4].iter().fold(0, |handle, &byte| (handle << 8) | byte as u32);
+        Ok(registration)
+    }
+
+    fn unregister(&mut self) -> Result<(), BtError> {
+        let handle = self.handle;
+        let params = [(handle >> 24) as u8, (handle >> 16) as u8, (handle >> 8) as u8, handle as u8];
+        self.transact(SdpPduId::ServiceRemoveRequest, SdpPduId::ServiceRemoveResponse, &params)?;
+        Ok(())
+    }
 
-            &QueryRFCOMMChannelState::Done => {
-                panic!("Trying advance `QueryRFCOMMChannel` from `Done` state");
+    /// Sends a request to the local SDP server and returns the parameters of the response.
+    fn transact(&mut self, request_id: SdpPduId, response_id: SdpPduId, params: &[u8]) -> Result<Vec<u8>, BtError> {
+        fn io_error(error: std::io::Error) -> BtError {
+            create_error_from_errno("Service registration: Communication with local SDP server failed",
+                                    error.raw_os_error().unwrap_or(0))
+        }
+
+        let tid = self.tid;
+        self.tid = self.tid.wrapping_add(1);
+        self.socket.write_all(&build_pdu(request_id, tid, params)).map_err(io_error)?;
+
+        let mut header = [0u8; 5];
+        self.socket.read_exact(&mut header).map_err(io_error)?;
+        let mut response = vec![0u8; ((header[3] as usize) << 8) | header[4] as usize];
+        self.socket.read_exact(&mut response).map_err(io_error)?;
+
+        if ((header[1] as u16) << 8) | header[2] as u16 != tid {
+            Err(create_protocol_error("Service registration: Unexpected transaction ID"))
+        } else if header[0] == SdpPduId::ErrorResponse as u8 && response.len() < 2 {
+            Err(create_protocol_error("Service registration: PDU too short"))
+        } else if header[0] == SdpPduId::ErrorResponse as u8 {
+            let code = ((response[0] as u16) << 8) | response[1] as u16;
+            Err(create_error_response_error("Service registration: Local SDP server rejected the request", code))
+        } else if header[0] != response_id as u8 {
+            Err(create_protocol_error("Service registration: Unexpected PDU"))
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+impl ServiceRegistration for BtServiceRegistration {
+    fn handle(&self) -> u32 {
+        self.handle
+    }
+}
+
+impl Drop for BtServiceRegistration {
+    fn drop(&mut self) {
+        // Closing the socket removes the record as well, so errors can be ignored
+        let _ = self.unregister();
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bluetooth::{BtServiceRecord, BtUuid};
+    use std::io::{Read, Write};
+    use std::thread;
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::net::UnixStream;
+
+    fn seqpacket_pair() -> (UnixStream, UnixStream) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK, 0, fds.as_mut_ptr()) }, 0);
+        unsafe { (UnixStream::from_raw_fd(fds[0]), UnixStream::from_raw_fd(fds[1])) }
+    }
+
+    /// Attribute lists of a single SPP record on RFCOMM channel 3.
+    pub(crate) fn spp_attribute_lists() -> Vec<u8> {
+        SdpValue::Sequence(vec![SdpValue::Sequence(vec![SdpValue::Uint16(0x0000),
+                                                        SdpValue::Uint32(0x00010001),
+                                                        SdpValue::Uint16(0x0001),
+                                                        SdpValue::Sequence(vec![SdpValue::Uuid(BtUuid::serial_port())]),
+                                                        SdpValue::Uint16(0x0004),
+                                                        SdpValue::Sequence(vec![
+                        SdpValue::Sequence(vec![SdpValue::Uuid(BtUuid::Uuid16(0x0100))]),
+                        SdpValue::Sequence(vec![SdpValue::Uuid(BtUuid::Uuid16(0x0003)), SdpValue::Uint8(3)]),
+                    ])])])
+            .to_bytes()
+    }
+
+    pub(crate) fn response_pdu(tid: u16, attribute_lists: &[u8], continuation: &[u8]) -> Vec<u8> {
+        let params_len = 2 + attribute_lists.len() + 1 + continuation.len();
+        let mut pdu = vec![0x07, (tid >> 8) as u8, tid as u8, (params_len >> 8) as u8, params_len as u8];
+        pdu.push((attribute_lists.len() >> 8) as u8);
+        pdu.push(attribute_lists.len() as u8);
+        pdu.extend_from_slice(attribute_lists);
+        pdu.push(continuation.len() as u8);
+        pdu.extend_from_slice(continuation);
+        pdu
+    }
+
+    pub(crate) fn receive_request(server: &mut UnixStream) -> Vec<u8> {
+        let mut buf = [0u8; 1024];
+        let size = server.read(&mut buf).unwrap();
+        buf[..size].to_vec()
+    }
+
+    #[test]
+    fn builds_search_attr_request() {
+        assert_eq!(build_search_attr_request(0x0102, BtUuid::serial_port(), &[0xAA]),
+                   vec![0x06, 0x01, 0x02, 0x00, 0x10, // header
+                        0x35, 0x03, 0x19, 0x11, 0x01, // ServiceSearchPattern
+                        0xFF, 0xFF, // MaximumAttributeByteCount
+                        0x35, 0x05, 0x0A, 0x00, 0x00, 0xFF, 0xFF, // AttributeIDList
+                        0x01, 0xAA /* ContinuationState */]);
+    }
+
+    #[test]
+    fn rejects_invalid_responses() {
+        assert!(parse_search_attr_response(0, &[0x07, 0x00, 0x00]).is_err());
+        assert!(parse_search_attr_response(1, &response_pdu(0, &[], &[])).is_err());
+        assert!(parse_search_attr_response(0, &[0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x03]).is_err());
+        assert!(parse_search_attr_response(0, &[0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0x05, 0x00]).is_err());
+        assert_eq!(parse_search_attr_response(7, &response_pdu(7, &[1, 2], &[3])).unwrap(),
+                   (&[1u8, 2][..], &[3u8][..]));
+    }
+
+    #[test]
+    fn queries_fake_sdp_server() {
+        let (client, mut server) = seqpacket_pair();
+        let mut query = QueryServices::with_socket(client, BtUuid::serial_port());
+
+        match query.advance().unwrap() {
+            QueryServicesStatus::WaitReadable(_) => {}
+            status => panic!("Unexpected status {:?}", status),
+        }
+        let request = receive_request(&mut server);
+        assert_eq!(request, build_search_attr_request(0, BtUuid::serial_port(), &[]));
+
+        // Split response over two PDUs to exercise the continuation state handling
+        let attribute_lists = spp_attribute_lists();
+        let (first, second) = attribute_lists.split_at(10);
+        server.write_all(&response_pdu(0, first, &[0x42, 0x43])).unwrap();
+
+        match query.advance().unwrap() {
+            QueryServicesStatus::WaitReadable(_) => {}
+            status => panic!("Unexpected status {:?}", status),
+        }
+        let request = receive_request(&mut server);
+        assert_eq!(request, build_search_attr_request(1, BtUuid::serial_port(), &[0x42, 0x43]));
+        server.write_all(&response_pdu(1, second, &[])).unwrap();
+
+        match query.advance().unwrap() {
+            QueryServicesStatus::Done(records) => {
+                assert_eq!(records.len(), 1);
+                assert_eq!(records[0].handle, 0x00010001);
+                assert_eq!(records[0].rfcomm_channel(), Some(3));
             }
+            status => panic!("Unexpected status {:?}", status),
         }
     }
+
+    #[test]
+    fn limits_response_size() {
+        let (client, mut server) = seqpacket_pair();
+        let mut query = QueryServices::with_socket(client, BtUuid::serial_port());
+        let attribute_lists = vec![0u8; 0x8000];
+
+        for tid in 0.. {
+            match query.advance() {
+                Ok(QueryServicesStatus::WaitReadable(_)) => {}
+                Err(error) => {
+                    assert_eq!(error.kind(), BtErrorKind::SdpProtocol(None));
+                    assert_eq!(tid as usize, SDP_MAX_RESPONSE_SIZE / attribute_lists.len() + 1);
+                    break;
+                }
+                status => panic!("Unexpected status {:?}", status),
+            }
+            receive_request(&mut server);
+            server.write_all(&response_pdu(tid, &attribute_lists, &[0x01])).unwrap();
+        }
+    }
+
+    #[test]
+    fn registers_record_with_fake_sdp_server() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let record = BtServiceRecord::rfcomm(BtUuid::serial_port(), 1);
+
+        let server_thread = thread::spawn(move || {
+            let mut request = vec![0u8; 5];
+            server.read_exact(&mut request).unwrap();
+            let mut params = vec![0u8; ((request[3] as usize) << 8) | request[4] as usize];
+            server.read_exact(&mut params).unwrap();
+            assert_eq!(request[0], 0x75);
+            assert_eq!(params[0], 0x00);
+            assert_eq!(&params[1..], &build_service_record(&BtServiceRecord::rfcomm(BtUuid::serial_port(), 1)).to_bytes()[..]);
+            server.write_all(&[0x76, request[1], request[2], 0x00, 0x04, 0x00, 0x01, 0x00, 0x07]).unwrap();
+
+            // Record gets removed on drop
+            let mut request = vec![0u8; 9];
+            server.read_exact(&mut request).unwrap();
+            assert_eq!(request, vec![0x73, 0x00, 0x01, 0x00, 0x04, 0x00, 0x01, 0x00, 0x07]);
+            server.write_all(&[0x74, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00]).unwrap();
+        });
+
+        let registration = BtServiceRegistration::register_with(client, &record).unwrap();
+        assert_eq!(registration.handle(), 0x00010007);
+        assert_eq!(registration.socket.read_timeout().unwrap(), Some(SDP_UNIX_TIMEOUT));
+        drop(registration);
+
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn reports_rejected_registration() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+
+        let server_thread = thread::spawn(move || {
+            let mut request = vec![0u8; 5];
+            server.read_exact(&mut request).unwrap();
+            let mut params = vec![0u8; ((request[3] as usize) << 8) | request[4] as usize];
+            server.read_exact(&mut params).unwrap();
+            server.write_all(&[0x01, request[1], request[2], 0x00, 0x02, 0x00, 0x06]).unwrap();
+        });
+
+        let error = BtServiceRegistration::register_with(client, &BtServiceRecord::rfcomm(BtUuid::serial_port(), 1)).unwrap_err();
+        assert_eq!(error.kind(), BtErrorKind::SdpProtocol(Some(0x0006)));
+
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn explains_missing_sdp_server_socket() {
+        let path = std::env::temp_dir().join(format!("bt-no-sdp-{}", std::process::id()));
+        let error = BtServiceRegistration::register_at(&path, &BtServiceRecord::rfcomm(BtUuid::serial_port(), 1)).unwrap_err();
+        assert_eq!(error.errno(), Some(libc::ENOENT as u32));
+        assert!(error.to_string().contains("--compat"));
+    }
+
+    #[test]
+    fn reports_sdp_error_response() {
+        let (client, mut server) = seqpacket_pair();
+        let mut query = QueryServices::with_socket(client, BtUuid::serial_port());
+
+        query.advance().unwrap();
+        receive_request(&mut server);
+        server.write_all(&[0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x03]).unwrap();
+
+        let error = query.advance().unwrap_err();
+        assert_eq!(error.kind(), BtErrorKind::SdpProtocol(Some(0x0003)));
+        assert_eq!(error.errno(), None);
+    }
 }
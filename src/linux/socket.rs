@@ -2,16 +2,19 @@ extern crate libc;
 extern crate nix;
 extern crate mio;
 
-use bluetooth::{BtAddr, BtAsync, BtError, BtProtocol};
+use backend::{Accepted, Listener, Socket, SocketConnect};
+use bluetooth::{Advance, BtAddr, BtAsync, BtError, BtErrorKind, BtProtocol, BtSocketAddr, BtUuid, L2capMode, LinkMode, SecurityLevel};
 use super::sdp::{QueryRFCOMMChannel, QueryRFCOMMChannelStatus};
 use std;
 use std::io::{Read, Write};
-use std::mem;
+use std::mem::{self, size_of};
 use std::error::Error;
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use mio::{Poll, Ready};
+use std::ptr;
+use std::time::Duration;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use mio::{Interest, Registry};
 use std::os::unix::net::UnixStream;
-use mio::unix::EventedFd;
+use mio::unix::SourceFd;
 
 
 
@@ -24,9 +27,28 @@ pub fn create_error_from_last(message: &str) -> BtError {
     create_error_from_errno(message, nix::errno::errno())
 }
 
+/// Creates an error of a known kind from an error code.
+pub fn create_error_of_kind(kind: BtErrorKind, message: &str, errno: i32) -> BtError {
+    BtError::Kind(kind, Some(errno as u32), format!("{:}: {:}", message, nix::Errno::from_i32(errno).desc()))
+}
+
+/// Creates an error for a failed `connect()` or `bind()`, whose error code tells the kind of the
+/// error.
+pub fn create_connect_error(message: &str, errno: i32) -> BtError {
+    let kind = match errno {
+        libc::ENODEV => BtErrorKind::AdapterNotFound,
+        libc::EPERM | libc::EACCES => BtErrorKind::PermissionDenied,
+        libc::EHOSTDOWN | libc::EHOSTUNREACH => BtErrorKind::HostDown,
+        libc::ECONNREFUSED => BtErrorKind::ConnectionRefused,
+        libc::ETIMEDOUT => BtErrorKind::Timeout,
+        _ => return create_error_from_errno(message, errno),
+    };
+    create_error_of_kind(kind, message, errno)
+}
 
 
-const AF_BLUETOOTH: i32 = 31;
+
+pub const AF_BLUETOOTH: i32 = 31;
 
 const BTPROTO_L2CAP: isize = 0;
 const BTPROTO_HCI: isize = 1;
@@ -38,7 +60,7 @@ const BTPROTO_HIDP: isize = 6;
 const BTPROTO_AVDTP: isize = 7;
 
 #[allow(dead_code)]
-enum BtProtocolBlueZ {
+pub enum BtProtocolBlueZ {
     L2CAP = BTPROTO_L2CAP,
     HCI = BTPROTO_HCI,
     SCO = BTPROTO_SCO,
@@ -59,73 +81,580 @@ struct sockaddr_rc {
     rc_channel: u8,
 }
 
+#[repr(C)]
+#[derive(Copy, Debug, Clone)]
+pub struct sockaddr_l2 {
+    pub l2_family: libc::sa_family_t,
+    pub l2_psm: u16,
+    pub l2_bdaddr: BtAddr,
+    pub l2_cid: u16,
+    pub l2_bdaddr_type: u8,
+}
+
+impl sockaddr_l2 {
+    /// Creates the address of `psm` on the device `addr`, which is expected in host byte order.
+    pub fn new(addr: BtAddr, psm: u16) -> sockaddr_l2 {
+        sockaddr_l2 {
+            l2_family: AF_BLUETOOTH as u16,
+            l2_psm: psm.to_le(),
+            l2_bdaddr: addr,
+            l2_cid: 0,
+            l2_bdaddr_type: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Debug, Clone, Default)]
+struct l2cap_options {
+    omtu: u16,
+    imtu: u16,
+    flush_to: u16,
+    mode: u8,
+    fcs: u8,
+    max_tx: u8,
+    txwin_size: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Debug, Clone)]
+struct bt_security {
+    level: u8,
+    key_size: u8,
+}
+
+const SOL_L2CAP: libc::c_int = 6;
+const L2CAP_OPTIONS: libc::c_int = 0x01;
+const L2CAP_LM: libc::c_int = 0x03;
+
+const SOL_RFCOMM: libc::c_int = 18;
+const RFCOMM_LM: libc::c_int = 0x03;
+
+const SOL_BLUETOOTH: libc::c_int = 274;
+const BT_SECURITY: libc::c_int = 4;
+const BT_DEFER_SETUP: libc::c_int = 7;
+
+// Link mode flags, identical for `L2CAP_LM` and `RFCOMM_LM`
+const LM_MASTER: u32 = 0x0001;
+const LM_AUTH: u32 = 0x0002;
+const LM_ENCRYPT: u32 = 0x0004;
+const LM_TRUSTED: u32 = 0x0008;
+const LM_RELIABLE: u32 = 0x0010;
+const LM_SECURE: u32 = 0x0020;
+
+/// Reads the socket option `name` on `level`, `message` describes a failure.
+fn get_option<T>(fd: RawFd, level: libc::c_int, name: libc::c_int, message: &str) -> Result<T, BtError> {
+    let mut value: T = unsafe { mem::zeroed() };
+    let mut len = size_of::<T>() as libc::socklen_t;
+    if unsafe { libc::getsockopt(fd, level, name, ptr::addr_of_mut!(value).cast(), &mut len) } < 0 {
+        Err(create_error_from_last(message))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Changes the socket option `name` on `level`, `message` describes a failure.
+fn set_option<T>(fd: RawFd, level: libc::c_int, name: libc::c_int, value: &T, message: &str) -> Result<(), BtError> {
+    if unsafe { libc::setsockopt(fd, level, name, ptr::addr_of!(*value).cast(), size_of::<T>() as u32) } < 0 {
+        Err(create_error_from_last(message))
+    } else {
+        Ok(())
+    }
+}
+
+fn get_l2cap_options(fd: RawFd) -> Result<l2cap_options, BtError> {
+    get_option(fd, SOL_L2CAP, L2CAP_OPTIONS, "getsockopt(L2CAP_OPTIONS) failed")
+}
+
+fn set_l2cap_options(fd: RawFd, options: &l2cap_options) -> Result<(), BtError> {
+    set_option(fd, SOL_L2CAP, L2CAP_OPTIONS, options, "setsockopt(L2CAP_OPTIONS) failed")
+}
+
+/// Reads the security level of a connection.
+fn security(fd: RawFd) -> Result<SecurityLevel, BtError> {
+    let security: bt_security = get_option(fd, SOL_BLUETOOTH, BT_SECURITY, "getsockopt(BT_SECURITY) failed")?;
+    Ok(match security.level {
+        0 => SecurityLevel::Sdp,
+        1 => SecurityLevel::Low,
+        2 => SecurityLevel::Medium,
+        3 => SecurityLevel::High,
+        _ => SecurityLevel::Fips,
+    })
+}
+
+/// Changes the security level a connection requires.
+fn set_security(fd: RawFd, level: SecurityLevel) -> Result<(), BtError> {
+    let security = bt_security {
+        level: level as u8,
+        key_size: 0,
+    };
+    set_option(fd, SOL_BLUETOOTH, BT_SECURITY, &security, "setsockopt(BT_SECURITY) failed")
+}
+
+/// Returns the level and name of the link mode option of `proto`.
+fn link_mode_option(proto: BtProtocol) -> (libc::c_int, libc::c_int) {
+    match proto {
+        BtProtocol::RFCOMM => (SOL_RFCOMM, RFCOMM_LM),
+        BtProtocol::L2CAP(_) => (SOL_L2CAP, L2CAP_LM),
+    }
+}
+
+/// Reads the link mode flags of a connection.
+fn link_mode(fd: RawFd, proto: BtProtocol) -> Result<LinkMode, BtError> {
+    let (level, name) = link_mode_option(proto);
+    let flags: u32 = get_option(fd, level, name, "getsockopt(LM) failed")?;
+    Ok(LinkMode {
+        master: flags & LM_MASTER != 0,
+        auth: flags & LM_AUTH != 0,
+        encrypt: flags & LM_ENCRYPT != 0,
+        trusted: flags & LM_TRUSTED != 0,
+        reliable: flags & LM_RELIABLE != 0,
+        secure: flags & LM_SECURE != 0,
+    })
+}
+
+/// Changes the link mode flags of a connection.
+fn set_link_mode(fd: RawFd, proto: BtProtocol, mode: LinkMode) -> Result<(), BtError> {
+    let (level, name) = link_mode_option(proto);
+    let flags = [(mode.master, LM_MASTER),
+                 (mode.auth, LM_AUTH),
+                 (mode.encrypt, LM_ENCRYPT),
+                 (mode.trusted, LM_TRUSTED),
+                 (mode.reliable, LM_RELIABLE),
+                 (mode.secure, LM_SECURE)]
+        .iter()
+        .filter(|&&(enabled, _)| enabled)
+        .fold(0u32, |flags, &(_, flag)| flags | flag);
+    set_option(fd, level, name, &flags, "setsockopt(LM) failed")
+}
+
+fn defer_setup(fd: RawFd) -> Result<bool, BtError> {
+    let defer: u32 = get_option(fd, SOL_BLUETOOTH, BT_DEFER_SETUP, "getsockopt(BT_DEFER_SETUP) failed")?;
+    Ok(defer != 0)
+}
+
+fn set_defer_setup(fd: RawFd, defer: bool) -> Result<(), BtError> {
+    set_option(fd, SOL_BLUETOOTH, BT_DEFER_SETUP, &(defer as u32), "setsockopt(BT_DEFER_SETUP) failed")
+}
+
+/// Reads the size of the send (`SO_SNDBUF`) or receive (`SO_RCVBUF`) buffer.
+fn buffer_size(fd: RawFd, name: libc::c_int) -> Result<usize, BtError> {
+    let size: libc::c_int = get_option(fd, libc::SOL_SOCKET, name, "getsockopt(SO_SNDBUF/SO_RCVBUF) failed")?;
+    Ok(size as usize)
+}
+
+/// Changes the size of the send (`SO_SNDBUF`) or receive (`SO_RCVBUF`) buffer.
+fn set_buffer_size(fd: RawFd, name: libc::c_int, size: usize) -> Result<(), BtError> {
+    let size = std::cmp::min(size, libc::c_int::MAX as usize) as libc::c_int;
+    set_option(fd, libc::SOL_SOCKET, name, &size, "setsockopt(SO_SNDBUF/SO_RCVBUF) failed")
+}
+
+fn linger(fd: RawFd) -> Result<Option<Duration>, BtError> {
+    let linger: libc::linger = get_option(fd, libc::SOL_SOCKET, libc::SO_LINGER, "getsockopt(SO_LINGER) failed")?;
+    if linger.l_onoff == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Duration::from_secs(linger.l_linger as u64)))
+    }
+}
+
+fn set_linger(fd: RawFd, timeout: Option<Duration>) -> Result<(), BtError> {
+    let linger = libc::linger {
+        l_onoff: timeout.is_some() as libc::c_int,
+        l_linger: timeout.map_or(0, |timeout| std::cmp::min(timeout.as_secs(), libc::c_int::MAX as u64) as libc::c_int),
+    };
+    set_option(fd, libc::SOL_SOCKET, libc::SO_LINGER, &linger, "setsockopt(SO_LINGER) failed")
+}
+
+/// Reads the incoming and outgoing MTU of an L2CAP socket.
+fn l2cap_mtu(fd: RawFd) -> Result<(u16, u16), BtError> {
+    let options = get_l2cap_options(fd)?;
+    Ok((options.imtu, options.omtu))
+}
+
+/// Changes the incoming and/or outgoing MTU of an L2CAP socket.
+fn set_l2cap_mtu(fd: RawFd, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+    let mut options = get_l2cap_options(fd)?;
+    if let Some(input) = input {
+        options.imtu = input;
+    }
+    if let Some(output) = output {
+        options.omtu = output;
+    }
+    set_l2cap_options(fd, &options)
+}
+
+/// Creates a Bluetooth socket for `proto`.
+fn create_socket(proto: BtProtocol, flags: libc::c_int) -> Result<RawFd, BtError> {
+    let fd = match proto {
+        BtProtocol::RFCOMM => unsafe { libc::socket(AF_BLUETOOTH, libc::SOCK_STREAM | flags, BtProtocolBlueZ::RFCOMM as i32) },
+        BtProtocol::L2CAP(L2capMode::SeqPacket) => unsafe {
+            libc::socket(AF_BLUETOOTH, libc::SOCK_SEQPACKET | flags, BtProtocolBlueZ::L2CAP as i32)
+        },
+        BtProtocol::L2CAP(L2capMode::Stream) => unsafe { libc::socket(AF_BLUETOOTH, libc::SOCK_STREAM | flags, BtProtocolBlueZ::L2CAP as i32) },
+    };
+    if fd < 0 { Err(create_error_from_last("Failed to create Bluetooth socket")) } else { Ok(fd) }
+}
+
+/// Determines the protocol of a Bluetooth socket created elsewhere, assuming RFCOMM if the kernel
+/// does not tell.
+fn socket_protocol(fd: RawFd) -> BtProtocol {
+    let get_option = |option: libc::c_int| {
+        let mut value: libc::c_int = 0;
+        let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+        if unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, option, ptr::addr_of_mut!(value).cast(), &mut len) } < 0 { None } else { Some(value) }
+    };
+
+    match (get_option(libc::SO_PROTOCOL), get_option(libc::SO_TYPE)) {
+        (Some(protocol), Some(libc::SOCK_SEQPACKET)) if protocol == BtProtocolBlueZ::L2CAP as i32 => BtProtocol::L2CAP(L2capMode::SeqPacket),
+        (Some(protocol), Some(_)) if protocol == BtProtocolBlueZ::L2CAP as i32 => BtProtocol::L2CAP(L2capMode::Stream),
+        _ => BtProtocol::RFCOMM,
+    }
+}
+
+/// Lets `query` fill in an address of `proto`, like `getsockname()`, `getpeername()` and
+/// `accept()` do. Returns the result of `query` together with the address.
+fn query_addr<F>(proto: BtProtocol, query: F) -> (libc::c_int, BtSocketAddr)
+    where F: FnOnce(*mut libc::sockaddr, &mut libc::socklen_t) -> libc::c_int
+{
+    match proto {
+        BtProtocol::RFCOMM => {
+            let mut address = sockaddr_rc {
+                rc_family: AF_BLUETOOTH as u16,
+                rc_bdaddr: BtAddr::any(),
+                rc_channel: 0,
+            };
+            let mut socklen = size_of::<sockaddr_rc>() as libc::socklen_t;
+            let result = query(ptr::addr_of_mut!(address).cast(), &mut socklen);
+            (result,
+             BtSocketAddr {
+                 addr: address.rc_bdaddr.convert_host_byteorder(),
+                 channel: address.rc_channel as u16,
+             })
+        }
+        BtProtocol::L2CAP(_) => {
+            let mut address = sockaddr_l2::new(BtAddr::any(), 0);
+            let mut socklen = size_of::<sockaddr_l2>() as libc::socklen_t;
+            let result = query(ptr::addr_of_mut!(address).cast(), &mut socklen);
+            (result,
+             BtSocketAddr {
+                 addr: address.l2_bdaddr.convert_host_byteorder(),
+                 channel: u16::from_le(address.l2_psm),
+             })
+        }
+    }
+}
+
+/// Reads the local (`getsockname()`) or the remote (`getpeername()`) address of `fd`.
+fn socket_addr(fd: RawFd, proto: BtProtocol, peer: bool) -> Result<BtSocketAddr, BtError> {
+    let (result, address) = query_addr(proto, |address, socklen| unsafe {
+        if peer { libc::getpeername(fd, address, socklen) } else { libc::getsockname(fd, address, socklen) }
+    });
+    if result < 0 {
+        Err(create_error_from_last(if peer { "getpeername() failed" } else { "getsockname() failed" }))
+    } else {
+        Ok(address)
+    }
+}
+
+/// Binds `fd` to the RFCOMM channel or L2CAP PSM `port` on the local adapter `addr`, which is
+/// expected in host byte order.
+fn bind_socket(fd: RawFd, proto: BtProtocol, addr: BtAddr, port: u16) -> Result<(), BtError> {
+    let result = match proto {
+        BtProtocol::RFCOMM => {
+            let full_address: sockaddr_rc = sockaddr_rc {
+                rc_family: AF_BLUETOOTH as u16,
+                rc_bdaddr: addr,
+                rc_channel: port as u8,
+            };
+            unsafe { libc::bind(fd, ptr::addr_of!(full_address).cast(), size_of::<sockaddr_rc>() as u32) }
+        }
+        BtProtocol::L2CAP(_) => {
+            let full_address = sockaddr_l2::new(addr, port);
+            unsafe { libc::bind(fd, ptr::addr_of!(full_address).cast(), size_of::<sockaddr_l2>() as u32) }
+        }
+    };
+
+    if result < 0 {
+        Err(create_connect_error("Failed to bind() Bluetooth socket", nix::errno::errno()))
+    } else {
+        Ok(())
+    }
+}
+
 
 
 #[derive(Debug)]
 pub struct BtSocket {
     stream: UnixStream,
+    protocol: BtProtocol,
+    local_addr: BtAddr,
 }
 
 impl BtSocket {
     pub fn new(proto: BtProtocol) -> Result<BtSocket, BtError> {
-        match proto {
-            BtProtocol::RFCOMM => {
-                let fd = unsafe {
-                    libc::socket(AF_BLUETOOTH,
-                                 libc::SOCK_STREAM,
-                                 BtProtocolBlueZ::RFCOMM as i32)
-                };
-                if fd < 0 { Err(create_error_from_last("Failed to create Bluetooth socket")) } else { Ok(BtSocket::from(fd)) }
-            }
+        let fd = create_socket(proto, 0)?;
+        Ok(BtSocket::with_fd(fd, proto))
+    }
+
+    fn with_fd(fd: RawFd, proto: BtProtocol) -> BtSocket {
+        BtSocket {
+            stream: unsafe { UnixStream::from_raw_fd(fd) },
+            protocol: proto,
+            local_addr: BtAddr::any(),
         }
     }
 
-    pub fn connect<'a>(&'a mut self, addr: BtAddr) -> BtSocketConnect<'a> {
-        let addr = addr.convert_host_byteorder();
+    /// Takes ownership of a Bluetooth socket created elsewhere.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> BtSocket {
+        BtSocket::with_fd(fd, socket_protocol(fd))
+    }
+}
+
+impl Socket for BtSocket {
+    fn bind(&mut self, local_addr: BtAddr) -> Result<(), BtError> {
+        let local_addr = local_addr.convert_host_byteorder();
+        bind_socket(self.stream.as_raw_fd(), self.protocol, local_addr, 0)?;
+        self.local_addr = local_addr;
+        Ok(())
+    }
+
+    fn connect(&mut self, addr: BtAddr, service: BtUuid) -> Box<dyn SocketConnect> {
+        Box::new(BtSocketConnect::new(self, addr, service))
+    }
+
+    fn connect_channel(&mut self, addr: BtAddr, channel: u8) -> Box<dyn SocketConnect> {
+        Box::new(BtSocketConnect::with_channel(self, addr, channel))
+    }
+
+    fn connect_psm(&mut self, addr: BtAddr, psm: u16) -> Box<dyn SocketConnect> {
+        Box::new(BtSocketConnect::with_psm(self, addr, psm))
+    }
+
+    fn mtu(&self) -> Result<(u16, u16), BtError> {
+        l2cap_mtu(self.stream.as_raw_fd())
+    }
+
+    fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        set_l2cap_mtu(self.stream.as_raw_fd(), input, output)
+    }
+
+    fn peer_addr(&self) -> Result<BtSocketAddr, BtError> {
+        socket_addr(self.stream.as_raw_fd(), self.protocol, true)
+    }
+
+    fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        socket_addr(self.stream.as_raw_fd(), self.protocol, false)
+    }
+
+    fn security(&self) -> Result<SecurityLevel, BtError> {
+        security(self.stream.as_raw_fd())
+    }
+
+    fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        set_security(self.stream.as_raw_fd(), level)
+    }
+
+    fn link_mode(&self) -> Result<LinkMode, BtError> {
+        link_mode(self.stream.as_raw_fd(), self.protocol)
+    }
+
+    fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        set_link_mode(self.stream.as_raw_fd(), self.protocol, mode)
+    }
+
+    fn send_buffer_size(&self) -> Result<usize, BtError> {
+        buffer_size(self.stream.as_raw_fd(), libc::SO_SNDBUF)
+    }
+
+    fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        set_buffer_size(self.stream.as_raw_fd(), libc::SO_SNDBUF, size)
+    }
+
+    fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        buffer_size(self.stream.as_raw_fd(), libc::SO_RCVBUF)
+    }
+
+    fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        set_buffer_size(self.stream.as_raw_fd(), libc::SO_RCVBUF, size)
+    }
+
+    fn linger(&self) -> Result<Option<Duration>, BtError> {
+        linger(self.stream.as_raw_fd())
+    }
+
+    fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        set_linger(self.stream.as_raw_fd(), timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), BtError> {
+        set_nonblocking(self.stream.as_raw_fd(), nonblocking)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (&self.stream).read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> std::io::Result<usize> {
+        (&self.stream).write(buf)
+    }
 
-        BtSocketConnect::new(self, addr)
+    fn flush(&self) -> std::io::Result<()> {
+        (&self.stream).flush()
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
+    fn into_raw_fd(self: Box<Self>) -> RawFd {
+        self.stream.into_raw_fd()
+    }
+}
+
+/// Switches `fd` into or out of non-blocking mode.
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> Result<(), BtError> {
+    let flags = nonblocking_flags(fd)?;
+    let flags = if nonblocking { flags | libc::O_NONBLOCK } else { flags & !libc::O_NONBLOCK };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        Err(create_error_from_last("Failed to change blocking mode of Bluetooth socket"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns true if `fd` is in non-blocking mode.
+fn is_nonblocking(fd: RawFd) -> Result<bool, BtError> {
+    Ok(nonblocking_flags(fd)? & libc::O_NONBLOCK != 0)
+}
+
+fn nonblocking_flags(fd: RawFd) -> Result<libc::c_int, BtError> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        Err(create_error_from_last("Failed to read blocking mode of Bluetooth socket"))
+    } else {
+        Ok(flags)
     }
 }
 
 impl From<nix::Error> for BtError {
     fn from(e: nix::Error) -> BtError {
-        BtError::Errno(e.errno() as u32, e.description().to_string())
+        let description = match e {
+            nix::Error::Sys(errno) => errno.desc(),
+            nix::Error::InvalidPath => "Invalid path",
+        };
+        BtError::Errno(e.errno() as u32, description.to_string())
     }
 }
 
-impl From<RawFd> for BtSocket {
-    fn from(rawfd: RawFd) -> BtSocket {
-        BtSocket { stream: unsafe { UnixStream::from_raw_fd(rawfd) } }
+
+#[derive(Debug)]
+pub struct BtListener {
+    stream: UnixStream,
+    protocol: BtProtocol,
+}
+
+impl BtListener {
+    pub fn new(proto: BtProtocol) -> Result<BtListener, BtError> {
+        // Listening socket is always non-blocking so that `accept()` can be driven by `mio`
+        let fd = create_socket(proto, libc::SOCK_NONBLOCK)?;
+        Ok(BtListener {
+            stream: unsafe { UnixStream::from_raw_fd(fd) },
+            protocol: proto,
+        })
     }
 }
 
-impl mio::Evented for BtSocket {
-    fn register(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
-        EventedFd(&self.stream.as_raw_fd()).register(poll, token, interest, opts)
+impl Listener for BtListener {
+    fn bind(&self, addr: BtAddr, port: u16) -> Result<(), BtError> {
+        bind_socket(self.stream.as_raw_fd(), self.protocol, addr.convert_host_byteorder(), port)
     }
 
-    fn reregister(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
-        EventedFd(&self.stream.as_raw_fd()).reregister(poll, token, interest, opts)
+    fn mtu(&self) -> Result<(u16, u16), BtError> {
+        l2cap_mtu(self.stream.as_raw_fd())
     }
 
-    fn deregister(&self, poll: &Poll) -> std::io::Result<()> {
-        EventedFd(&self.stream.as_raw_fd()).deregister(poll)
+    fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        set_l2cap_mtu(self.stream.as_raw_fd(), input, output)
     }
-}
 
-impl Read for BtSocket {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.stream.read(buf)
+    fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        socket_addr(self.stream.as_raw_fd(), self.protocol, false)
     }
-}
 
-impl Write for BtSocket {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.stream.write(buf)
+    fn security(&self) -> Result<SecurityLevel, BtError> {
+        security(self.stream.as_raw_fd())
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.stream.flush()
+    fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        set_security(self.stream.as_raw_fd(), level)
+    }
+
+    fn link_mode(&self) -> Result<LinkMode, BtError> {
+        link_mode(self.stream.as_raw_fd(), self.protocol)
+    }
+
+    fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        set_link_mode(self.stream.as_raw_fd(), self.protocol, mode)
+    }
+
+    fn defer_setup(&self) -> Result<bool, BtError> {
+        defer_setup(self.stream.as_raw_fd())
+    }
+
+    fn set_defer_setup(&self, defer: bool) -> Result<(), BtError> {
+        set_defer_setup(self.stream.as_raw_fd(), defer)
+    }
+
+    fn send_buffer_size(&self) -> Result<usize, BtError> {
+        buffer_size(self.stream.as_raw_fd(), libc::SO_SNDBUF)
+    }
+
+    fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        set_buffer_size(self.stream.as_raw_fd(), libc::SO_SNDBUF, size)
+    }
+
+    fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        buffer_size(self.stream.as_raw_fd(), libc::SO_RCVBUF)
+    }
+
+    fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        set_buffer_size(self.stream.as_raw_fd(), libc::SO_RCVBUF, size)
+    }
+
+    fn linger(&self) -> Result<Option<Duration>, BtError> {
+        linger(self.stream.as_raw_fd())
+    }
+
+    fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        set_linger(self.stream.as_raw_fd(), timeout)
+    }
+
+    fn listen(&self, backlog: i32) -> Result<(), BtError> {
+        if unsafe { libc::listen(self.stream.as_raw_fd(), backlog) } < 0 {
+            Err(create_error_from_last("Failed to listen() on Bluetooth socket"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn accept(&self) -> Result<Option<Accepted>, BtError> {
+        // Take the peer address from `accept()` itself, so the connection can't be lost in between
+        let listener = self.stream.as_raw_fd();
+        let (fd, peer) = query_addr(self.protocol, |address, socklen| unsafe { libc::accept(listener, address, socklen) });
+        if fd < 0 {
+            match nix::Errno::last() {
+                // No pending connection – caller needs to wait for the listener to become readable
+                nix::Errno::EAGAIN => Ok(None),
+                _ => Err(create_error_from_last("Failed to accept() connection on Bluetooth socket")),
+            }
+        } else {
+            Ok(Some((Box::new(BtSocket::with_fd(fd, self.protocol)), peer.addr)))
+        }
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
     }
 }
 
@@ -133,87 +662,142 @@ impl Write for BtSocket {
 #[derive(Debug)]
 enum BtSocketConnectState {
     SDPSearch,
+    ChannelKnown(u8),
+    PsmKnown(u16),
     Connect,
     Done,
 }
 
+/// Connects a socket, which needs to stay open until the connection process is dropped.
 #[derive(Debug)]
-pub struct BtSocketConnect<'a> {
+pub struct BtSocketConnect {
     addr: BtAddr,
+    fd: RawFd,
+    protocol: BtProtocol,
     pollfd: RawFd,
     state: BtSocketConnectState,
-    socket: &'a mut BtSocket,
     query: QueryRFCOMMChannel,
+    // Set while a blocking socket is switched to non-blocking mode for `connect()`
+    restore_blocking: bool,
 }
-impl<'a> BtSocketConnect<'a> {
-    pub fn new(socket: &'a mut BtSocket, addr: BtAddr) -> Self {
+
+impl BtSocketConnect {
+    pub fn new(socket: &BtSocket, addr: BtAddr, service: BtUuid) -> Self {
+        BtSocketConnect::with_state(socket, addr, service, BtSocketConnectState::SDPSearch)
+    }
+
+    pub fn with_channel(socket: &BtSocket, addr: BtAddr, channel: u8) -> Self {
+        BtSocketConnect::with_state(socket, addr, BtUuid::serial_port(), BtSocketConnectState::ChannelKnown(channel))
+    }
+
+    pub fn with_psm(socket: &BtSocket, addr: BtAddr, psm: u16) -> Self {
+        BtSocketConnect::with_state(socket, addr, BtUuid::serial_port(), BtSocketConnectState::PsmKnown(psm))
+    }
+
+    fn with_state(socket: &BtSocket, addr: BtAddr, service: BtUuid, state: BtSocketConnectState) -> Self {
+        let addr = addr.convert_host_byteorder();
         BtSocketConnect {
-            addr: addr.clone(),
+            addr,
+            fd: socket.stream.as_raw_fd(),
+            protocol: socket.protocol,
             pollfd: 0,
-            query: QueryRFCOMMChannel::new(addr),
-            socket: socket,
-            state: BtSocketConnectState::SDPSearch,
+            state,
+            // Query the SDP server through the adapter the socket is bound to
+            query: QueryRFCOMMChannel::new(socket.local_addr, addr, service),
+            restore_blocking: false,
+        }
+    }
+
+    /// Switches a blocking socket back to blocking mode once the connection process is over.
+    fn restore_blocking_mode(&mut self) -> Result<(), BtError> {
+        if self.restore_blocking {
+            self.restore_blocking = false;
+            set_nonblocking(self.fd, false)?;
         }
+        Ok(())
     }
 
-    pub fn advance(&mut self) -> Result<BtAsync, BtError> {
+    fn start_connect(&mut self, port: u16) -> Result<BtAsync<'_>, BtError> {
+        self.pollfd = self.fd;
+
+        // `connect()` must not block, so that the wait for it can time out or be aborted
+        if !is_nonblocking(self.fd)? {
+            set_nonblocking(self.fd, true)?;
+            self.restore_blocking = true;
+        }
+
+        let result = match self.protocol {
+            BtProtocol::RFCOMM => {
+                let full_address: sockaddr_rc = sockaddr_rc {
+                    rc_family: AF_BLUETOOTH as u16,
+                    rc_bdaddr: self.addr,
+                    rc_channel: port as u8,
+                };
+                unsafe { libc::connect(self.fd, ptr::addr_of!(full_address).cast(), size_of::<sockaddr_rc>() as u32) }
+            }
+            BtProtocol::L2CAP(_) => {
+                let full_address = sockaddr_l2::new(self.addr, port);
+                unsafe { libc::connect(self.fd, ptr::addr_of!(full_address).cast(), size_of::<sockaddr_l2>() as u32) }
+            }
+        };
+
+        // Non-blocking sockets report the connection as in progress
+        if result < 0 && nix::errno::errno() != libc::EINPROGRESS {
+            let error = create_connect_error("Failed to connect() to target device", nix::errno::errno());
+            self.restore_blocking_mode()?;
+            Err(error)
+        } else {
+            self.state = BtSocketConnectState::Connect;
+            Ok(BtAsync::WaitFor(self, Interest::WRITABLE))
+        }
+    }
+}
+
+impl Advance for BtSocketConnect {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
         match &self.state {
             &BtSocketConnectState::SDPSearch => {
                 match try!(self.query.advance()) {
                     // Forward SDP's pleas for another round
                     QueryRFCOMMChannelStatus::WaitReadable(fd) => {
                         self.pollfd = fd;
-                        Ok(BtAsync::WaitFor(self, Ready::readable()))
+                        Ok(BtAsync::WaitFor(self, Interest::READABLE))
                     }
 
                     QueryRFCOMMChannelStatus::WaitWritable(fd) => {
                         self.pollfd = fd;
-                        Ok(BtAsync::WaitFor(self, Ready::writable()))
+                        Ok(BtAsync::WaitFor(self, Interest::WRITABLE))
                     }
 
                     // Received channel number, start actual connection
-                    QueryRFCOMMChannelStatus::Done(channel) => {
-                        let full_address: sockaddr_rc = sockaddr_rc {
-                            rc_family: AF_BLUETOOTH as u16,
-                            rc_bdaddr: self.addr,
-                            rc_channel: channel,
-                        };
-
-                        self.pollfd = self.socket.stream.as_raw_fd();
-                        if unsafe {
-                            libc::connect(self.pollfd,
-                                          mem::transmute(&full_address),
-                                          mem::size_of::<sockaddr_rc>() as u32)
-                        } < 0 {
-                            Err(create_error_from_last("Failed to connect() to target device"))
-                        } else {
-                            self.state = BtSocketConnectState::Connect;
-                            Ok(BtAsync::WaitFor(self, Ready::writable()))
-                        }
-                    }
+                    QueryRFCOMMChannelStatus::Done(channel) => self.start_connect(channel as u16),
                 }
             }
 
+            // Channel number was given by the caller, skip the SDP lookup
+            &BtSocketConnectState::ChannelKnown(channel) => self.start_connect(channel as u16),
+
+            &BtSocketConnectState::PsmKnown(psm) => self.start_connect(psm),
+
             &BtSocketConnectState::Connect => {
-                // First check if socket is actually connected using `getpeername()`
-                let mut full_address: sockaddr_rc = sockaddr_rc {
-                    rc_family: AF_BLUETOOTH as u16,
-                    rc_bdaddr: BtAddr::any(),
-                    rc_channel: 0,
-                };
-                let mut socklen: libc::socklen_t = mem::size_of::<sockaddr_rc>() as libc::socklen_t;
-                if unsafe { libc::getpeername(self.pollfd, mem::transmute(&mut full_address), &mut socklen) } < 0 {
-                    if nix::Errno::last() == nix::Errno::ENOTCONN {
-                        // Connection has failed – obtain actual error code using `read()`
-                        let mut buf = [0u8; 1];
-                        nix::unistd::read(self.pollfd, &mut buf).unwrap_err();
-                        Err(create_error_from_last("Failed to connect() to target device"))
-                    } else {
-                        // Some unexpected error
-                        Err(create_error_from_last("getpeername() failed"))
+                // The socket became writable, `SO_ERROR` tells whether the connection succeeded
+                let error: libc::c_int = get_option(self.fd, libc::SOL_SOCKET, libc::SO_ERROR, "getsockopt(SO_ERROR) failed")?;
+                if error == 0 {
+                    match socket_addr(self.fd, self.protocol, true) {
+                        // Woken up early, the connection is still in progress
+                        Err(ref error) if error.errno() == Some(libc::ENOTCONN as u32) => {
+                            return Ok(BtAsync::WaitFor(self, Interest::WRITABLE));
+                        }
+                        Err(error) => return Err(error),
+                        Ok(_) => {}
                     }
+                }
+
+                self.state = BtSocketConnectState::Done;
+                self.restore_blocking_mode()?;
+                if error != 0 {
+                    Err(create_connect_error("Failed to connect() to target device", error))
                 } else {
-                    self.state = BtSocketConnectState::Done;
                     Ok(BtAsync::Done)
                 }
             }
@@ -223,18 +807,227 @@ impl<'a> BtSocketConnect<'a> {
             }
         }
     }
+
+    fn pollfd(&self) -> RawFd {
+        self.pollfd
+    }
 }
 
-impl<'a> mio::Evented for BtSocketConnect<'a> {
-    fn register(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
-        EventedFd(&self.pollfd).register(poll, token, interest, opts)
+impl SocketConnect for BtSocketConnect {}
+
+impl Drop for BtSocketConnect {
+    fn drop(&mut self) {
+        // Abort a pending connection, so the adapter stops paging the device. An SDP session still
+        // in flight is closed together with `query`.
+        if let BtSocketConnectState::Connect = self.state {
+            unsafe { libc::shutdown(self.fd, libc::SHUT_RDWR) };
+        }
+        let _ = self.restore_blocking_mode();
+    }
+}
+
+impl mio::event::Source for BtSocketConnect {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        SourceFd(&self.pollfd).deregister(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linux::sdp::tests::{receive_request, response_pdu, spp_attribute_lists};
+    use bluetooth::wait_until_done_timeout;
+    use mio::{Events, Poll, Token};
+    use std::time::Instant;
+
+    fn seqpacket_pair() -> (RawFd, UnixStream) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK, 0, fds.as_mut_ptr()) }, 0);
+        (fds[0], unsafe { UnixStream::from_raw_fd(fds[1]) })
+    }
+
+    #[test]
+    fn sets_generic_socket_options() {
+        let (fd, _peer) = seqpacket_pair();
+        let socket = BtSocket::with_fd(fd, BtProtocol::RFCOMM);
+
+        socket.set_send_buffer_size(4096).unwrap();
+        assert!(socket.send_buffer_size().unwrap() >= 4096);
+        socket.set_recv_buffer_size(4096).unwrap();
+        assert!(socket.recv_buffer_size().unwrap() >= 4096);
+
+        assert_eq!(socket.linger().unwrap(), None);
+        socket.set_linger(Some(Duration::from_secs(3))).unwrap();
+        assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(3)));
+        socket.set_linger(None).unwrap();
+        assert_eq!(socket.linger().unwrap(), None);
     }
 
-    fn reregister(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
-        EventedFd(&self.pollfd).reregister(poll, token, interest, opts)
+    #[test]
+    fn closes_sdp_session_when_dropped() {
+        let (socket_fd, _) = seqpacket_pair();
+        let socket = BtSocket::with_fd(socket_fd, BtProtocol::RFCOMM);
+        let (sdp_fd, mut sdp_server) = seqpacket_pair();
+
+        {
+            let mut connect = BtSocketConnect::new(&socket, BtAddr::any(), BtUuid::serial_port());
+            connect.query = QueryRFCOMMChannel::with_socket(unsafe { UnixStream::from_raw_fd(sdp_fd) }, BtUuid::serial_port());
+            match connect.advance().unwrap() {
+                BtAsync::WaitFor(_, interest) => assert_eq!(interest, Interest::READABLE),
+                BtAsync::Done => panic!("SDP query finished without response"),
+            }
+        }
+
+        // Service search request, then end of file once the session is closed
+        let mut buf = [0u8; 64];
+        assert!(sdp_server.read(&mut buf).unwrap() > 0);
+        assert_eq!(sdp_server.read(&mut buf).unwrap(), 0);
     }
 
-    fn deregister(&self, poll: &Poll) -> std::io::Result<()> {
-        EventedFd(&self.pollfd).deregister(poll)
+    #[test]
+    fn accepts_connections_closed_by_the_peer() {
+        let path = std::env::temp_dir().join(format!("bt-accept-{}", std::process::id()));
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = BtListener {
+            stream: unsafe { UnixStream::from_raw_fd(listener.into_raw_fd()) },
+            protocol: BtProtocol::RFCOMM,
+        };
+        assert!(listener.accept().unwrap().is_none());
+
+        // The connection is queued, but its peer is gone before it is accepted
+        UnixStream::connect(&path).unwrap().write_all(b"x").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (socket, _) = listener.accept().unwrap().unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(socket.read(&mut buf).unwrap(), 1);
+        assert_eq!(socket.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn aborts_pending_connection_when_dropped() {
+        let (socket_fd, mut peer) = seqpacket_pair();
+        let socket = BtSocket::with_fd(socket_fd, BtProtocol::RFCOMM);
+
+        {
+            let mut connect = BtSocketConnect::with_channel(&socket, BtAddr::any(), 1);
+            connect.state = BtSocketConnectState::Connect;
+        }
+
+        let mut buf = [0u8; 1];
+        assert_eq!(peer.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn drives_sdp_lookup_through_mio_poll() {
+        let (socket_fd, _peer) = seqpacket_pair();
+        let socket = BtSocket::with_fd(socket_fd, BtProtocol::RFCOMM);
+        let (sdp_fd, mut sdp_server) = seqpacket_pair();
+        let mut connect = BtSocketConnect::new(&socket, BtAddr::any(), BtUuid::serial_port());
+        connect.query = QueryRFCOMMChannel::with_socket(unsafe { UnixStream::from_raw_fd(sdp_fd) }, BtUuid::serial_port());
+        let mut poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(2);
+
+        match connect.advance().unwrap() {
+            BtAsync::WaitFor(source, interest) => {
+                assert_eq!(interest, Interest::READABLE);
+                poll.registry().register(source, Token(1), interest).unwrap();
+            }
+            BtAsync::Done => panic!("SDP query finished without response"),
+        }
+        poll.poll(&mut events, Some(Duration::from_secs(0))).unwrap();
+        assert!(events.is_empty());
+
+        receive_request(&mut sdp_server);
+        sdp_server.write_all(&response_pdu(0, &spp_attribute_lists(), &[])).unwrap();
+        poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(events.iter().map(|event| event.token()).collect::<Vec<_>>(), vec![Token(1)]);
+        poll.registry().deregister(&mut connect).unwrap();
+
+        // The record names RFCOMM channel 3, which the Unix socket refuses to connect to
+        match connect.advance() {
+            Err(error) => assert_eq!(error.errno(), Some(libc::EINVAL as u32)),
+            _ => panic!("connecting a Unix socket to a Bluetooth address succeeded"),
+        }
+    }
+
+    #[test]
+    fn completes_connection_once_writable() {
+        let (socket_fd, _peer) = seqpacket_pair();
+        let socket = BtSocket::with_fd(socket_fd, BtProtocol::RFCOMM);
+        let mut connect = BtSocketConnect::with_channel(&socket, BtAddr::any(), 1);
+        connect.state = BtSocketConnectState::Connect;
+        connect.pollfd = socket_fd;
+        let mut poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(2);
+
+        poll.registry().register(&mut connect, Token(1), Interest::WRITABLE).unwrap();
+        poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+        let event = events.iter().next().unwrap();
+        assert_eq!(event.token(), Token(1));
+        assert!(event.is_writable());
+        poll.registry().deregister(&mut connect).unwrap();
+
+        match connect.advance().unwrap() {
+            BtAsync::Done => {}
+            BtAsync::WaitFor(..) => panic!("connected socket is still waiting"),
+        }
+    }
+
+    #[test]
+    fn restores_blocking_mode_after_connect() {
+        let (socket_fd, _peer) = seqpacket_pair();
+        let socket = BtSocket::with_fd(socket_fd, BtProtocol::RFCOMM);
+        socket.set_nonblocking(false).unwrap();
+
+        {
+            // `connect()` rejects the Bluetooth address on a Unix socket
+            let mut connect = BtSocketConnect::with_channel(&socket, BtAddr::any(), 1);
+            assert!(connect.advance().is_err());
+            assert!(!is_nonblocking(socket_fd).unwrap());
+        }
+
+        {
+            let mut connect = BtSocketConnect::with_channel(&socket, BtAddr::any(), 1);
+            set_nonblocking(socket_fd, true).unwrap();
+            connect.restore_blocking = true;
+            connect.state = BtSocketConnectState::Connect;
+            connect.pollfd = socket_fd;
+            match connect.advance().unwrap() {
+                BtAsync::Done => {}
+                BtAsync::WaitFor(..) => panic!("connected socket is still waiting"),
+            }
+            assert!(!is_nonblocking(socket_fd).unwrap());
+        }
+    }
+
+    #[test]
+    fn times_out_connection_which_never_completes() {
+        // A listening socket is neither connected nor ever writable, like a connect to an absent
+        // device
+        let path = std::env::temp_dir().join(format!("bluetooth-connect-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let socket_fd = listener.into_raw_fd();
+        let socket = BtSocket::with_fd(socket_fd, BtProtocol::RFCOMM);
+        let mut connect = BtSocketConnect::with_channel(&socket, BtAddr::any(), 1);
+        connect.state = BtSocketConnectState::Connect;
+        connect.pollfd = socket_fd;
+
+        let start = Instant::now();
+        let error = wait_until_done_timeout(&mut connect, Some(Duration::from_millis(200))).unwrap_err();
+        assert_eq!(error.kind(), BtErrorKind::Timeout);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(2));
     }
 }
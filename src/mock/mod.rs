@@ -0,0 +1,1372 @@
+//! An in-memory Bluetooth stack which tests install in place of the Linux stack, enabled by the
+//! `mock` feature.
+//!
+//! Adapters, devices in range, their SDP records and the services accepting connections are
+//! scripted through a `MockStack`. Connections are socketpairs, so reading, writing and waiting
+//! for readiness behave like on real sockets.
+
+use backend::{self, Accepted, Discovery, Hci, Listener, ReadRemoteName, ServiceBrowse, ServiceRegistration, Socket,
+              SocketConnect, Stack};
+use bluetooth::{Advance, BtAdapter, BtAddr, BtAsync, BtDevice, BtDiscoveryEvent, BtError, BtErrorKind,
+                BtProtocol, BtServiceRecord, BtSocketAddr, BtUuid, DeviceClass, InquiryMode, L2capMode, LinkMode, ScanOptions,
+                SecurityLevel};
+use libc;
+use mio;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+use mio::{Interest, Registry};
+use mio::unix::SourceFd;
+
+fn create_error(message: &str, errno: i32) -> BtError {
+    BtError::Errno(errno as u32, format!("{}: {}", message, io::Error::from_raw_os_error(errno)))
+}
+
+fn create_error_of_kind(kind: BtErrorKind, message: &str, errno: i32) -> BtError {
+    BtError::Kind(kind, Some(errno as u32), format!("{}: {}", message, io::Error::from_raw_os_error(errno)))
+}
+
+fn create_error_from_io(message: &str, error: io::Error) -> BtError {
+    create_error(message, error.raw_os_error().unwrap_or(0))
+}
+
+/// The Unix socket type which carries the traffic of `protocol`.
+fn socket_type(protocol: BtProtocol) -> libc::c_int {
+    match protocol {
+        BtProtocol::L2CAP(L2capMode::SeqPacket) => libc::SOCK_SEQPACKET,
+        BtProtocol::L2CAP(L2capMode::Stream) | BtProtocol::RFCOMM => libc::SOCK_STREAM,
+    }
+}
+
+fn socket_pair(protocol: BtProtocol) -> Result<(UnixStream, UnixStream), BtError> {
+    let mut fds = [0; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, socket_type(protocol) | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) } < 0 {
+        return Err(create_error_from_io("Failed to create socketpair", io::Error::last_os_error()));
+    }
+    Ok(unsafe { (UnixStream::from_raw_fd(fds[0]), UnixStream::from_raw_fd(fds[1])) })
+}
+
+
+/// An RFCOMM channel or L2CAP PSM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Port {
+    Channel(u8),
+    Psm(u16),
+}
+
+impl Port {
+    fn new(protocol: BtProtocol, port: u16) -> Port {
+        match protocol {
+            BtProtocol::RFCOMM => Port::Channel(port as u8),
+            BtProtocol::L2CAP(_) => Port::Psm(port),
+        }
+    }
+
+    fn number(&self) -> u16 {
+        match *self {
+            Port::Channel(channel) => channel as u16,
+            Port::Psm(psm) => psm,
+        }
+    }
+
+    fn matches(&self, protocol: BtProtocol) -> bool {
+        matches!((*self, protocol), (Port::Channel(_), BtProtocol::RFCOMM) | (Port::Psm(_), BtProtocol::L2CAP(_)))
+    }
+}
+
+/// A file descriptor which is readable while rung, for waiting on in-memory events.
+#[derive(Debug)]
+struct Bell {
+    reader: UnixStream,
+    writer: UnixStream,
+}
+
+impl Bell {
+    fn new() -> Result<Bell, BtError> {
+        let (reader, writer) = socket_pair(BtProtocol::RFCOMM)?;
+        reader.set_nonblocking(true).map_err(|error| create_error_from_io("Failed to create bell", error))?;
+        writer.set_nonblocking(true).map_err(|error| create_error_from_io("Failed to create bell", error))?;
+        Ok(Bell { reader, writer })
+    }
+
+    fn ringing() -> Result<Bell, BtError> {
+        let bell = Bell::new()?;
+        bell.ring();
+        Ok(bell)
+    }
+
+    fn ring(&self) {
+        // A full buffer rings already
+        let _ = (&self.writer).write(&[0]);
+    }
+
+    fn silence(&self) {
+        let mut buf = [0u8; 64];
+        while let Ok(size) = (&self.reader).read(&mut buf) {
+            if size == 0 {
+                break;
+            }
+        }
+    }
+}
+
+impl AsRawFd for Bell {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
+
+
+/// Socket options, which are only recorded as there is no link they could apply to.
+#[derive(Clone, Copy, Debug)]
+struct SocketOptions {
+    security: SecurityLevel,
+    link_mode: LinkMode,
+    mtu: (u16, u16),
+    defer_setup: bool,
+    send_buffer_size: usize,
+    recv_buffer_size: usize,
+    linger: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            security: SecurityLevel::Low,
+            link_mode: LinkMode::default(),
+            // The L2CAP default MTU
+            mtu: (672, 672),
+            defer_setup: false,
+            send_buffer_size: 212992,
+            recv_buffer_size: 212992,
+            linger: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options(Mutex<SocketOptions>);
+
+impl Options {
+    fn get(&self) -> SocketOptions {
+        *self.0.lock().unwrap()
+    }
+
+    fn update<F: FnOnce(&mut SocketOptions)>(&self, update: F) -> Result<(), BtError> {
+        update(&mut self.0.lock().unwrap());
+        Ok(())
+    }
+
+    fn mtu(&self, protocol: BtProtocol) -> Result<(u16, u16), BtError> {
+        match protocol {
+            BtProtocol::L2CAP(_) => Ok(self.get().mtu),
+            BtProtocol::RFCOMM => Err(create_error("getsockopt(L2CAP_OPTIONS) failed", libc::ENOPROTOOPT)),
+        }
+    }
+
+    fn set_mtu(&self, protocol: BtProtocol, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        match protocol {
+            BtProtocol::L2CAP(_) => {
+                self.update(|options| {
+                    options.mtu = (input.unwrap_or(options.mtu.0), output.unwrap_or(options.mtu.1));
+                })
+            }
+            BtProtocol::RFCOMM => Err(create_error("setsockopt(L2CAP_OPTIONS) failed", libc::ENOPROTOOPT)),
+        }
+    }
+}
+
+
+/// Connections waiting to be accepted by a `BtListener`.
+#[derive(Debug)]
+struct Backlog {
+    protocol: BtProtocol,
+    options: Options,
+    pending: Mutex<VecDeque<(UnixStream, BtSocketAddr)>>,
+    bell: Bell,
+}
+
+#[derive(Debug)]
+struct MockDevice {
+    device: BtDevice,
+    records: Vec<BtServiceRecord>,
+    peers: Vec<(Port, Sender<UnixStream>)>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    adapters: Vec<BtAdapter>,
+    devices: Vec<MockDevice>,
+    listeners: Vec<(Port, Weak<Backlog>)>,
+    registrations: Vec<BtServiceRecord>,
+    next_handle: u32,
+}
+
+impl MockState {
+    fn adapter(&mut self, id: Option<u16>) -> Result<&mut BtAdapter, BtError> {
+        let adapter = match id {
+            Some(id) => self.adapters.iter_mut().find(|adapter| adapter.id == id),
+            None => self.adapters.iter_mut().next(),
+        };
+        adapter.ok_or_else(|| create_error_of_kind(BtErrorKind::AdapterNotFound, "Bluetooth adapter not available", libc::ENODEV))
+    }
+
+    fn adapter_up(&mut self, id: Option<u16>) -> Result<&mut BtAdapter, BtError> {
+        let adapter = self.adapter(id)?;
+        if adapter.up {
+            Ok(adapter)
+        } else {
+            Err(create_error("Bluetooth adapter is down", libc::ENETDOWN))
+        }
+    }
+
+    fn check_local_addr(&self, addr: BtAddr) -> Result<(), BtError> {
+        if addr == BtAddr::any() || self.adapters.iter().any(|adapter| adapter.addr == addr) {
+            Ok(())
+        } else {
+            Err(create_error("Failed to bind() to local adapter", libc::EADDRNOTAVAIL))
+        }
+    }
+
+    fn device(&mut self, addr: BtAddr) -> Result<&mut MockDevice, BtError> {
+        self.devices
+            .iter_mut()
+            .find(|device| device.device.addr == addr)
+            .ok_or_else(|| create_error_of_kind(BtErrorKind::HostDown, "Remote device not in range", libc::EHOSTDOWN))
+    }
+
+    fn listener(&self, port: Port) -> Option<Arc<Backlog>> {
+        self.listeners.iter().filter(|&&(bound, _)| bound == port).filter_map(|(_, backlog)| backlog.upgrade()).next()
+    }
+
+    /// Returns the lowest port of `protocol` neither bound to a listener nor the SDP server.
+    fn free_port(&self, protocol: BtProtocol) -> u16 {
+        let mut candidates: Box<dyn Iterator<Item = u16>> = match protocol {
+            BtProtocol::RFCOMM => Box::new(1..31),
+            // Dynamically assigned PSMs are odd
+            BtProtocol::L2CAP(_) => Box::new((0x1001..0x8000).filter(|psm| psm % 2 == 1)),
+        };
+        candidates.find(|&port| self.listener(Port::new(protocol, port)).is_none()).unwrap_or(0)
+    }
+}
+
+
+/// A scripted Bluetooth stack, which tests `install` in place of the operating system's.
+///
+/// Sockets, listeners, scans and SDP requests use the stack installed on the thread creating them,
+/// or the Linux stack if there is none. Installing a new stack per test keeps tests running in
+/// parallel from seeing each other's devices. Sockets, listeners and discoveries keep using the
+/// stack which created them when moved to another thread.
+///
+/// ```rust
+/// # use std::io::{Read, Write};
+/// # use bluetooth_serial_port::{BtAddr, BtDevice, BtProtocol, BtServiceRecord, BtSocket, BtUuid, MockStack};
+/// let addr = BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+/// let stack = MockStack::new();
+/// stack.install();
+/// stack.add_device(BtDevice::new("Sensor".to_string(), addr));
+/// stack.add_service(addr, BtServiceRecord::rfcomm(BtUuid::serial_port(), 3));
+/// let peer = stack.listen(addr, 3);
+///
+/// let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+/// socket.connect(addr).unwrap();
+/// let mut remote = peer.accept().unwrap();
+/// remote.write_all(b"ready").unwrap();
+///
+/// let mut buf = [0u8; 5];
+/// socket.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, b"ready");
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockStack(Arc<Mutex<MockState>>);
+
+impl Default for MockStack {
+    fn default() -> MockStack {
+        MockStack::new()
+    }
+}
+
+impl MockStack {
+    /// Create a stack with the adapter `hci0`, which is up, and no devices in range.
+    pub fn new() -> MockStack {
+        let adapter = BtAdapter {
+            id: 0,
+            addr: BtAddr([0x00, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            name: Some("mock".to_string()),
+            up: true,
+            features: [0; 8],
+        };
+        MockStack(Arc::new(Mutex::new(MockState {
+            adapters: vec![adapter],
+            devices: Vec::new(),
+            listeners: Vec::new(),
+            registrations: Vec::new(),
+            next_handle: 0x00010000,
+        })))
+    }
+
+    /// Make this the stack of the current thread in place of the Linux stack, also to share it
+    /// with a spawned thread.
+    pub fn install(&self) {
+        backend::install(Some(Rc::new(self.clone())));
+    }
+
+    /// Restore the Linux stack on the current thread.
+    pub fn uninstall() {
+        backend::install(None);
+    }
+
+    /// Add a local adapter, replacing the adapter with the same id.
+    pub fn add_adapter(&self, adapter: BtAdapter) {
+        let mut state = self.lock();
+        state.adapters.retain(|known| known.id != adapter.id);
+        state.adapters.push(adapter);
+        state.adapters.sort_by_key(|adapter| adapter.id);
+    }
+
+    /// Bring `device` into range, replacing the device with the same address.
+    ///
+    /// Scans report the device in the order the devices were added, without its name unless
+    /// names are resolved. `read_remote_name` returns the name.
+    pub fn add_device(&self, device: BtDevice) {
+        let mut state = self.lock();
+        state.devices.retain(|known| known.device.addr != device.addr);
+        state.devices.push(MockDevice {
+            device,
+            records: Vec::new(),
+            peers: Vec::new(),
+        });
+    }
+
+    /// Publish `record` in the SDP server of the device with address `addr`. Records without a
+    /// handle are assigned one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device has not been added.
+    pub fn add_service(&self, addr: BtAddr, mut record: BtServiceRecord) {
+        let mut state = self.lock();
+        let device = state.device(addr).expect("service added to a device which is not in range");
+        if record.handle == 0 {
+            record.handle = 0x00010000 + device.records.len() as u32;
+        }
+        device.records.push(record);
+    }
+
+    /// Accept RFCOMM connections on `channel` of the device with address `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device has not been added.
+    pub fn listen(&self, addr: BtAddr, channel: u8) -> MockPeer {
+        self.listen_port(addr, Port::Channel(channel))
+    }
+
+    /// Accept L2CAP connections on `psm` of the device with address `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device has not been added.
+    pub fn listen_psm(&self, addr: BtAddr, psm: u16) -> MockPeer {
+        self.listen_port(addr, Port::Psm(psm))
+    }
+
+    fn listen_port(&self, addr: BtAddr, port: Port) -> MockPeer {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = self.lock();
+        let device = state.device(addr).expect("listening on a device which is not in range");
+        device.peers.retain(|&(bound, _)| bound != port);
+        device.peers.push((port, sender));
+        MockPeer(receiver)
+    }
+
+    /// Connect the device with address `addr` to the local listener on RFCOMM `channel`, returning
+    /// the remote end of the connection.
+    pub fn connect(&self, addr: BtAddr, channel: u8) -> Result<UnixStream, BtError> {
+        self.connect_port(addr, Port::Channel(channel))
+    }
+
+    /// Connect the device with address `addr` to the local listener on L2CAP `psm`, returning the
+    /// remote end of the connection.
+    pub fn connect_psm(&self, addr: BtAddr, psm: u16) -> Result<UnixStream, BtError> {
+        self.connect_port(addr, Port::Psm(psm))
+    }
+
+    fn connect_port(&self, addr: BtAddr, port: Port) -> Result<UnixStream, BtError> {
+        let backlog = match self.lock().listener(port) {
+            Some(backlog) => backlog,
+            None => return Err(create_error_of_kind(BtErrorKind::ConnectionRefused, "Failed to connect to local listener", libc::ECONNREFUSED)),
+        };
+
+        let (local, remote) = socket_pair(backlog.protocol)?;
+        let mut pending = backlog.pending.lock().unwrap();
+        pending.push_back((local, BtSocketAddr { addr, channel: port.number() }));
+        backlog.bell.ring();
+        Ok(remote)
+    }
+
+    /// Returns the records registered with the local SDP server through `register_service`.
+    pub fn registered_services(&self) -> Vec<BtServiceRecord> {
+        self.lock().registrations.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.0.lock().unwrap()
+    }
+
+    /// Opens a connection to `port` on the device with address `addr`.
+    fn open(&self, addr: BtAddr, port: Port, protocol: BtProtocol) -> Result<UnixStream, BtError> {
+        if !port.matches(protocol) {
+            return Err(create_error("Failed to connect() to target device", libc::EINVAL));
+        }
+
+        let mut state = self.lock();
+        let device = state.device(addr)?;
+        let sender = match device.peers.iter().find(|&&(bound, _)| bound == port) {
+            Some((_, sender)) => sender,
+            None => return Err(create_error_of_kind(BtErrorKind::ConnectionRefused, "Failed to connect() to target device", libc::ECONNREFUSED)),
+        };
+
+        let (local, remote) = socket_pair(protocol)?;
+        match sender.send(remote) {
+            Ok(()) => Ok(local),
+            // The peer has been dropped
+            Err(_) => Err(create_error_of_kind(BtErrorKind::ConnectionRefused, "Failed to connect() to target device", libc::ECONNREFUSED)),
+        }
+    }
+
+    /// Finds the RFCOMM channel of `service` through SDP.
+    fn lookup_channel(&self, addr: BtAddr, service: BtUuid) -> Result<u8, BtError> {
+        let mut state = self.lock();
+        let device = state.device(addr)?;
+        let uuid = service.to_uuid128();
+        device.records
+            .iter()
+            .filter(|record| record.service_classes.iter().any(|class| class.to_uuid128() == uuid))
+            .filter_map(|record| record.rfcomm_channel())
+            .next()
+            .ok_or_else(|| BtError::Kind(BtErrorKind::ServiceNotFound, None, "No RFCOMM service on remote device".to_string()))
+    }
+}
+
+/// A remote service accepting connections, created by `MockStack::listen`.
+///
+/// Every connection made to the service yields the remote end of a socketpair. Dropping the peer
+/// refuses further connections.
+#[derive(Debug)]
+pub struct MockPeer(Receiver<UnixStream>);
+
+impl MockPeer {
+    /// Wait for the next connection and return its remote end.
+    ///
+    /// Returns `None` once the stack has been dropped.
+    pub fn accept(&self) -> Option<UnixStream> {
+        self.0.recv().ok()
+    }
+
+    /// Return the remote end of the next connection if one has been made, without waiting.
+    pub fn try_accept(&self) -> Option<UnixStream> {
+        self.0.try_recv().ok()
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BtSocket {
+    stream: UnixStream,
+    protocol: BtProtocol,
+    stack: MockStack,
+    local_addr: BtSocketAddr,
+    // Shared with the connection process, which records the peer once connected
+    peer_addr: Arc<Mutex<Option<BtSocketAddr>>>,
+    options: Options,
+}
+
+impl BtSocket {
+    fn new(stack: &MockStack, protocol: BtProtocol) -> Result<BtSocket, BtError> {
+        let fd = unsafe { libc::socket(libc::AF_UNIX, socket_type(protocol) | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(create_error_from_io("Failed to create Bluetooth socket", io::Error::last_os_error()));
+        }
+        Ok(BtSocket::with_stream(unsafe { UnixStream::from_raw_fd(fd) }, protocol, stack.clone()))
+    }
+
+    fn with_stream(stream: UnixStream, protocol: BtProtocol, stack: MockStack) -> BtSocket {
+        BtSocket {
+            stream,
+            protocol,
+            stack,
+            local_addr: BtSocketAddr { addr: BtAddr::any(), channel: 0 },
+            peer_addr: Arc::new(Mutex::new(None)),
+            options: Options::default(),
+        }
+    }
+
+    fn connect_process(&self, addr: BtAddr, state: BtSocketConnectState) -> Box<dyn SocketConnect> {
+        Box::new(BtSocketConnect {
+            stack: self.stack.clone(),
+            fd: self.stream.as_raw_fd(),
+            protocol: self.protocol,
+            peer_addr: self.peer_addr.clone(),
+            addr,
+            state,
+        })
+    }
+}
+
+/// Replaces the socket `fd` by the local end of `connection`, keeping its blocking mode.
+fn attach(fd: RawFd, connection: UnixStream) -> Result<(), BtError> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::dup3(connection.as_raw_fd(), fd, libc::O_CLOEXEC) } < 0 ||
+       unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(create_error_from_io("Failed to connect() to target device", io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+impl Socket for BtSocket {
+    fn bind(&mut self, local_addr: BtAddr) -> Result<(), BtError> {
+        self.stack.lock().check_local_addr(local_addr)?;
+        self.local_addr.addr = local_addr;
+        Ok(())
+    }
+
+    fn connect(&mut self, addr: BtAddr, service: BtUuid) -> Box<dyn SocketConnect> {
+        self.connect_process(addr, BtSocketConnectState::SDPSearch(service))
+    }
+
+    fn connect_channel(&mut self, addr: BtAddr, channel: u8) -> Box<dyn SocketConnect> {
+        self.connect_process(addr, BtSocketConnectState::PortKnown(Port::Channel(channel)))
+    }
+
+    fn connect_psm(&mut self, addr: BtAddr, psm: u16) -> Box<dyn SocketConnect> {
+        self.connect_process(addr, BtSocketConnectState::PortKnown(Port::Psm(psm)))
+    }
+
+    fn mtu(&self) -> Result<(u16, u16), BtError> {
+        self.options.mtu(self.protocol)
+    }
+
+    fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        self.options.set_mtu(self.protocol, input, output)
+    }
+
+    fn peer_addr(&self) -> Result<BtSocketAddr, BtError> {
+        self.peer_addr.lock().unwrap().ok_or_else(|| create_error("getpeername() failed", libc::ENOTCONN))
+    }
+
+    fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        Ok(self.local_addr)
+    }
+
+    fn security(&self) -> Result<SecurityLevel, BtError> {
+        Ok(self.options.get().security)
+    }
+
+    fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        self.options.update(|options| options.security = level)
+    }
+
+    fn link_mode(&self) -> Result<LinkMode, BtError> {
+        Ok(self.options.get().link_mode)
+    }
+
+    fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        self.options.update(|options| options.link_mode = mode)
+    }
+
+    fn send_buffer_size(&self) -> Result<usize, BtError> {
+        Ok(self.options.get().send_buffer_size)
+    }
+
+    fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.options.update(|options| options.send_buffer_size = size)
+    }
+
+    fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        Ok(self.options.get().recv_buffer_size)
+    }
+
+    fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.options.update(|options| options.recv_buffer_size = size)
+    }
+
+    fn linger(&self) -> Result<Option<Duration>, BtError> {
+        Ok(self.options.get().linger)
+    }
+
+    fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        self.options.update(|options| options.linger = timeout.map(|timeout| Duration::from_secs(timeout.as_secs())))
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), BtError> {
+        self.stream
+            .set_nonblocking(nonblocking)
+            .map_err(|error| create_error_from_io("Failed to change blocking mode of Bluetooth socket", error))
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        (&self.stream).read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        (&self.stream).write(buf)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (&self.stream).flush()
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+
+    fn into_raw_fd(self: Box<Self>) -> RawFd {
+        self.stream.into_raw_fd()
+    }
+}
+
+#[derive(Debug)]
+pub struct BtListener {
+    stack: MockStack,
+    backlog: Arc<Backlog>,
+    local_addr: Mutex<Option<BtSocketAddr>>,
+}
+
+impl BtListener {
+    fn new(stack: &MockStack, protocol: BtProtocol) -> Result<BtListener, BtError> {
+        Ok(BtListener {
+            stack: stack.clone(),
+            backlog: Arc::new(Backlog {
+                protocol,
+                options: Options::default(),
+                pending: Mutex::new(VecDeque::new()),
+                bell: Bell::new()?,
+            }),
+            local_addr: Mutex::new(None),
+        })
+    }
+}
+
+impl Listener for BtListener {
+    fn bind(&self, addr: BtAddr, port: u16) -> Result<(), BtError> {
+        let state = self.stack.lock();
+        state.check_local_addr(addr)?;
+        let protocol = self.backlog.protocol;
+        let port = if port == 0 { state.free_port(protocol) } else { port };
+        if state.listener(Port::new(protocol, port)).is_some() {
+            return Err(create_error("Failed to bind() Bluetooth socket", libc::EADDRINUSE));
+        }
+        *self.local_addr.lock().unwrap() = Some(BtSocketAddr { addr, channel: port });
+        Ok(())
+    }
+
+    fn mtu(&self) -> Result<(u16, u16), BtError> {
+        self.backlog.options.mtu(self.backlog.protocol)
+    }
+
+    fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        self.backlog.options.set_mtu(self.backlog.protocol, input, output)
+    }
+
+    fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        Ok(self.local_addr.lock().unwrap().unwrap_or(BtSocketAddr { addr: BtAddr::any(), channel: 0 }))
+    }
+
+    fn defer_setup(&self) -> Result<bool, BtError> {
+        Ok(self.backlog.options.get().defer_setup)
+    }
+
+    fn set_defer_setup(&self, defer: bool) -> Result<(), BtError> {
+        self.backlog.options.update(|options| options.defer_setup = defer)
+    }
+
+    fn security(&self) -> Result<SecurityLevel, BtError> {
+        Ok(self.backlog.options.get().security)
+    }
+
+    fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        self.backlog.options.update(|options| options.security = level)
+    }
+
+    fn link_mode(&self) -> Result<LinkMode, BtError> {
+        Ok(self.backlog.options.get().link_mode)
+    }
+
+    fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        self.backlog.options.update(|options| options.link_mode = mode)
+    }
+
+    fn send_buffer_size(&self) -> Result<usize, BtError> {
+        Ok(self.backlog.options.get().send_buffer_size)
+    }
+
+    fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.backlog.options.update(|options| options.send_buffer_size = size)
+    }
+
+    fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        Ok(self.backlog.options.get().recv_buffer_size)
+    }
+
+    fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        self.backlog.options.update(|options| options.recv_buffer_size = size)
+    }
+
+    fn linger(&self) -> Result<Option<Duration>, BtError> {
+        Ok(self.backlog.options.get().linger)
+    }
+
+    fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        self.backlog.options.update(|options| options.linger = timeout.map(|timeout| Duration::from_secs(timeout.as_secs())))
+    }
+
+    fn listen(&self, _backlog: i32) -> Result<(), BtError> {
+        let local_addr = match *self.local_addr.lock().unwrap() {
+            Some(local_addr) => local_addr,
+            None => return Err(create_error("Failed to listen() on Bluetooth socket", libc::EBADFD)),
+        };
+
+        let mut state = self.stack.lock();
+        let port = Port::new(self.backlog.protocol, local_addr.channel);
+        if state.listener(port).is_none() {
+            state.listeners.retain(|(_, backlog)| backlog.upgrade().is_some());
+            state.listeners.push((port, Arc::downgrade(&self.backlog)));
+        }
+        Ok(())
+    }
+
+    fn accept(&self) -> Result<Option<Accepted>, BtError> {
+        let mut pending = self.backlog.pending.lock().unwrap();
+        let accepted = pending.pop_front();
+        if pending.is_empty() {
+            self.backlog.bell.silence();
+        }
+
+        Ok(accepted.map(|(stream, peer_addr)| {
+            let mut socket = BtSocket::with_stream(stream, self.backlog.protocol, self.stack.clone());
+            socket.local_addr = self.local_addr().unwrap();
+            *socket.peer_addr.lock().unwrap() = Some(peer_addr);
+            // Accepted connections inherit the options of the listener
+            *socket.options.0.lock().unwrap() = self.backlog.options.get();
+            let socket: Box<dyn Socket> = Box::new(socket);
+            (socket, peer_addr.addr)
+        }))
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.backlog.bell.as_raw_fd()
+    }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+enum BtSocketConnectState {
+    SDPSearch(BtUuid),
+    PortKnown(Port),
+    Connect,
+    Done,
+}
+
+/// Connects the socket with file descriptor `fd`, which outlives the connection process.
+#[derive(Debug)]
+pub struct BtSocketConnect {
+    stack: MockStack,
+    fd: RawFd,
+    protocol: BtProtocol,
+    peer_addr: Arc<Mutex<Option<BtSocketAddr>>>,
+    addr: BtAddr,
+    state: BtSocketConnectState,
+}
+
+impl Advance for BtSocketConnect {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        match self.state {
+            BtSocketConnectState::SDPSearch(service) => {
+                let channel = self.stack.lookup_channel(self.addr, service)?;
+                self.state = BtSocketConnectState::PortKnown(Port::Channel(channel));
+                self.advance()
+            }
+
+            BtSocketConnectState::PortKnown(port) => {
+                let connection = self.stack.open(self.addr, port, self.protocol)?;
+                attach(self.fd, connection)?;
+                *self.peer_addr.lock().unwrap() = Some(BtSocketAddr { addr: self.addr, channel: port.number() });
+
+                // The connection is established already, but callers wait for it like for a real one
+                self.state = BtSocketConnectState::Connect;
+                Ok(BtAsync::WaitFor(self, Interest::WRITABLE))
+            }
+
+            BtSocketConnectState::Connect => {
+                self.state = BtSocketConnectState::Done;
+                Ok(BtAsync::Done)
+            }
+
+            BtSocketConnectState::Done => {
+                panic!("Trying advance `BtSocketConnect` from `Done` state");
+            }
+        }
+    }
+
+    fn pollfd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl SocketConnect for BtSocketConnect {}
+
+impl mio::event::Source for BtSocketConnect {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.fd).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.fd).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.fd).deregister(registry)
+    }
+}
+
+
+/// A request answered by the stack once its bell has been waited for.
+#[derive(Debug)]
+enum Request {
+    New,
+    Waiting(Bell),
+    Done,
+}
+
+impl Request {
+    /// Returns `true` once the caller has waited for the answer.
+    fn advance(&mut self) -> Result<bool, BtError> {
+        match *self {
+            Request::New => {
+                *self = Request::Waiting(Bell::ringing()?);
+                Ok(false)
+            }
+            Request::Waiting(_) => {
+                *self = Request::Done;
+                Ok(true)
+            }
+            Request::Done => panic!("Trying advance a request from `Done` state"),
+        }
+    }
+
+    fn pollfd(&self) -> RawFd {
+        match *self {
+            Request::Waiting(ref bell) => bell.as_raw_fd(),
+            _ => -1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BtServiceBrowse {
+    stack: MockStack,
+    local_addr: BtAddr,
+    addr: BtAddr,
+    request: Request,
+    records: Option<Vec<BtServiceRecord>>,
+}
+
+impl Advance for BtServiceBrowse {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        if !self.request.advance()? {
+            return Ok(BtAsync::WaitFor(self, Interest::READABLE));
+        }
+
+        let mut state = self.stack.lock();
+        state.check_local_addr(self.local_addr)?;
+        self.records = Some(state.device(self.addr)?.records.clone());
+        Ok(BtAsync::Done)
+    }
+
+    fn pollfd(&self) -> RawFd {
+        self.request.pollfd()
+    }
+}
+
+impl ServiceBrowse for BtServiceBrowse {
+    fn take_records(&mut self) -> Option<Vec<BtServiceRecord>> {
+        self.records.take()
+    }
+}
+
+impl mio::event::Source for BtServiceBrowse {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.pollfd()).deregister(registry)
+    }
+}
+
+#[derive(Debug)]
+pub struct BtServiceRegistration {
+    stack: MockStack,
+    handle: u32,
+}
+
+impl ServiceRegistration for BtServiceRegistration {
+    fn handle(&self) -> u32 {
+        self.handle
+    }
+}
+
+impl Drop for BtServiceRegistration {
+    fn drop(&mut self) {
+        let handle = self.handle;
+        self.stack.lock().registrations.retain(|record| record.handle != handle);
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BtReadRemoteName {
+    stack: MockStack,
+    adapter: Option<u16>,
+    addr: BtAddr,
+    request: Request,
+    name: Option<String>,
+}
+
+impl Advance for BtReadRemoteName {
+    fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+        if !self.request.advance()? {
+            return Ok(BtAsync::WaitFor(self, Interest::READABLE));
+        }
+
+        let mut state = self.stack.lock();
+        state.adapter_up(self.adapter)?;
+        let device = state.device(self.addr)?;
+        self.name = Some(device.device.name.clone().unwrap_or_default());
+        Ok(BtAsync::Done)
+    }
+
+    fn pollfd(&self) -> RawFd {
+        self.request.pollfd()
+    }
+}
+
+impl ReadRemoteName for BtReadRemoteName {
+    fn take_name(&mut self) -> Option<String> {
+        self.name.take()
+    }
+}
+
+impl mio::event::Source for BtReadRemoteName {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.pollfd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.pollfd()).deregister(registry)
+    }
+}
+
+
+/// Returns the devices an inquiry with `options` finds, without their names.
+fn inquire(stack: &MockStack, options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+    let mut state = stack.lock();
+    state.adapter_up(options.adapter)?;
+    Ok(state.devices
+        .iter()
+        .take(options.max_responses as usize)
+        .map(|device| BtDevice { name: None, ..device.device.clone() })
+        .collect())
+}
+
+/// Runs a discovery, reporting all devices in range right away.
+///
+/// Scripted devices are reported as `InquiryResultWithRSSI` if they have a signal strength,
+/// otherwise as `InquiryResult`.
+#[derive(Debug)]
+pub struct BtDiscovery {
+    bell: Bell,
+    events: VecDeque<BtDiscoveryEvent>,
+    done: bool,
+}
+
+impl BtDiscovery {
+    fn start(stack: &MockStack, options: &ScanOptions) -> Result<BtDiscovery, BtError> {
+        let devices = inquire(stack, options)?;
+        let mut events: VecDeque<BtDiscoveryEvent> = devices.into_iter()
+            .map(|device| if device.rssi.is_some() {
+                BtDiscoveryEvent::InquiryResultWithRSSI(device)
+            } else {
+                BtDiscoveryEvent::InquiryResult(device)
+            })
+            .collect();
+        events.push_back(BtDiscoveryEvent::InquiryComplete);
+
+        Ok(BtDiscovery {
+            bell: Bell::ringing()?,
+            events,
+            done: false,
+        })
+    }
+}
+
+impl Discovery for BtDiscovery {
+    fn next_event(&mut self) -> Result<Option<BtDiscoveryEvent>, BtError> {
+        let event = self.events.pop_front();
+        match event {
+            Some(BtDiscoveryEvent::InquiryComplete) => self.done = true,
+            None => self.bell.silence(),
+            _ => {}
+        }
+        Ok(event)
+    }
+
+    fn cancel(&mut self) -> Result<(), BtError> {
+        // All events have been received already
+        Ok(())
+    }
+
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.bell.as_raw_fd()
+    }
+}
+
+impl Stack for MockStack {
+    fn socket(&self, protocol: BtProtocol) -> Result<Box<dyn Socket>, BtError> {
+        Ok(Box::new(BtSocket::new(self, protocol)?))
+    }
+
+    /// Stream sockets are taken for RFCOMM, sequential packet sockets for L2CAP.
+    unsafe fn socket_from_raw_fd(&self, fd: RawFd) -> Box<dyn Socket> {
+        let mut socket_type: libc::c_int = 0;
+        let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+        let value: *mut libc::c_int = &mut socket_type;
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE, value as *mut libc::c_void, &mut len);
+        let protocol = if socket_type == libc::SOCK_SEQPACKET { BtProtocol::L2CAP(L2capMode::SeqPacket) } else { BtProtocol::RFCOMM };
+        Box::new(BtSocket::with_stream(UnixStream::from_raw_fd(fd), protocol, self.clone()))
+    }
+
+    fn listener(&self, protocol: BtProtocol) -> Result<Box<dyn Listener>, BtError> {
+        Ok(Box::new(BtListener::new(self, protocol)?))
+    }
+
+    fn browse_services(&self, local_addr: BtAddr, addr: BtAddr) -> Box<dyn ServiceBrowse> {
+        Box::new(BtServiceBrowse {
+            stack: self.clone(),
+            local_addr,
+            addr,
+            request: Request::New,
+            records: None,
+        })
+    }
+
+    fn register_service(&self, record: &BtServiceRecord) -> Result<Box<dyn ServiceRegistration>, BtError> {
+        let handle = {
+            let mut state = self.lock();
+            let handle = state.next_handle;
+            state.next_handle += 1;
+            let mut record = record.clone();
+            record.handle = handle;
+            state.registrations.push(record);
+            handle
+        };
+        Ok(Box::new(BtServiceRegistration { stack: self.clone(), handle }))
+    }
+
+    /// The page scan parameters of the inquiry don't matter to the mock stack.
+    fn read_remote_name(&self, adapter: Option<u16>, addr: BtAddr, _inquiry_info: Option<(u8, Option<u16>)>)
+            -> Box<dyn ReadRemoteName> {
+        Box::new(BtReadRemoteName {
+            stack: self.clone(),
+            adapter,
+            addr,
+            request: Request::New,
+            name: None,
+        })
+    }
+
+    fn discover_devices(&self, options: &ScanOptions) -> Result<Box<dyn Discovery>, BtError> {
+        Ok(Box::new(BtDiscovery::start(self, options)?))
+    }
+
+    fn scan_devices(&self, options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
+        let mut devices = inquire(self, options)?;
+        if options.resolve_names {
+            let state = self.lock();
+            for device in devices.iter_mut() {
+                device.name = state.devices.iter().find(|known| known.device.addr == device.addr).and_then(|known| known.device.name.clone());
+            }
+        }
+        Ok(devices)
+    }
+
+    /// Raw HCI sockets are not emulated, opening one fails.
+    fn open_hci_socket(&self, adapter: Option<u16>) -> Result<Box<dyn Hci>, BtError> {
+        self.lock().adapter(adapter)?;
+        Err(BtError::Desc("Raw HCI sockets are not available on the mock stack".to_string()))
+    }
+
+    fn list_adapters(&self) -> Result<Vec<BtAdapter>, BtError> {
+        Ok(self.lock()
+            .adapters
+            .iter()
+            .map(|adapter| BtAdapter { name: if adapter.up { adapter.name.clone() } else { None }, ..adapter.clone() })
+            .collect())
+    }
+
+    fn default_adapter(&self) -> Result<BtAdapter, BtError> {
+        let adapters = self.list_adapters()?;
+        adapters.into_iter().next().ok_or_else(|| create_error_of_kind(BtErrorKind::AdapterNotFound, "No Bluetooth adapter available", libc::ENODEV))
+    }
+
+    fn read_local_name(&self, adapter: u16) -> Result<String, BtError> {
+        let mut state = self.lock();
+        let adapter = state.adapter_up(Some(adapter))?;
+        Ok(adapter.name.clone().unwrap_or_default())
+    }
+
+    fn set_adapter_up(&self, adapter: u16, up: bool) -> Result<(), BtError> {
+        let mut state = self.lock();
+        state.adapter(Some(adapter))?.up = up;
+        Ok(())
+    }
+
+    fn write_scan_enable(&self, adapter: u16, _discoverable: bool, _connectable: bool) -> Result<(), BtError> {
+        let mut state = self.lock();
+        state.adapter_up(Some(adapter))?;
+        Ok(())
+    }
+
+    fn write_local_name(&self, adapter: u16, name: &str) -> Result<(), BtError> {
+        let mut state = self.lock();
+        state.adapter_up(Some(adapter))?.name = Some(name.to_string());
+        Ok(())
+    }
+
+    fn write_class_of_device(&self, adapter: u16, _class: DeviceClass) -> Result<(), BtError> {
+        let mut state = self.lock();
+        state.adapter_up(Some(adapter))?;
+        Ok(())
+    }
+
+    fn write_inquiry_mode(&self, adapter: Option<u16>, _mode: InquiryMode) -> Result<(), BtError> {
+        let mut state = self.lock();
+        state.adapter_up(adapter)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bluetooth::{browse_services, discover_devices, read_remote_name, register_service, scan_devices,
+                    scan_devices_with, BtErrorKind, BtListener, BtSocket};
+    use platform::{list_adapters, read_local_name, set_adapter_up, write_local_name};
+
+    /// Installs a new stack, as tests may run one after another on the same thread.
+    fn stack() -> MockStack {
+        let stack = MockStack::new();
+        stack.install();
+        stack
+    }
+
+    fn sensor() -> BtAddr {
+        BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])
+    }
+
+    fn add_sensor(stack: &MockStack) {
+        stack.add_device(BtDevice::new("Sensor".to_string(), sensor()));
+        stack.add_service(sensor(), BtServiceRecord::rfcomm(BtUuid::serial_port(), 3));
+    }
+
+    #[test]
+    fn scans_scripted_devices() {
+        let stack = stack();
+        add_sensor(&stack);
+        let mut headset = BtDevice::new("Headset".to_string(), BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x66]));
+        headset.rssi = Some(-60);
+        stack.add_device(headset);
+
+        let devices = scan_devices().unwrap();
+        assert_eq!(devices.iter().map(|device| device.name.clone()).collect::<Vec<_>>(),
+                   vec![Some("Sensor".to_string()), Some("Headset".to_string())]);
+
+        let devices = scan_devices_with(ScanOptions::new().resolve_names(false).max_responses(1)).unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, None);
+
+        let mut discovery = discover_devices(ScanOptions::new()).unwrap();
+        match discovery.next_event().unwrap() {
+            Some(BtDiscoveryEvent::InquiryResult(device)) => assert_eq!(device.addr, sensor()),
+            event => panic!("unexpected event {:?}", event),
+        }
+        match discovery.next_event().unwrap() {
+            Some(BtDiscoveryEvent::InquiryResultWithRSSI(device)) => assert_eq!(device.rssi, Some(-60)),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(discovery.next_event().unwrap(), Some(BtDiscoveryEvent::InquiryComplete));
+        assert!(discovery.is_done());
+    }
+
+    #[test]
+    fn fails_scans_on_adapters_which_are_down() {
+        stack();
+        set_adapter_up(0, false).unwrap();
+        assert!(scan_devices().is_err());
+        assert_eq!(list_adapters().unwrap()[0].name, None);
+
+        set_adapter_up(0, true).unwrap();
+        write_local_name(0, "renamed").unwrap();
+        assert_eq!(read_local_name(0).unwrap(), "renamed");
+        assert_eq!(set_adapter_up(1, true).unwrap_err().kind(), BtErrorKind::AdapterNotFound);
+    }
+
+    #[test]
+    fn connects_and_exchanges_data() {
+        let stack = stack();
+        add_sensor(&stack);
+        let peer = stack.listen(sensor(), 3);
+
+        let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        socket.connect(sensor()).unwrap();
+        assert_eq!(socket.peer_addr().unwrap(), BtSocketAddr { addr: sensor(), channel: 3 });
+
+        let mut remote = peer.try_accept().unwrap();
+        socket.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        remote.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        remote.write_all(b"pong").unwrap();
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // The remote device hangs up
+        drop(remote);
+        assert_eq!(socket.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn connects_to_l2cap_psms() {
+        let stack = stack();
+        add_sensor(&stack);
+        let peer = stack.listen_psm(sensor(), 0x1001);
+
+        let mut socket = BtSocket::new(BtProtocol::L2CAP(L2capMode::SeqPacket)).unwrap();
+        socket.connect_psm(sensor(), 0x1001).unwrap();
+        let mut remote = peer.accept().unwrap();
+
+        // Packet boundaries are kept
+        socket.write_all(b"first").unwrap();
+        socket.write_all(b"second").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(remote.read(&mut buf).unwrap(), 5);
+        assert_eq!(remote.read(&mut buf).unwrap(), 6);
+
+        let mut socket = BtSocket::new(BtProtocol::L2CAP(L2capMode::SeqPacket)).unwrap();
+        assert!(socket.connect_channel(sensor(), 3).is_err());
+    }
+
+    #[test]
+    fn reports_connection_errors() {
+        let stack = stack();
+        let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        assert_eq!(socket.connect(sensor()).unwrap_err().kind(), BtErrorKind::HostDown);
+
+        add_sensor(&stack);
+        let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        assert_eq!(socket.connect(sensor()).unwrap_err().kind(), BtErrorKind::ConnectionRefused);
+
+        // Dropping the peer refuses further connections
+        drop(stack.listen(sensor(), 3));
+        let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        assert_eq!(socket.connect(sensor()).unwrap_err().kind(), BtErrorKind::ConnectionRefused);
+
+        let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        assert_eq!(socket.connect_service(sensor(), BtUuid::obex_object_push()).unwrap_err().kind(),
+                   BtErrorKind::ServiceNotFound);
+    }
+
+    #[test]
+    fn accepts_incoming_connections() {
+        let stack = stack();
+        let listener = BtListener::new(BtProtocol::RFCOMM).unwrap();
+        listener.bind(BtAddr::any(), 0).unwrap();
+        listener.listen(1).unwrap();
+        let channel = listener.local_addr().unwrap().channel as u8;
+        assert_eq!(channel, 1);
+
+        let other = BtListener::new(BtProtocol::RFCOMM).unwrap();
+        other.bind(BtAddr::any(), channel).unwrap_err();
+
+        let mut remote = stack.connect(sensor(), channel).unwrap();
+        remote.write_all(b"hello").unwrap();
+        let (mut socket, addr) = listener.accept().unwrap();
+        assert_eq!(addr, sensor());
+        assert_eq!(socket.peer_addr().unwrap(), BtSocketAddr { addr: sensor(), channel: channel as u16 });
+
+        let mut buf = [0u8; 5];
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert!(listener.try_accept().unwrap().is_none());
+
+        drop(listener);
+        assert_eq!(stack.connect(sensor(), channel).unwrap_err().kind(), BtErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn browses_services_and_reads_names() {
+        let stack = stack();
+        add_sensor(&stack);
+
+        let records = browse_services(sensor()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].rfcomm_channel(), Some(3));
+        assert_eq!(records[0].handle, 0x00010000);
+        assert_eq!(read_remote_name(sensor()).unwrap(), "Sensor");
+
+        let registration = register_service(&BtServiceRecord::rfcomm(BtUuid::serial_port(), 5)).unwrap();
+        assert_eq!(stack.registered_services()[0].handle, registration.handle());
+        drop(registration);
+        assert!(stack.registered_services().is_empty());
+    }
+
+    #[test]
+    fn shares_stacks_between_threads() {
+        let stack = stack();
+        add_sensor(&stack);
+        let peer = stack.listen(sensor(), 3);
+
+        let shared = stack.clone();
+        let thread = std::thread::spawn(move || {
+            shared.install();
+            let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+            socket.connect(sensor()).unwrap();
+            socket.write_all(b"!").unwrap();
+        });
+
+        let mut remote = peer.accept().unwrap();
+        let mut buf = [0u8; 1];
+        remote.read_exact(&mut buf).unwrap();
+        thread.join().unwrap();
+    }
+}
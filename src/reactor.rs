@@ -0,0 +1,330 @@
+//! Futures for the asynchronous processes, independent of any particular async runtime.
+//!
+//! The runtime in use supplies a `BtReactor`, which waits for raw file descriptors to become
+//! ready. Each future drives the same state machine as its `advance` method.
+
+use std::future::Future;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bluetooth::{Advance, BtAsync, BtError, BtServiceBrowse, BtServiceRecord, BtSocketConnect};
+
+/// The readiness a future waits for on a file descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BtInterest {
+    /// Data can be read from the file descriptor.
+    Readable,
+
+    /// Data can be written to the file descriptor, or a connection attempt has finished.
+    Writable,
+}
+
+/// Notifies futures when file descriptors become ready, supplied by the async runtime in use.
+///
+/// Implementations usually wrap the reactor of the runtime, like `async_io::Async` for async-std
+/// and smol.
+pub trait BtReactor {
+    /// Returns `Ready` if `fd` is ready for `interest`, otherwise arranges for the waker in `cx` to
+    /// be woken once it is and returns `Pending`.
+    ///
+    /// `Ready` may be returned spuriously, the future then calls this function again. It must not
+    /// keep reporting a file descriptor as ready which is not, or the future spins.
+    fn poll_ready(&self, fd: RawFd, interest: BtInterest, cx: &mut Context) -> Poll<io::Result<()>>;
+}
+
+impl<R: BtReactor + ?Sized> BtReactor for &R {
+    fn poll_ready(&self, fd: RawFd, interest: BtInterest, cx: &mut Context) -> Poll<io::Result<()>> {
+        (**self).poll_ready(fd, interest, cx)
+    }
+}
+
+impl<R: BtReactor + ?Sized> BtReactor for Arc<R> {
+    fn poll_ready(&self, fd: RawFd, interest: BtInterest, cx: &mut Context) -> Poll<io::Result<()>> {
+        (**self).poll_ready(fd, interest, cx)
+    }
+}
+
+/// Advance `process` as far as possible without blocking, waiting for `wait` through `reactor`.
+fn poll_advance<A: Advance, R: BtReactor>(process: &mut A,
+                                          reactor: &R,
+                                          wait: &mut Option<(RawFd, BtInterest)>,
+                                          cx: &mut Context)
+                                          -> Poll<Result<(), BtError>> {
+    loop {
+        if let Some((fd, interest)) = *wait {
+            match reactor.poll_ready(fd, interest, cx) {
+                Poll::Ready(Ok(())) => *wait = None,
+                Poll::Ready(Err(error)) => {
+                    let errno = error.raw_os_error().unwrap_or(0) as u32;
+                    return Poll::Ready(Err(BtError::Errno(errno, format!("Waiting for file descriptor failed: {}", error))));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let interest = match process.advance() {
+            Ok(BtAsync::WaitFor(_, interest)) if interest.is_writable() => BtInterest::Writable,
+            Ok(BtAsync::WaitFor(_, _)) => BtInterest::Readable,
+            Ok(BtAsync::Done) => return Poll::Ready(Ok(())),
+            Err(error) => return Poll::Ready(Err(error)),
+        };
+        *wait = Some((process.pollfd(), interest));
+    }
+}
+
+
+/// A future connecting a socket, created by `BtSocketConnect::with_reactor`.
+///
+/// Dropping it before completion aborts the connection attempt.
+#[derive(Debug)]
+pub struct BtSocketConnectFuture<'a, R> {
+    connect: BtSocketConnect<'a>,
+    reactor: R,
+    wait: Option<(RawFd, BtInterest)>,
+    // Switching the socket to non-blocking mode failed, reported by the first poll
+    error: Option<BtError>,
+    done: bool,
+}
+
+impl<'a, R: BtReactor> BtSocketConnectFuture<'a, R> {
+    pub(crate) fn new(connect: BtSocketConnect<'a>, reactor: R, nonblocking: Result<(), BtError>)
+                      -> BtSocketConnectFuture<'a, R> {
+        BtSocketConnectFuture {
+            connect,
+            reactor,
+            wait: None,
+            error: nonblocking.err(),
+            done: false,
+        }
+    }
+}
+
+impl<'a, R: BtReactor + Unpin> Future for BtSocketConnectFuture<'a, R> {
+    type Output = Result<(), BtError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), BtError>> {
+        let this = &mut *self;
+        assert!(!this.done, "BtSocketConnectFuture polled after completion");
+        if let Some(error) = this.error.take() {
+            this.done = true;
+            return Poll::Ready(Err(error));
+        }
+
+        let result = poll_advance(&mut this.connect, &this.reactor, &mut this.wait, cx);
+        if result.is_ready() {
+            this.done = true;
+        }
+        result
+    }
+}
+
+
+/// A future listing the SDP service records of a remote device, created by
+/// `BtServiceBrowse::with_reactor`.
+#[derive(Debug)]
+pub struct BtServiceBrowseFuture<R> {
+    browse: BtServiceBrowse,
+    reactor: R,
+    wait: Option<(RawFd, BtInterest)>,
+}
+
+impl<R: BtReactor> BtServiceBrowseFuture<R> {
+    pub(crate) fn new(browse: BtServiceBrowse, reactor: R) -> BtServiceBrowseFuture<R> {
+        BtServiceBrowseFuture {
+            browse,
+            reactor,
+            wait: None,
+        }
+    }
+}
+
+impl<R: BtReactor + Unpin> Future for BtServiceBrowseFuture<R> {
+    type Output = Result<Vec<BtServiceRecord>, BtError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Vec<BtServiceRecord>, BtError>> {
+        let this = &mut *self;
+        match poll_advance(&mut this.browse, &this.reactor, &mut this.wait, cx) {
+            Poll::Ready(Ok(())) => {
+                Poll::Ready(Ok(this.browse.take_records().expect("BtServiceBrowseFuture polled after completion")))
+            }
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::task::Waker;
+    use mio;
+    use mio::unix::SourceFd;
+    #[cfg(feature = "mock")]
+    use bluetooth::{browse_services_async, BtAddr, BtDevice, BtErrorKind, BtProtocol, BtSocket, BtUuid};
+    #[cfg(feature = "mock")]
+    use mock::MockStack;
+
+    /// Checks readiness with `poll(2)` and asks to be polled again right away if not ready.
+    struct PollReactor;
+
+    impl BtReactor for PollReactor {
+        fn poll_ready(&self, fd: RawFd, interest: BtInterest, cx: &mut Context) -> Poll<io::Result<()>> {
+            let events = match interest {
+                BtInterest::Readable => libc::POLLIN,
+                BtInterest::Writable => libc::POLLOUT,
+            };
+            let mut pollfd = libc::pollfd { fd, events, revents: 0 };
+            if unsafe { libc::poll(&mut pollfd, 1, 0) } > 0 {
+                Poll::Ready(Ok(()))
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Reads one message, waiting for the socket to become readable first.
+    struct ReadMessage {
+        socket: UnixStream,
+        message: Option<Vec<u8>>,
+    }
+
+    impl Advance for ReadMessage {
+        fn advance(&mut self) -> Result<BtAsync<'_>, BtError> {
+            let mut buf = [0u8; 16];
+            match self.socket.read(&mut buf) {
+                Ok(size) => {
+                    self.message = Some(buf[..size].to_vec());
+                    Ok(BtAsync::Done)
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    Ok(BtAsync::WaitFor(self, mio::Interest::READABLE))
+                }
+                Err(error) => Err(BtError::Desc(error.to_string())),
+            }
+        }
+
+        fn pollfd(&self) -> RawFd {
+            self.socket.as_raw_fd()
+        }
+    }
+
+    impl mio::event::Source for ReadMessage {
+        fn register(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+            SourceFd(&self.socket.as_raw_fd()).register(registry, token, interests)
+        }
+
+        fn reregister(&mut self, registry: &mio::Registry, token: mio::Token, interests: mio::Interest) -> io::Result<()> {
+            SourceFd(&self.socket.as_raw_fd()).reregister(registry, token, interests)
+        }
+
+        fn deregister(&mut self, registry: &mio::Registry) -> io::Result<()> {
+            SourceFd(&self.socket.as_raw_fd()).deregister(registry)
+        }
+    }
+
+    /// Polls `future` until it is ready, `PollReactor` asks to be polled again right away.
+    #[cfg(feature = "mock")]
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Installs a new mock stack with a device offering the serial port service on channel 3.
+    #[cfg(feature = "mock")]
+    fn sensor_stack() -> (MockStack, BtAddr) {
+        let addr = BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let stack = MockStack::new();
+        stack.install();
+        stack.add_device(BtDevice::new("Sensor".to_string(), addr));
+        stack.add_service(addr, BtServiceRecord::rfcomm(BtUuid::serial_port(), 3));
+        (stack, addr)
+    }
+
+    #[test]
+    fn advances_through_reactor() {
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut process = ReadMessage { socket, message: None };
+        let mut wait = None;
+        let mut cx = Context::from_waker(Waker::noop());
+
+        assert!(poll_advance(&mut process, &PollReactor, &mut wait, &mut cx).is_pending());
+        assert_eq!(wait, Some((process.socket.as_raw_fd(), BtInterest::Readable)));
+        assert!(poll_advance(&mut process, &PollReactor, &mut wait, &mut cx).is_pending());
+
+        peer.write_all(b"ping").unwrap();
+        match poll_advance(&mut process, &PollReactor, &mut wait, &mut cx) {
+            Poll::Ready(Ok(())) => assert_eq!(process.message, Some(b"ping".to_vec())),
+            _ => panic!("process did not finish once the socket became readable"),
+        }
+    }
+
+    #[test]
+    fn reports_reactor_errors() {
+        struct FailingReactor;
+        impl BtReactor for FailingReactor {
+            fn poll_ready(&self, _: RawFd, _: BtInterest, _: &mut Context) -> Poll<io::Result<()>> {
+                Poll::Ready(Err(io::Error::from_raw_os_error(libc::EBADF)))
+            }
+        }
+
+        let (socket, _peer) = UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut process = ReadMessage { socket, message: None };
+        let mut wait = None;
+        let mut cx = Context::from_waker(Waker::noop());
+
+        match poll_advance(&mut process, &FailingReactor, &mut wait, &mut cx) {
+            Poll::Ready(Err(error)) => assert_eq!(error.errno(), Some(libc::EBADF as u32)),
+            _ => panic!("reactor error was not reported"),
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn connects_socket_through_reactor() {
+        let (stack, addr) = sensor_stack();
+        let peer = stack.listen(addr, 3);
+
+        // New sockets are blocking
+        let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        block_on(socket.connect_async(addr).with_reactor(PollReactor)).unwrap();
+        let mut remote = peer.try_accept().unwrap();
+
+        // The socket stays non-blocking, so reading it doesn't stall the executor
+        let mut buf = [0u8; 4];
+        assert_eq!(socket.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        remote.write_all(b"ping").unwrap();
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        let unknown = BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x66]);
+        let mut socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        match block_on(socket.connect_async(unknown).with_reactor(PollReactor)) {
+            Err(error) => assert_eq!(error.kind(), BtErrorKind::HostDown),
+            Ok(()) => panic!("connected to a device which is not in range"),
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn browses_services_through_reactor() {
+        let (_stack, addr) = sensor_stack();
+
+        let records = block_on(browse_services_async(addr).with_reactor(PollReactor)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].rfcomm_channel(), Some(3));
+    }
+}
@@ -0,0 +1,651 @@
+use bluetooth::{BtError, BtProfileDescriptor, BtProtocolDescriptor, BtServiceRecord, BtUuid};
+
+use enum_primitive::FromPrimitive;
+
+enum_from_primitive!{
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum SdpPdu {
+        DataNil            = 0x00,
+        Uint8               = 0x08,
+        Uint16              = 0x09,
+        Uint32              = 0x0A,
+        Uint64              = 0x0B,
+        Uint128             = 0x0C,
+        Int8                = 0x10,
+        Int16               = 0x11,
+        Int32               = 0x12,
+        Int64               = 0x13,
+        Int128              = 0x14,
+        UuidUnspec         = 0x18,
+        Uuid16              = 0x19,
+        Uuid32              = 0x1A,
+        Uuid128             = 0x1C,
+        TextStrUnspec     = 0x20,
+        TextStr8           = 0x25,
+        TextStr16          = 0x26,
+        TextStr32          = 0x27,
+        Bool                = 0x28,
+        SeqUnspec          = 0x30,
+        Seq8                = 0x35,
+        Seq16               = 0x36,
+        Seq32               = 0x37,
+        AltUnspec          = 0x38,
+        Alt8                = 0x3D,
+        Alt16               = 0x3E,
+        Alt32               = 0x3F,
+        UrlStrUnspec      = 0x40,
+        UrlStr8            = 0x45,
+        UrlStr16           = 0x46,
+        UrlStr32           = 0x47,
+    }
+}
+
+/// Attribute IDs of the universal attributes evaluated by this library.
+pub enum SdpAttr {
+    ServiceRecordHandle = 0x0000,
+    ServiceClassIdList = 0x0001,
+    ProtocolDescriptorList = 0x0004,
+    BrowseGroupList = 0x0005,
+    LanguageBaseAttributeIdList = 0x0006,
+    ProfileDescriptorList = 0x0009,
+    ServiceNamePrimary = 0x0100,
+    ServiceDescPrimary = 0x0101,
+    ProviderNamePrimary = 0x0102,
+}
+
+pub enum SdpServiceClass {
+    PublicBrowseGroup = 0x1002,
+}
+
+/// A data element as used by the Service Discovery Protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SdpValue {
+    /// The null value.
+    Nil,
+
+    /// An 8-bit unsigned integer.
+    Uint8(u8),
+
+    /// A 16-bit unsigned integer.
+    Uint16(u16),
+
+    /// A 32-bit unsigned integer.
+    Uint32(u32),
+
+    /// A 64-bit unsigned integer.
+    Uint64(u64),
+
+    /// A 128-bit unsigned integer in big-endian byte order.
+    Uint128([u8; 16]),
+
+    /// An 8-bit signed integer.
+    Int8(i8),
+
+    /// A 16-bit signed integer.
+    Int16(i16),
+
+    /// A 32-bit signed integer.
+    Int32(i32),
+
+    /// A 64-bit signed integer.
+    Int64(i64),
+
+    /// A 128-bit signed integer in big-endian byte order.
+    Int128([u8; 16]),
+
+    /// A 16, 32 or 128-bit UUID.
+    Uuid(BtUuid),
+
+    /// A text string. The encoding is defined by the language base attribute of the record and
+    /// usually is UTF-8.
+    Text(Vec<u8>),
+
+    /// A boolean.
+    Bool(bool),
+
+    /// A sequence of data elements.
+    Sequence(Vec<SdpValue>),
+
+    /// A sequence of data elements from which one is to be selected.
+    Alternative(Vec<SdpValue>),
+
+    /// An URL.
+    Url(String),
+}
+
+fn truncated_error() -> BtError {
+    BtError::Desc("SDP data element is truncated".to_string())
+}
+
+fn read_be(data: &[u8]) -> u64 {
+    data.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn write_be(buf: &mut Vec<u8>, value: u64, size: usize) {
+    for i in (0..size).rev() {
+        buf.push((value >> (8 * i)) as u8);
+    }
+}
+
+/// The maximum number of sequences and alternatives nested in each other which are decoded.
+const MAX_NESTING_DEPTH: usize = 32;
+
+fn to_array16(data: &[u8]) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(data);
+    bytes
+}
+
+impl SdpValue {
+    /// Decodes the data element at the start of `data`.
+    ///
+    /// Returns the data element together with the number of bytes it occupies.
+    pub fn decode(data: &[u8]) -> Result<(SdpValue, usize), BtError> {
+        SdpValue::decode_nested(data, 0)
+    }
+
+    /// Decodes the data element at the start of `data`, which is nested in `depth` sequences or
+    /// alternatives.
+    fn decode_nested(data: &[u8], depth: usize) -> Result<(SdpValue, usize), BtError> {
+        let descriptor = *data.first().ok_or_else(truncated_error)?;
+        let pdu = SdpPdu::from_u8(descriptor)
+                           .ok_or_else(|| BtError::Desc(format!("Invalid SDP data element descriptor 0x{:02X}", descriptor)))?;
+
+        // Size of the data in bytes is either implied by the size index or given by an additional length field
+        let (header_len, data_len) = match descriptor & 0x07 {
+            0 if pdu == SdpPdu::DataNil => (1, 0),
+            index @ 0..=4 => (1, 1 << index),
+            index => {
+                let length_len = 1 << (index - 5);
+                if data.len() < 1 + length_len {
+                    return Err(truncated_error());
+                }
+                (1 + length_len, read_be(&data[1..1 + length_len]) as usize)
+            }
+        };
+        if data.len() - header_len < data_len {
+            return Err(truncated_error());
+        }
+        let body = &data[header_len..header_len + data_len];
+
+        let value = match pdu {
+            SdpPdu::DataNil => SdpValue::Nil,
+            SdpPdu::Uint8 => SdpValue::Uint8(body[0]),
+            SdpPdu::Uint16 => SdpValue::Uint16(read_be(body) as u16),
+            SdpPdu::Uint32 => SdpValue::Uint32(read_be(body) as u32),
+            SdpPdu::Uint64 => SdpValue::Uint64(read_be(body)),
+            SdpPdu::Uint128 => SdpValue::Uint128(to_array16(body)),
+            SdpPdu::Int8 => SdpValue::Int8(body[0] as i8),
+            SdpPdu::Int16 => SdpValue::Int16(read_be(body) as i16),
+            SdpPdu::Int32 => SdpValue::Int32(read_be(body) as i32),
+            SdpPdu::Int64 => SdpValue::Int64(read_be(body) as i64),
+            SdpPdu::Int128 => SdpValue::Int128(to_array16(body)),
+            SdpPdu::Uuid16 => SdpValue::Uuid(BtUuid::Uuid16(read_be(body) as u16)),
+            SdpPdu::Uuid32 => SdpValue::Uuid(BtUuid::Uuid32(read_be(body) as u32)),
+            SdpPdu::Uuid128 => SdpValue::Uuid(BtUuid::Uuid128(to_array16(body))),
+            SdpPdu::TextStr8 | SdpPdu::TextStr16 | SdpPdu::TextStr32 => SdpValue::Text(body.to_vec()),
+            SdpPdu::Bool => SdpValue::Bool(body[0] != 0),
+            SdpPdu::Seq8 | SdpPdu::Seq16 | SdpPdu::Seq32 => SdpValue::Sequence(SdpValue::decode_list(body, depth + 1)?),
+            SdpPdu::Alt8 | SdpPdu::Alt16 | SdpPdu::Alt32 => SdpValue::Alternative(SdpValue::decode_list(body, depth + 1)?),
+            SdpPdu::UrlStr8 | SdpPdu::UrlStr16 | SdpPdu::UrlStr32 => SdpValue::Url(String::from_utf8_lossy(body).into_owned()),
+            SdpPdu::UuidUnspec | SdpPdu::TextStrUnspec | SdpPdu::SeqUnspec | SdpPdu::AltUnspec | SdpPdu::UrlStrUnspec => {
+                return Err(BtError::Desc(format!("Invalid SDP data element descriptor 0x{:02X}", descriptor)));
+            }
+        };
+
+        Ok((value, header_len + data_len))
+    }
+
+    /// Decodes a list of data elements which fill all of `data`.
+    pub fn decode_all(data: &[u8]) -> Result<Vec<SdpValue>, BtError> {
+        SdpValue::decode_list(data, 0)
+    }
+
+    fn decode_list(mut data: &[u8], depth: usize) -> Result<Vec<SdpValue>, BtError> {
+        // Data comes from remote devices, which must not be able to exhaust the stack
+        if depth > MAX_NESTING_DEPTH {
+            return Err(BtError::Desc("SDP data elements are nested too deeply".to_string()));
+        }
+
+        let mut values = Vec::new();
+        while !data.is_empty() {
+            let (value, size) = SdpValue::decode_nested(data, depth)?;
+            values.push(value);
+            data = &data[size..];
+        }
+        Ok(values)
+    }
+
+    /// Appends the encoded data element to `buf`.
+    ///
+    /// Variable sized data elements are encoded using the shortest possible length field.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        fn encode_var(buf: &mut Vec<u8>, pdu8: SdpPdu, data: &[u8]) {
+            let (size_index, length_len) = if data.len() <= 0xFF {
+                (0, 1)
+            } else if data.len() <= 0xFFFF {
+                (1, 2)
+            } else {
+                (2, 4)
+            };
+            buf.push(pdu8 as u8 + size_index);
+            write_be(buf, data.len() as u64, length_len);
+            buf.extend_from_slice(data);
+        }
+
+        fn encode_list(values: &[SdpValue]) -> Vec<u8> {
+            let mut data = Vec::new();
+            for value in values {
+                value.encode(&mut data);
+            }
+            data
+        }
+
+        match self {
+            &SdpValue::Nil => buf.push(SdpPdu::DataNil as u8),
+            &SdpValue::Uint8(value) => {
+                buf.push(SdpPdu::Uint8 as u8);
+                buf.push(value);
+            }
+            &SdpValue::Uint16(value) => {
+                buf.push(SdpPdu::Uint16 as u8);
+                write_be(buf, value as u64, 2);
+            }
+            &SdpValue::Uint32(value) => {
+                buf.push(SdpPdu::Uint32 as u8);
+                write_be(buf, value as u64, 4);
+            }
+            &SdpValue::Uint64(value) => {
+                buf.push(SdpPdu::Uint64 as u8);
+                write_be(buf, value, 8);
+            }
+            SdpValue::Uint128(value) => {
+                buf.push(SdpPdu::Uint128 as u8);
+                buf.extend_from_slice(value);
+            }
+            &SdpValue::Int8(value) => {
+                buf.push(SdpPdu::Int8 as u8);
+                buf.push(value as u8);
+            }
+            &SdpValue::Int16(value) => {
+                buf.push(SdpPdu::Int16 as u8);
+                write_be(buf, value as u16 as u64, 2);
+            }
+            &SdpValue::Int32(value) => {
+                buf.push(SdpPdu::Int32 as u8);
+                write_be(buf, value as u32 as u64, 4);
+            }
+            &SdpValue::Int64(value) => {
+                buf.push(SdpPdu::Int64 as u8);
+                write_be(buf, value as u64, 8);
+            }
+            SdpValue::Int128(value) => {
+                buf.push(SdpPdu::Int128 as u8);
+                buf.extend_from_slice(value);
+            }
+            &SdpValue::Uuid(BtUuid::Uuid16(value)) => {
+                buf.push(SdpPdu::Uuid16 as u8);
+                write_be(buf, value as u64, 2);
+            }
+            &SdpValue::Uuid(BtUuid::Uuid32(value)) => {
+                buf.push(SdpPdu::Uuid32 as u8);
+                write_be(buf, value as u64, 4);
+            }
+            &SdpValue::Uuid(BtUuid::Uuid128(ref value)) => {
+                buf.push(SdpPdu::Uuid128 as u8);
+                buf.extend_from_slice(value);
+            }
+            SdpValue::Text(value) => encode_var(buf, SdpPdu::TextStr8, value),
+            &SdpValue::Bool(value) => {
+                buf.push(SdpPdu::Bool as u8);
+                buf.push(value as u8);
+            }
+            SdpValue::Sequence(values) => encode_var(buf, SdpPdu::Seq8, &encode_list(values)),
+            SdpValue::Alternative(values) => encode_var(buf, SdpPdu::Alt8, &encode_list(values)),
+            SdpValue::Url(value) => encode_var(buf, SdpPdu::UrlStr8, value.as_bytes()),
+        }
+    }
+
+    /// Returns the encoded data element.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+
+    /// Returns the value of an unsigned integer of up to 64 bits.
+    pub fn as_uint(&self) -> Option<u64> {
+        match *self {
+            SdpValue::Uint8(value) => Some(value as u64),
+            SdpValue::Uint16(value) => Some(value as u64),
+            SdpValue::Uint32(value) => Some(value as u64),
+            SdpValue::Uint64(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an UUID.
+    pub fn as_uuid(&self) -> Option<BtUuid> {
+        match self {
+            &SdpValue::Uuid(uuid) => Some(uuid),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a text string up to the first NUL character, replacing invalid UTF-8.
+    pub fn as_text(&self) -> Option<String> {
+        match self {
+            SdpValue::Text(value) => {
+                let end = value.iter().position(|&byte| byte == 0).unwrap_or(value.len());
+                Some(String::from_utf8_lossy(&value[..end]).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of a sequence or an alternative.
+    pub fn as_list(&self) -> Option<&[SdpValue]> {
+        match self {
+            &SdpValue::Sequence(ref values) |
+            &SdpValue::Alternative(ref values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Builds a `BtServiceRecord` from an attribute list, i.e. a sequence of attribute ID and
+/// attribute value pairs.
+///
+/// Malformed attribute values are ignored.
+pub fn parse_service_record(attributes: &SdpValue) -> Result<BtServiceRecord, BtError> {
+    let invalid_error = || BtError::Desc("Invalid SDP attribute list".to_string());
+
+    let attributes = attributes.as_list().ok_or_else(&invalid_error)?;
+    if attributes.len() % 2 != 0 {
+        return Err(invalid_error());
+    }
+
+    let mut record = BtServiceRecord {
+        handle: 0,
+        service_classes: Vec::new(),
+        name: None,
+        description: None,
+        provider: None,
+        protocols: Vec::new(),
+        profiles: Vec::new(),
+    };
+
+    for pair in attributes.chunks(2) {
+        let attr_id = match &pair[0] {
+            &SdpValue::Uint16(attr_id) => attr_id,
+            _ => return Err(invalid_error()),
+        };
+        let value = &pair[1];
+
+        match attr_id {
+            id if id == SdpAttr::ServiceRecordHandle as u16 => {
+                record.handle = value.as_uint().unwrap_or(0) as u32;
+            }
+            id if id == SdpAttr::ServiceClassIdList as u16 => {
+                record.service_classes = value.as_list().unwrap_or(&[]).iter().filter_map(SdpValue::as_uuid).collect();
+            }
+            id if id == SdpAttr::ProtocolDescriptorList as u16 => {
+                // Either a single protocol stack or an alternative of several protocol stacks
+                let stacks = match value {
+                    SdpValue::Alternative(stacks) => stacks.iter().collect(),
+                    stack => vec![stack],
+                };
+                for stack in stacks {
+                    for descriptor in stack.as_list().unwrap_or(&[]) {
+                        let descriptor = descriptor.as_list().unwrap_or(&[]);
+                        if let Some(protocol) = descriptor.first().and_then(SdpValue::as_uuid) {
+                            record.protocols.push(BtProtocolDescriptor {
+                                protocol,
+                                parameter: descriptor.get(1).and_then(SdpValue::as_uint).map(|parameter| parameter as u16),
+                            });
+                        }
+                    }
+                }
+            }
+            id if id == SdpAttr::ProfileDescriptorList as u16 => {
+                for descriptor in value.as_list().unwrap_or(&[]) {
+                    let descriptor = descriptor.as_list().unwrap_or(&[]);
+                    if let Some(profile) = descriptor.first().and_then(SdpValue::as_uuid) {
+                        record.profiles.push(BtProfileDescriptor {
+                            profile,
+                            version: descriptor.get(1).and_then(SdpValue::as_uint).unwrap_or(0) as u16,
+                        });
+                    }
+                }
+            }
+            id if id == SdpAttr::ServiceNamePrimary as u16 => record.name = value.as_text(),
+            id if id == SdpAttr::ServiceDescPrimary as u16 => record.description = value.as_text(),
+            id if id == SdpAttr::ProviderNamePrimary as u16 => record.provider = value.as_text(),
+            _ => {}
+        }
+    }
+
+    Ok(record)
+}
+
+/// Builds the attribute list of `record`, ready to be registered with an SDP server.
+///
+/// The record is added to the public browse group. The record handle is only included if it is
+/// non-zero, otherwise the server assigns one.
+pub fn build_service_record(record: &BtServiceRecord) -> SdpValue {
+    let mut attributes = Vec::new();
+    {
+        let mut add = |attr: SdpAttr, value: SdpValue| {
+            attributes.push(SdpValue::Uint16(attr as u16));
+            attributes.push(value);
+        };
+
+        if record.handle != 0 {
+            add(SdpAttr::ServiceRecordHandle, SdpValue::Uint32(record.handle));
+        }
+        add(SdpAttr::ServiceClassIdList,
+            SdpValue::Sequence(record.service_classes.iter().map(|&uuid| SdpValue::Uuid(uuid)).collect()));
+        if !record.protocols.is_empty() {
+            let descriptors = record.protocols
+                .iter()
+                .map(|descriptor| {
+                    let mut values = vec![SdpValue::Uuid(descriptor.protocol)];
+                    match descriptor.parameter {
+                        // RFCOMM channel numbers are encoded as 8-bit values
+                        Some(channel) if descriptor.protocol.to_uuid128() == BtUuid::Uuid16(0x0003).to_uuid128() => {
+                            values.push(SdpValue::Uint8(channel as u8))
+                        }
+                        Some(parameter) => values.push(SdpValue::Uint16(parameter)),
+                        None => {}
+                    }
+                    SdpValue::Sequence(values)
+                })
+                .collect();
+            add(SdpAttr::ProtocolDescriptorList, SdpValue::Sequence(descriptors));
+        }
+        add(SdpAttr::BrowseGroupList,
+            SdpValue::Sequence(vec![SdpValue::Uuid(BtUuid::Uuid16(SdpServiceClass::PublicBrowseGroup as u16))]));
+        if record.name.is_some() || record.description.is_some() || record.provider.is_some() {
+            // English, UTF-8, primary language base at 0x0100
+            add(SdpAttr::LanguageBaseAttributeIdList,
+                SdpValue::Sequence(vec![SdpValue::Uint16(0x656E), SdpValue::Uint16(0x006A), SdpValue::Uint16(0x0100)]));
+        }
+        if !record.profiles.is_empty() {
+            let descriptors = record.profiles
+                .iter()
+                .map(|descriptor| SdpValue::Sequence(vec![SdpValue::Uuid(descriptor.profile), SdpValue::Uint16(descriptor.version)]))
+                .collect();
+            add(SdpAttr::ProfileDescriptorList, SdpValue::Sequence(descriptors));
+        }
+        for (attr, text) in [(SdpAttr::ServiceNamePrimary, &record.name),
+                             (SdpAttr::ServiceDescPrimary, &record.description),
+                             (SdpAttr::ProviderNamePrimary, &record.provider)] {
+            if let Some(text) = text {
+                add(attr, SdpValue::Text(text.as_bytes().to_vec()));
+            }
+        }
+    }
+
+    SdpValue::Sequence(attributes)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bluetooth::{BtProfileDescriptor, BtProtocolDescriptor, BtServiceRecord, BtUuid};
+
+    #[test]
+    fn decodes_fixed_size_elements() {
+        let fixtures: Vec<(&[u8], SdpValue)> = vec![(&[0x00], SdpValue::Nil),
+                                                    (&[0x08, 0x2A], SdpValue::Uint8(42)),
+                                                    (&[0x09, 0x01, 0x02], SdpValue::Uint16(0x0102)),
+                                                    (&[0x0A, 0x00, 0x01, 0x00, 0x05], SdpValue::Uint32(0x00010005)),
+                                                    (&[0x0B, 0, 0, 0, 0, 0, 0, 0x01, 0x00], SdpValue::Uint64(256)),
+                                                    (&[0x10, 0xFF], SdpValue::Int8(-1)),
+                                                    (&[0x11, 0xFF, 0xFE], SdpValue::Int16(-2)),
+                                                    (&[0x12, 0xFF, 0xFF, 0xFF, 0xFD], SdpValue::Int32(-3)),
+                                                    (&[0x19, 0x11, 0x01], SdpValue::Uuid(BtUuid::Uuid16(0x1101))),
+                                                    (&[0x1A, 0x00, 0x00, 0x11, 0x01], SdpValue::Uuid(BtUuid::Uuid32(0x1101))),
+                                                    (&[0x28, 0x01], SdpValue::Bool(true))];
+
+        for (bytes, value) in fixtures {
+            assert_eq!(SdpValue::decode(bytes).unwrap(), (value.clone(), bytes.len()));
+            assert_eq!(value.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn decodes_128_bit_elements() {
+        let mut bytes = vec![0x1C];
+        bytes.extend_from_slice(&BtUuid::serial_port().to_uuid128());
+        assert_eq!(SdpValue::decode(&bytes).unwrap(),
+                   (SdpValue::Uuid(BtUuid::Uuid128(BtUuid::serial_port().to_uuid128())), 17));
+
+        bytes[0] = 0x0C;
+        assert_eq!(SdpValue::decode(&bytes).unwrap().0, SdpValue::Uint128(BtUuid::serial_port().to_uuid128()));
+        bytes[0] = 0x14;
+        assert_eq!(SdpValue::decode(&bytes).unwrap().0, SdpValue::Int128(BtUuid::serial_port().to_uuid128()));
+    }
+
+    #[test]
+    fn decodes_variable_size_elements() {
+        assert_eq!(SdpValue::decode(&[0x25, 0x03, b'S', b'P', b'P', 0xFF]).unwrap(),
+                   (SdpValue::Text(b"SPP".to_vec()), 5));
+        assert_eq!(SdpValue::decode(&[0x26, 0x00, 0x02, b'h', b'i']).unwrap(),
+                   (SdpValue::Text(b"hi".to_vec()), 5));
+        assert_eq!(SdpValue::decode(&[0x47, 0x00, 0x00, 0x00, 0x01, b'x']).unwrap(),
+                   (SdpValue::Url("x".to_string()), 6));
+        assert_eq!(SdpValue::decode(&[0x35, 0x05, 0x19, 0x01, 0x00, 0x08, 0x03, 0x28, 0x00]).unwrap(),
+                   (SdpValue::Sequence(vec![SdpValue::Uuid(BtUuid::Uuid16(0x0100)), SdpValue::Uint8(3)]), 7));
+        assert_eq!(SdpValue::decode(&[0x3D, 0x02, 0x28, 0x00]).unwrap(),
+                   (SdpValue::Alternative(vec![SdpValue::Bool(false)]), 4));
+    }
+
+    #[test]
+    fn encodes_with_shortest_length_field() {
+        let long_text = SdpValue::Text(vec![b'a'; 300]);
+        let bytes = long_text.to_bytes();
+        assert_eq!(&bytes[..3], &[0x26, 0x01, 0x2C]);
+        assert_eq!(SdpValue::decode(&bytes).unwrap(), (long_text, 303));
+
+        let nested = SdpValue::Sequence(vec![SdpValue::Alternative(vec![SdpValue::Nil, SdpValue::Url("http://x".to_string())]),
+                                             SdpValue::Int64(-5)]);
+        assert_eq!(SdpValue::decode(&nested.to_bytes()).unwrap().0, nested);
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth: usize| {
+            let mut value = SdpValue::Nil;
+            for _ in 0..depth {
+                value = SdpValue::Sequence(vec![value]);
+            }
+            value.to_bytes()
+        };
+        assert!(SdpValue::decode(&nested(MAX_NESTING_DEPTH)).is_ok());
+        assert!(SdpValue::decode(&nested(MAX_NESTING_DEPTH + 1)).is_err());
+
+        // Nothing but sequence headers, as a malicious SDP server could send them
+        let depth = 20000;
+        let mut nested_headers = Vec::new();
+        for level in 0..depth {
+            nested_headers.push(0x37);
+            write_be(&mut nested_headers, 5 * (depth - 1 - level) as u64, 4);
+        }
+        assert!(SdpValue::decode(&nested_headers).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_elements() {
+        let fail_fixtures: [&[u8]; 7] = [&[], &[0x09, 0x01], &[0x25], &[0x25, 0x05, b'a'], &[0x35, 0x02, 0x09, 0x00], &[0x18, 0x00], &[0xFF]];
+        for bytes in &fail_fixtures {
+            assert!(SdpValue::decode(bytes).is_err(), "Somehow managed to decode {:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn parses_serial_port_record() {
+        // Attribute list of a typical SPP service record
+        let bytes = [0x35, 0x3B, // attribute list
+                     0x09, 0x00, 0x00, 0x0A, 0x00, 0x01, 0x00, 0x05, // ServiceRecordHandle
+                     0x09, 0x00, 0x01, 0x35, 0x03, 0x19, 0x11, 0x01, // ServiceClassIDList
+                     0x09, 0x00, 0x04, 0x35, 0x0C, // ProtocolDescriptorList
+                     0x35, 0x03, 0x19, 0x01, 0x00, // L2CAP
+                     0x35, 0x05, 0x19, 0x00, 0x03, 0x08, 0x02, // RFCOMM, channel 2
+                     0x09, 0x00, 0x09, 0x35, 0x08, 0x35, 0x06, 0x19, 0x11, 0x01, 0x09, 0x01, 0x02, // BluetoothProfileDescriptorList
+                     0x09, 0x01, 0x00, 0x25, 0x08, b'S', b'e', b'r', b'i', b'a', b'l', 0x20, 0x00 /* ServiceName */];
+
+        let (attributes, size) = SdpValue::decode(&bytes).unwrap();
+        assert_eq!(size, bytes.len());
+
+        let record = parse_service_record(&attributes).unwrap();
+        assert_eq!(record.handle, 0x00010005);
+        assert_eq!(record.service_classes, vec![BtUuid::serial_port()]);
+        assert_eq!(record.name, Some("Serial ".to_string()));
+        assert_eq!(record.description, None);
+        assert_eq!(record.protocols,
+                   vec![BtProtocolDescriptor {
+                            protocol: BtUuid::Uuid16(0x0100),
+                            parameter: None,
+                        },
+                        BtProtocolDescriptor {
+                            protocol: BtUuid::Uuid16(0x0003),
+                            parameter: Some(2),
+                        }]);
+        assert_eq!(record.rfcomm_channel(), Some(2));
+        assert_eq!(record.profiles,
+                   vec![BtProfileDescriptor {
+                            profile: BtUuid::serial_port(),
+                            version: 0x0102,
+                        }]);
+    }
+
+    #[test]
+    fn builds_serial_port_record() {
+        let mut record = BtServiceRecord::rfcomm(BtUuid::serial_port(), 5);
+        record.name = Some("Gateway".to_string());
+
+        let bytes = build_service_record(&record).to_bytes();
+        assert_eq!(bytes,
+                   vec![0x35, 0x48, // attribute list
+                        0x09, 0x00, 0x01, 0x35, 0x03, 0x19, 0x11, 0x01, // ServiceClassIDList
+                        0x09, 0x00, 0x04, 0x35, 0x0C, // ProtocolDescriptorList
+                        0x35, 0x03, 0x19, 0x01, 0x00, // L2CAP
+                        0x35, 0x05, 0x19, 0x00, 0x03, 0x08, 0x05, // RFCOMM, channel 5
+                        0x09, 0x00, 0x05, 0x35, 0x03, 0x19, 0x10, 0x02, // BrowseGroupList
+                        0x09, 0x00, 0x06, 0x35, 0x09, 0x09, 0x65, 0x6E, 0x09, 0x00, 0x6A, 0x09, 0x01, 0x00, // LanguageBaseAttributeIDList
+                        0x09, 0x00, 0x09, 0x35, 0x08, 0x35, 0x06, 0x19, 0x11, 0x01, 0x09, 0x01, 0x02, // BluetoothProfileDescriptorList
+                        0x09, 0x01, 0x00, 0x25, 0x07, b'G', b'a', b't', b'e', b'w', b'a', b'y' /* ServiceName */]);
+
+        let (attributes, _) = SdpValue::decode(&bytes).unwrap();
+        assert_eq!(parse_service_record(&attributes).unwrap(), record);
+    }
+
+    #[test]
+    fn rejects_invalid_attribute_list() {
+        assert!(parse_service_record(&SdpValue::Uint8(0)).is_err());
+        assert!(parse_service_record(&SdpValue::Sequence(vec![SdpValue::Uint16(0)])).is_err());
+        assert!(parse_service_record(&SdpValue::Sequence(vec![SdpValue::Uint8(0), SdpValue::Nil])).is_err());
+    }
+}
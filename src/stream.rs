@@ -0,0 +1,679 @@
+//! Tokio integration, enabled by the `tokio` feature.
+//!
+//! The types here drive the same non-blocking state machines as `BtSocketConnect` and
+//! `BtDiscovery`, but wait for them on the reactor of the current Tokio runtime.
+
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use libc;
+use mio;
+use tokio::io::{AsyncRead, AsyncWrite, Interest, ReadBuf};
+use tokio::io::unix::AsyncFd;
+use tokio::time::{self, Sleep};
+
+use bluetooth::{discover_devices, Advance, BtAddr, BtAsync, BtDevice, BtDiscovery, BtDiscoveryEvent, BtError,
+                BtListener, BtReadRemoteName, BtSocket, BtSocketConnectOwned, BtUuid, ScanOptions};
+
+/// Unwraps a `Result`, returning its error from a function returning `Poll<Result<_, _>>`.
+macro_rules! try_ready {
+    ($expr:expr) => (match $expr {
+        Ok(value) => value,
+        Err(error) => return Poll::Ready(Err(error)),
+    })
+}
+
+fn create_error_from_io(message: &str, error: io::Error) -> BtError {
+    match error.raw_os_error() {
+        Some(errno) => BtError::Errno(errno as u32, format!("{}: {}", message, error)),
+        None => BtError::Desc(format!("{}: {}", message, error)),
+    }
+}
+
+/// Registers `inner` with the reactor of the current Tokio runtime.
+fn register<T: AsRawFd>(inner: T, interest: Interest) -> Result<AsyncFd<T>, BtError> {
+    // All registered objects own their file descriptor, which stays open until the `AsyncFd` is
+    // dropped
+    unsafe { AsyncFd::register_with_interest(inner, interest) }
+        .map_err(|error| create_error_from_io("Failed to register with Tokio reactor", error.into_parts().1))
+}
+
+/// Waits on the Tokio reactor for an event source to become ready for what a state machine asked
+/// for.
+///
+/// The source is registered with a private `mio::Poll`, whose epoll descriptor becomes readable
+/// once the requested event occurred.
+#[derive(Debug)]
+struct SourceWait(Option<AsyncFd<mio::Poll>>);
+
+impl SourceWait {
+    fn new() -> SourceWait {
+        SourceWait(None)
+    }
+
+    fn is_registered(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Start waiting for `source` to become ready for `interest`, replacing the previous
+    /// registration.
+    fn register(&mut self, source: &mut dyn mio::event::Source, interest: mio::Interest) -> Result<(), BtError> {
+        self.0 = None;
+        let poll = mio::Poll::new().map_err(|error| create_error_from_io("Failed to create event loop", error))?;
+        poll.registry().register(source, mio::Token(0), interest)
+            .map_err(|error| create_error_from_io("Failed to register with event loop", error))?;
+        self.0 = Some(register(poll, Interest::READABLE)?);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.0 = None;
+    }
+
+    /// Returns `Ready` once the registered source became ready for the requested interest.
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), BtError>> {
+        let poll = match self.0 {
+            Some(ref mut poll) => poll,
+            None => return Poll::Ready(Ok(())),
+        };
+
+        let mut events = mio::Events::with_capacity(1);
+        loop {
+            let mut guard = match poll.poll_read_ready_mut(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(create_error_from_io("Waiting for event failed", error))),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Err(error) = guard.get_inner_mut().poll(&mut events, Some(Duration::from_secs(0))) {
+                return Poll::Ready(Err(create_error_from_io("Waiting for event failed", error)));
+            }
+            if !events.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            // Spurious wakeup, or the state has been consumed since
+            guard.clear_ready();
+        }
+    }
+}
+
+/// Advance `process` as far as possible without blocking.
+fn poll_advance<A: Advance>(process: &mut A, wait: &mut SourceWait, cx: &mut Context) -> Poll<Result<(), BtError>> {
+    loop {
+        if wait.is_registered() {
+            match wait.poll_ready(cx) {
+                Poll::Ready(Ok(())) => wait.clear(),
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        match process.advance() {
+            Ok(BtAsync::WaitFor(evented, interest)) => {
+                if let Err(error) = wait.register(evented, interest) {
+                    return Poll::Ready(Err(error));
+                }
+            }
+            Ok(BtAsync::Done) => return Poll::Ready(Ok(())),
+            Err(error) => return Poll::Ready(Err(error)),
+        }
+    }
+}
+
+
+/// A connected Bluetooth socket for use with Tokio.
+///
+/// Implements `AsyncRead` and `AsyncWrite`, and can be split into halves for concurrent reading
+/// and writing.
+#[derive(Debug)]
+pub struct BtStream(AsyncFd<BtSocket>);
+
+impl BtStream {
+    /// Register the connected `socket` with the reactor of the current Tokio runtime.
+    ///
+    /// The socket is switched into non-blocking mode. Must be called from within a runtime.
+    pub fn from_socket(socket: BtSocket) -> Result<BtStream, BtError> {
+        socket.set_nonblocking(true)?;
+        Ok(BtStream(register(socket, Interest::READABLE | Interest::WRITABLE)?))
+    }
+
+    /// Connect `socket` to the RFCOMM service on remote device with address `addr`. Channel will
+    /// be determined through SDP protocol.
+    ///
+    /// The returned future resolves once the connection has been established. Dropping it aborts
+    /// the connection attempt, so `tokio::time::timeout` can limit how long it takes.
+    pub fn connect(socket: BtSocket, addr: BtAddr) -> BtStreamConnect {
+        BtStream::connect_service(socket, addr, BtUuid::serial_port())
+    }
+
+    /// Connect `socket` to the RFCOMM service with the service class `service` on remote device
+    /// with address `addr`, see `connect`.
+    pub fn connect_service(socket: BtSocket, addr: BtAddr, service: BtUuid) -> BtStreamConnect {
+        BtStreamConnect::new(socket, |socket| BtSocketConnectOwned::service(socket, addr, service))
+    }
+
+    /// Connect `socket` to the given RFCOMM `channel` on remote device with address `addr`, without
+    /// an SDP lookup, see `connect`.
+    pub fn connect_channel(socket: BtSocket, addr: BtAddr, channel: u8) -> BtStreamConnect {
+        BtStreamConnect::new(socket, |socket| BtSocketConnectOwned::channel(socket, addr, channel))
+    }
+
+    /// Connect the L2CAP `socket` to the protocol/service multiplexer `psm` on remote device with
+    /// address `addr`, see `connect`.
+    pub fn connect_psm(socket: BtSocket, addr: BtAddr, psm: u16) -> BtStreamConnect {
+        BtStreamConnect::new(socket, |socket| BtSocketConnectOwned::psm(socket, addr, psm))
+    }
+
+    /// Returns the underlying socket, for querying its addresses and options.
+    pub fn socket(&self) -> &BtSocket {
+        self.0.get_ref()
+    }
+
+    /// Deregisters the socket from the reactor and returns it, still in non-blocking mode.
+    pub fn into_socket(self) -> BtSocket {
+        self.0.into_inner()
+    }
+
+    /// Split the stream into a read and a write half borrowing it.
+    pub fn split(&mut self) -> (BtReadHalf<'_>, BtWriteHalf<'_>) {
+        (BtReadHalf(&*self), BtWriteHalf(&*self))
+    }
+
+    /// Split the stream into a read and a write half, which can be moved into different tasks.
+    pub fn into_split(self) -> (BtOwnedReadHalf, BtOwnedWriteHalf) {
+        let stream = Arc::new(self);
+        (BtOwnedReadHalf(stream.clone()), BtOwnedWriteHalf(stream))
+    }
+
+    fn poll_read_priv(&self, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = match self.0.poll_read_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let result = guard.try_io(|socket| {
+                let mut socket = socket.get_ref();
+                socket.read(buf.initialize_unfilled())
+            });
+            match result {
+                Ok(Ok(size)) => {
+                    buf.advance(size);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(error)) => return Poll::Ready(Err(error)),
+                // Would block, the readiness has been cleared
+                Err(_) => continue,
+            }
+        }
+    }
+
+    fn poll_write_priv(&self, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = match self.0.poll_write_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let result = guard.try_io(|socket| {
+                let mut socket = socket.get_ref();
+                socket.write(buf)
+            });
+            match result {
+                Ok(result) => return Poll::Ready(result),
+                Err(_) => continue,
+            }
+        }
+    }
+
+    fn shutdown_write(&self) -> io::Result<()> {
+        if unsafe { libc::shutdown(self.0.as_raw_fd(), libc::SHUT_WR) } < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl AsyncRead for BtStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        self.poll_read_priv(cx, buf)
+    }
+}
+
+impl AsyncWrite for BtStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_priv(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(self.shutdown_write())
+    }
+}
+
+/// The read half of a `BtStream`, created by `BtStream::split`.
+#[derive(Debug)]
+#[allow(missing_copy_implementations)]
+pub struct BtReadHalf<'a>(&'a BtStream);
+
+/// The write half of a `BtStream`, created by `BtStream::split`.
+///
+/// Shutting it down shuts down the writing direction of the whole stream.
+#[derive(Debug)]
+#[allow(missing_copy_implementations)]
+pub struct BtWriteHalf<'a>(&'a BtStream);
+
+impl<'a> AsyncRead for BtReadHalf<'a> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        self.0.poll_read_priv(cx, buf)
+    }
+}
+
+impl<'a> AsyncWrite for BtWriteHalf<'a> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.0.poll_write_priv(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(self.0.shutdown_write())
+    }
+}
+
+/// The owned read half of a `BtStream`, created by `BtStream::into_split`.
+#[derive(Debug)]
+pub struct BtOwnedReadHalf(Arc<BtStream>);
+
+/// The owned write half of a `BtStream`, created by `BtStream::into_split`.
+///
+/// Shutting it down shuts down the writing direction of the whole stream.
+#[derive(Debug)]
+pub struct BtOwnedWriteHalf(Arc<BtStream>);
+
+impl BtOwnedReadHalf {
+    /// Join the halves created by the same `into_split` call back into the stream.
+    ///
+    /// Returns the halves unchanged if they belong to different streams.
+    pub fn reunite(self, other: BtOwnedWriteHalf) -> Result<BtStream, (BtOwnedReadHalf, BtOwnedWriteHalf)> {
+        if !Arc::ptr_eq(&self.0, &other.0) {
+            return Err((self, other));
+        }
+        drop(other);
+        Ok(Arc::try_unwrap(self.0).expect("BtOwnedReadHalf: stream still shared"))
+    }
+}
+
+impl AsyncRead for BtOwnedReadHalf {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        self.0.poll_read_priv(cx, buf)
+    }
+}
+
+impl AsyncWrite for BtOwnedWriteHalf {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.0.poll_write_priv(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(self.0.shutdown_write())
+    }
+}
+
+
+/// A future connecting a `BtStream`, created by `BtStream::connect` and its variants.
+#[derive(Debug)]
+pub struct BtStreamConnect {
+    connect: Result<BtSocketConnectOwned, Option<BtError>>,
+    wait: SourceWait,
+}
+
+impl BtStreamConnect {
+    fn new<F: FnOnce(BtSocket) -> BtSocketConnectOwned>(socket: BtSocket, connect: F) -> BtStreamConnect {
+        // The connection process must not block the runtime
+        let connect = match socket.set_nonblocking(true) {
+            Ok(()) => Ok(connect(socket)),
+            Err(error) => Err(Some(error)),
+        };
+        BtStreamConnect {
+            connect,
+            wait: SourceWait::new(),
+        }
+    }
+}
+
+impl Future for BtStreamConnect {
+    type Output = Result<BtStream, BtError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<BtStream, BtError>> {
+        let this = &mut *self;
+        let result = match this.connect {
+            Ok(ref mut connect) => poll_advance(connect, &mut this.wait, cx),
+            Err(ref mut error) => Poll::Ready(Err(error.take().expect("BtStreamConnect polled after completion"))),
+        };
+
+        match result {
+            Poll::Ready(Ok(())) => {
+                match mem::replace(&mut this.connect, Err(None)) {
+                    Ok(connect) => Poll::Ready(BtStream::from_socket(connect.into_socket())),
+                    Err(_) => unreachable!(),
+                }
+            }
+            Poll::Ready(Err(error)) => {
+                this.connect = Err(None);
+                Poll::Ready(Err(error))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+
+/// A Bluetooth listener for use with Tokio.
+#[derive(Debug)]
+pub struct BtStreamListener(AsyncFd<BtListener>);
+
+impl BtStreamListener {
+    /// Register the bound and listening `listener` with the reactor of the current Tokio runtime.
+    ///
+    /// Must be called from within a runtime.
+    pub fn from_listener(listener: BtListener) -> Result<BtStreamListener, BtError> {
+        Ok(BtStreamListener(register(listener, Interest::READABLE)?))
+    }
+
+    /// Returns the underlying listener, for querying its address and options.
+    pub fn listener(&self) -> &BtListener {
+        self.0.get_ref()
+    }
+
+    /// Accept the next incoming connection, resolving to the connected stream and the address of
+    /// the remote device.
+    pub fn accept(&self) -> BtStreamAccept<'_> {
+        BtStreamAccept(self)
+    }
+
+    /// Poll for an incoming connection, see `accept`.
+    pub fn poll_accept(&self, cx: &mut Context) -> Poll<Result<(BtStream, BtAddr), BtError>> {
+        loop {
+            let mut guard = match self.0.poll_read_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(create_error_from_io("Waiting for connection failed", error))),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match self.0.get_ref().try_accept() {
+                Ok(Some((socket, addr))) => return Poll::Ready(BtStream::from_socket(socket).map(|stream| (stream, addr))),
+                Ok(None) => guard.clear_ready(),
+                Err(error) => return Poll::Ready(Err(error)),
+            }
+        }
+    }
+}
+
+/// A future accepting a connection, created by `BtStreamListener::accept`.
+#[derive(Debug)]
+#[allow(missing_copy_implementations)]
+pub struct BtStreamAccept<'a>(&'a BtStreamListener);
+
+impl<'a> Future for BtStreamAccept<'a> {
+    type Output = Result<(BtStream, BtAddr), BtError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(BtStream, BtAddr), BtError>> {
+        self.0.poll_accept(cx)
+    }
+}
+
+
+/// Finds the Bluetooth devices in range without blocking the Tokio runtime, using the inquiry
+/// parameters in `options`.
+///
+/// Unlike `scan_devices_with`, the inquiry always runs through a raw HCI socket. Dropping the
+/// returned future cancels the inquiry. A name timeout in `options` requires the time driver of
+/// the runtime to be enabled.
+pub fn scan_devices_async(options: ScanOptions) -> BtScanDevices {
+    BtScanDevices {
+        options,
+        state: BtScanDevicesState::Start,
+        devices: Vec::new(),
+        wait: SourceWait::new(),
+    }
+}
+
+#[derive(Debug)]
+enum BtScanDevicesState {
+    Start,
+    Inquiry(BtDiscovery),
+    // Index of the next device, and the request for the current one with its timeout
+    ReadNames(usize, Option<(BtReadRemoteName, Option<Pin<Box<Sleep>>>)>),
+    Done,
+}
+
+/// A future finding the Bluetooth devices in range, created by `scan_devices_async`.
+#[derive(Debug)]
+pub struct BtScanDevices {
+    options: ScanOptions,
+    state: BtScanDevicesState,
+    devices: Vec<BtDevice>,
+    wait: SourceWait,
+}
+
+impl BtScanDevices {
+    fn add_device(&mut self, event: BtDiscoveryEvent) {
+        let device = match event {
+            BtDiscoveryEvent::InquiryResult(device) => device,
+            BtDiscoveryEvent::InquiryResultWithRSSI(device) => device,
+            BtDiscoveryEvent::ExtendedInquiryResult(device, _) => device,
+            BtDiscoveryEvent::InquiryComplete => return,
+        };
+
+        // Devices can respond more than once during an inquiry
+        match self.devices.iter().position(|known| known.addr == device.addr) {
+            Some(index) => self.devices[index] = device,
+            None => self.devices.push(device),
+        }
+    }
+
+    fn poll_scan(&mut self, cx: &mut Context) -> Poll<Result<Vec<BtDevice>, BtError>> {
+        loop {
+            match mem::replace(&mut self.state, BtScanDevicesState::Done) {
+                BtScanDevicesState::Start => {
+                    let mut discovery = try_ready!(discover_devices(self.options));
+                    try_ready!(self.wait.register(&mut discovery, mio::Interest::READABLE));
+                    self.state = BtScanDevicesState::Inquiry(discovery);
+                }
+
+                BtScanDevicesState::Inquiry(mut discovery) => {
+                    while let Some(event) = try_ready!(discovery.next_event()) {
+                        self.add_device(event);
+                    }
+
+                    if discovery.is_done() {
+                        self.wait.clear();
+                        let state = if self.options.resolve_names {
+                            BtScanDevicesState::ReadNames(0, None)
+                        } else {
+                            BtScanDevicesState::Done
+                        };
+                        self.state = state;
+                        continue;
+                    }
+
+                    let ready = self.wait.poll_ready(cx);
+                    self.state = BtScanDevicesState::Inquiry(discovery);
+                    match ready {
+                        Poll::Ready(Ok(())) => continue,
+                        Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+
+                BtScanDevicesState::ReadNames(index, None) => {
+                    if index == self.devices.len() {
+                        continue;
+                    }
+
+                    // The complete name from the extended inquiry response saves the name request
+                    let device = &self.devices[index];
+                    if device.eir.as_ref().is_some_and(|eir| eir.name_complete) {
+                        self.state = BtScanDevicesState::ReadNames(index + 1, None);
+                        continue;
+                    }
+
+                    let request = BtReadRemoteName::for_device(&self.options, device);
+                    let timeout = self.options.name_timeout.map(|timeout| Box::pin(time::sleep(timeout)));
+                    self.state = BtScanDevicesState::ReadNames(index, Some((request, timeout)));
+                }
+
+                BtScanDevicesState::ReadNames(index, Some((mut request, mut timeout))) => {
+                    let name = match poll_advance(&mut request, &mut self.wait, cx) {
+                        Poll::Ready(Ok(())) => request.take_name(),
+                        // Devices which do not answer are reported without a name
+                        Poll::Ready(Err(_)) => None,
+                        Poll::Pending => {
+                            let expired = timeout.as_mut().is_some_and(|timeout| timeout.as_mut().poll(cx).is_ready());
+                            if !expired {
+                                self.state = BtScanDevicesState::ReadNames(index, Some((request, timeout)));
+                                return Poll::Pending;
+                            }
+                            None
+                        }
+                    };
+
+                    self.wait.clear();
+                    self.devices[index].name = name;
+                    self.state = BtScanDevicesState::ReadNames(index + 1, None);
+                }
+
+                BtScanDevicesState::Done => return Poll::Ready(Ok(mem::take(&mut self.devices))),
+            }
+        }
+    }
+}
+
+impl Future for BtScanDevices {
+    type Output = Result<Vec<BtDevice>, BtError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Vec<BtDevice>, BtError>> {
+        self.poll_scan(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::task::Waker;
+    use mio::unix::SourceFd;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::runtime::{Builder, Runtime};
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread().enable_io().build().unwrap()
+    }
+
+    fn stream_pair() -> (BtSocket, UnixStream) {
+        let (socket, peer) = UnixStream::pair().unwrap();
+        (unsafe { BtSocket::from_raw_fd(socket.into_raw_fd()) }, peer)
+    }
+
+    #[test]
+    fn waits_for_event_sources() {
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        let (socket, mut peer) = UnixStream::pair().unwrap();
+
+        let mut wait = SourceWait::new();
+        wait.register(&mut SourceFd(&socket.as_raw_fd()), mio::Interest::READABLE).unwrap();
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(wait.poll_ready(&mut cx).is_pending());
+
+        peer.write_all(b"x").unwrap();
+        runtime.block_on(future::poll_fn(|cx| wait.poll_ready(cx))).unwrap();
+    }
+
+    #[test]
+    fn reads_and_writes_streams() {
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        let (socket, mut peer) = stream_pair();
+        let mut stream = BtStream::from_socket(socket).unwrap();
+
+        let mut buf = [0u8; 4];
+        peer.write_all(b"ping").unwrap();
+        runtime.block_on(stream.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        runtime.block_on(stream.write_all(b"pong")).unwrap();
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[test]
+    fn splits_streams_into_owned_halves() {
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        let (socket, mut peer) = stream_pair();
+        let (mut read, mut write) = BtStream::from_socket(socket).unwrap().into_split();
+
+        let mut buf = [0u8; 4];
+        runtime.block_on(write.write_all(b"ping")).unwrap();
+        peer.read_exact(&mut buf).unwrap();
+        peer.write_all(b"pong").unwrap();
+        runtime.block_on(read.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // Shutting down the write half is seen by the peer as end of file
+        let mut stream = read.reunite(write).unwrap();
+        runtime.block_on(stream.shutdown()).unwrap();
+        assert_eq!(peer.read(&mut buf).unwrap(), 0);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn connects_and_scans_through_mock_stack() {
+        use bluetooth::{BtProtocol, BtServiceRecord};
+        use mock::MockStack;
+
+        let runtime = runtime();
+        let _guard = runtime.enter();
+        let addr = BtAddr([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let stack = MockStack::new();
+        stack.install();
+        stack.add_device(BtDevice::new("Sensor".to_string(), addr));
+        stack.add_service(addr, BtServiceRecord::rfcomm(BtUuid::serial_port(), 3));
+        let peer = stack.listen(addr, 3);
+
+        let devices = runtime.block_on(scan_devices_async(ScanOptions::new())).unwrap();
+        assert_eq!(devices[0].name, Some("Sensor".to_string()));
+
+        let socket = BtSocket::new(BtProtocol::RFCOMM).unwrap();
+        let mut stream = runtime.block_on(BtStream::connect(socket, addr)).unwrap();
+        let mut remote = peer.accept().unwrap();
+
+        let mut buf = [0u8; 4];
+        remote.write_all(b"ping").unwrap();
+        runtime.block_on(stream.read_exact(&mut buf)).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
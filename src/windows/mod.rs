@@ -1,8 +1,11 @@
-use bluetooth::{BtAddr, BtAsync, BtDevice, BtError, BtProtocol};
+use bluetooth::{BtAdapter, BtAddr, BtAsync, BtDevice, BtDiscoveryEvent, BtError, BtProtocol, BtServiceRecord, BtSocketAddr, BtUuid, DeviceClass, InquiryMode, LinkMode, ScanOptions, SecurityLevel};
+use hci_packet::{HciCommand, HciEvent, HciFilter};
 use mio;
 use std;
+use std::borrow::BorrowMut;
 use std::io::{Read, Write};
-use mio::{Poll, Ready};
+use std::time::Duration;
+use mio::{Interest, Registry};
 
 #[derive(Debug)]
 #[allow(missing_copy_implementations)]
@@ -14,21 +17,75 @@ impl BtSocket {
     pub fn new(protocol: BtProtocol) -> Result<BtSocket, BtError> {
         unimplemented!();
     }
-    pub fn connect(&mut self, addr: BtAddr) -> BtSocketConnect {
+    pub fn bind(&mut self, local_addr: BtAddr) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn connect(&mut self, addr: BtAddr, service: BtUuid) -> BtSocketConnect<&mut BtSocket> {
+        unimplemented!();
+    }
+    pub fn connect_channel(&mut self, addr: BtAddr, channel: u8) -> BtSocketConnect<&mut BtSocket> {
+        unimplemented!();
+    }
+    pub fn connect_psm(&mut self, addr: BtAddr, psm: u16) -> BtSocketConnect<&mut BtSocket> {
+        unimplemented!();
+    }
+    pub fn mtu(&self) -> Result<(u16, u16), BtError> {
+        unimplemented!();
+    }
+    pub fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn peer_addr(&self) -> Result<BtSocketAddr, BtError> {
+        unimplemented!();
+    }
+    pub fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        unimplemented!();
+    }
+    pub fn security(&self) -> Result<SecurityLevel, BtError> {
+        unimplemented!();
+    }
+    pub fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn link_mode(&self) -> Result<LinkMode, BtError> {
+        unimplemented!();
+    }
+    pub fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn send_buffer_size(&self) -> Result<usize, BtError> {
+        unimplemented!();
+    }
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        unimplemented!();
+    }
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn linger(&self) -> Result<Option<Duration>, BtError> {
+        unimplemented!();
+    }
+    pub fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), BtError> {
         unimplemented!();
     }
 }
 
-impl mio::Evented for BtSocket {
-    fn register(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
+impl mio::event::Source for BtSocket {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
         unimplemented!();
     }
 
-    fn reregister(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
         unimplemented!();
     }
 
-    fn deregister(&self, poll: &Poll) -> std::io::Result<()> {
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
         unimplemented!();
     }
 }
@@ -49,38 +106,341 @@ impl Write for BtSocket {
     }
 }
 
+impl<'a> Read for &'a BtSocket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        unimplemented!()
+    }
+}
+
+impl<'a> Write for &'a BtSocket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        unimplemented!()
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        unimplemented!()
+    }
+}
+
+#[derive(Debug)]
+#[allow(missing_copy_implementations)]
+pub struct BtListener {
+
+}
+
+impl BtListener {
+    pub fn new(protocol: BtProtocol) -> Result<BtListener, BtError> {
+        unimplemented!();
+    }
+    pub fn bind(&self, addr: BtAddr, port: u16) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn mtu(&self) -> Result<(u16, u16), BtError> {
+        unimplemented!();
+    }
+    pub fn set_mtu(&self, input: Option<u16>, output: Option<u16>) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn local_addr(&self) -> Result<BtSocketAddr, BtError> {
+        unimplemented!();
+    }
+    pub fn defer_setup(&self) -> Result<bool, BtError> {
+        unimplemented!();
+    }
+    pub fn set_defer_setup(&self, defer: bool) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn security(&self) -> Result<SecurityLevel, BtError> {
+        unimplemented!();
+    }
+    pub fn set_security(&self, level: SecurityLevel) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn link_mode(&self) -> Result<LinkMode, BtError> {
+        unimplemented!();
+    }
+    pub fn set_link_mode(&self, mode: LinkMode) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn send_buffer_size(&self) -> Result<usize, BtError> {
+        unimplemented!();
+    }
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn recv_buffer_size(&self) -> Result<usize, BtError> {
+        unimplemented!();
+    }
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn linger(&self) -> Result<Option<Duration>, BtError> {
+        unimplemented!();
+    }
+    pub fn set_linger(&self, timeout: Option<Duration>) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn listen(&self, backlog: i32) -> Result<(), BtError> {
+        unimplemented!();
+    }
+    pub fn accept(&self) -> Result<Option<(BtSocket, BtAddr)>, BtError> {
+        unimplemented!();
+    }
+}
+
+impl mio::event::Source for BtListener {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        unimplemented!();
+    }
+}
+
+impl<'a> mio::event::Source for &'a BtListener {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        unimplemented!();
+    }
+}
+
+#[derive(Debug)]
+pub struct BtSocketConnect<S: BorrowMut<BtSocket>> {
+    addr: BtAddr,
+    socket: S,
+}
+impl<S: BorrowMut<BtSocket>> BtSocketConnect<S> {
+    pub fn new(socket: S, addr: BtAddr, service: BtUuid) -> Self {
+        unimplemented!();
+    }
+    pub fn with_channel(socket: S, addr: BtAddr, channel: u8) -> Self {
+        unimplemented!();
+    }
+    pub fn with_psm(socket: S, addr: BtAddr, psm: u16) -> Self {
+        unimplemented!();
+    }
+    pub fn into_socket(self) -> S {
+        unimplemented!();
+    }
+
+    pub fn advance(&mut self) -> Result<BtAsync, BtError> {
+        unimplemented!();
+    }
+}
+
+impl<S: BorrowMut<BtSocket>> mio::event::Source for BtSocketConnect<S> {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        unimplemented!();
+    }
+}
+
 #[derive(Debug)]
-pub struct BtSocketConnect<'a> {
+pub struct BtServiceBrowse {
     addr: BtAddr,
-    socket: &'a mut BtSocket,
 }
-impl<'a> BtSocketConnect<'a> {
-    pub fn new(socket: &'a mut BtSocket, addr: BtAddr) -> Self {
-        BtSocketConnect {
-            addr: addr.clone(),
-            socket: socket,
-        }
+impl BtServiceBrowse {
+    pub fn new(local_addr: BtAddr, addr: BtAddr) -> Self {
+        BtServiceBrowse { addr: addr }
     }
 
     pub fn advance(&mut self) -> Result<BtAsync, BtError> {
         unimplemented!();
     }
+
+    pub fn take_records(&mut self) -> Option<Vec<BtServiceRecord>> {
+        unimplemented!();
+    }
 }
 
-impl<'a> mio::Evented for BtSocketConnect<'a> {
-    fn register(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
+impl mio::event::Source for BtServiceBrowse {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
         unimplemented!();
     }
 
-    fn reregister(&self, poll: &Poll, token: mio::Token, interest: Ready, opts: mio::PollOpt) -> std::io::Result<()> {
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
         unimplemented!();
     }
 
-    fn deregister(&self, poll: &Poll) -> std::io::Result<()> {
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
         unimplemented!();
     }
 }
 
-pub fn scan_devices() -> Result<Vec<BtDevice>, BtError> {
+#[derive(Debug)]
+#[allow(missing_copy_implementations)]
+pub struct BtServiceRegistration {
+
+}
+impl BtServiceRegistration {
+    pub fn register(record: &BtServiceRecord) -> Result<BtServiceRegistration, BtError> {
+        unimplemented!();
+    }
+
+    pub fn handle(&self) -> u32 {
+        unimplemented!();
+    }
+}
+
+#[derive(Debug)]
+pub struct BtReadRemoteName {
+    addr: BtAddr,
+}
+impl BtReadRemoteName {
+    pub fn new(adapter: Option<u16>, addr: BtAddr) -> Self {
+        BtReadRemoteName { addr: addr }
+    }
+    pub fn with_inquiry_info(adapter: Option<u16>, addr: BtAddr, pscan_rep_mode: u8, clock_offset: Option<u16>) -> Self {
+        unimplemented!();
+    }
+
+    pub fn advance(&mut self) -> Result<BtAsync, BtError> {
+        unimplemented!();
+    }
+
+    pub fn take_name(&mut self) -> Option<String> {
+        unimplemented!();
+    }
+}
+
+impl mio::event::Source for BtReadRemoteName {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        unimplemented!();
+    }
+}
+
+#[derive(Debug)]
+#[allow(missing_copy_implementations)]
+pub struct BtDiscovery {
+
+}
+impl BtDiscovery {
+    pub fn start(options: &ScanOptions) -> Result<BtDiscovery, BtError> {
+        unimplemented!();
+    }
+
+    pub fn next_event(&mut self) -> Result<Option<BtDiscoveryEvent>, BtError> {
+        unimplemented!();
+    }
+
+    pub fn cancel(&mut self) -> Result<(), BtError> {
+        unimplemented!();
+    }
+
+    pub fn is_done(&self) -> bool {
+        unimplemented!();
+    }
+}
+
+impl mio::event::Source for BtDiscovery {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        unimplemented!();
+    }
+}
+
+#[derive(Debug)]
+pub struct HciSocket {
+
+}
+impl HciSocket {
+    pub fn open(adapter: Option<u16>) -> Result<HciSocket, BtError> {
+        unimplemented!();
+    }
+
+    pub fn set_filter(&self, filter: &HciFilter) -> Result<(), BtError> {
+        unimplemented!();
+    }
+
+    pub fn send(&self, command: &HciCommand) -> Result<(), BtError> {
+        unimplemented!();
+    }
+
+    pub fn read_event(&self) -> Result<Option<HciEvent>, BtError> {
+        unimplemented!();
+    }
+}
+
+impl mio::event::Source for HciSocket {
+    fn register(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: mio::Token, interests: Interest) -> std::io::Result<()> {
+        unimplemented!();
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> std::io::Result<()> {
+        unimplemented!();
+    }
+}
+
+pub fn list_adapters() -> Result<Vec<BtAdapter>, BtError> {
+    unimplemented!()
+}
+
+pub fn default_adapter() -> Result<BtAdapter, BtError> {
+    unimplemented!()
+}
+
+pub fn read_local_name(adapter: u16) -> Result<String, BtError> {
+    unimplemented!()
+}
+
+pub fn set_adapter_up(adapter: u16, up: bool) -> Result<(), BtError> {
+    unimplemented!()
+}
+
+pub fn write_scan_enable(adapter: u16, discoverable: bool, connectable: bool) -> Result<(), BtError> {
+    unimplemented!()
+}
+
+pub fn write_local_name(adapter: u16, name: &str) -> Result<(), BtError> {
+    unimplemented!()
+}
+
+pub fn write_class_of_device(adapter: u16, class: DeviceClass) -> Result<(), BtError> {
+    unimplemented!()
+}
+
+pub fn write_inquiry_mode(adapter: Option<u16>, mode: InquiryMode) -> Result<(), BtError> {
+    unimplemented!()
+}
+
+pub fn scan_devices(options: &ScanOptions) -> Result<Vec<BtDevice>, BtError> {
     unimplemented!()
 }